@@ -0,0 +1,9 @@
+CREATE TABLE sessions (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	token_hash text UNIQUE NOT NULL,
+	owner integer NOT NULL references users(id),
+	time_created timestamp with time zone NOT NULL,
+	time_last_used timestamp with time zone NOT NULL,
+	time_expires timestamp with time zone NOT NULL,
+	user_agent text
+);
@@ -0,0 +1,10 @@
+CREATE TABLE api_keys (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	key_hash text UNIQUE NOT NULL,
+	owner integer NOT NULL references users(id),
+	name text NOT NULL,
+	scopes text[] NOT NULL,
+	time_created timestamp with time zone NOT NULL,
+	time_expires timestamp with time zone,
+	time_last_used timestamp with time zone
+);
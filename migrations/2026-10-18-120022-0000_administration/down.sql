@@ -0,0 +1,3 @@
+DROP TABLE invites;
+ALTER TABLE users DROP COLUMN disabled;
+ALTER TABLE users DROP COLUMN is_admin;
@@ -0,0 +1,15 @@
+ALTER TABLE users ADD COLUMN is_admin boolean NOT NULL DEFAULT false;
+ALTER TABLE users ADD COLUMN disabled boolean NOT NULL DEFAULT false;
+
+-- The earliest account administers any existing deployment
+UPDATE users SET is_admin = true WHERE id = (SELECT min(id) FROM users);
+
+CREATE TABLE invites (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	code_hash text UNIQUE NOT NULL,
+	creator integer NOT NULL references users(id),
+	time_created timestamp with time zone NOT NULL,
+	time_expires timestamp with time zone,
+	redeemer integer references users(id),
+	time_redeemed timestamp with time zone
+);
@@ -0,0 +1,10 @@
+CREATE TABLE login_attempts (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	username text NOT NULL,
+	ip text,
+	time_attempted timestamp with time zone NOT NULL,
+	success boolean NOT NULL
+);
+
+CREATE INDEX login_attempts_username ON login_attempts (username, time_attempted);
+CREATE INDEX login_attempts_ip ON login_attempts (ip, time_attempted);
@@ -0,0 +1,9 @@
+ALTER TABLE users ADD COLUMN totp_secret text;
+ALTER TABLE users ADD COLUMN totp_enabled boolean NOT NULL DEFAULT false;
+ALTER TABLE users ADD COLUMN totp_last_step bigint;
+
+CREATE TABLE recovery_codes (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	code_hash text UNIQUE NOT NULL,
+	owner integer NOT NULL references users(id)
+);
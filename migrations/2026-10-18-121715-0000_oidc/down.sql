@@ -0,0 +1,4 @@
+DROP TABLE oidc_identities;
+
+UPDATE users SET passhash = '' WHERE passhash IS NULL;
+ALTER TABLE users ALTER COLUMN passhash SET NOT NULL;
@@ -0,0 +1,10 @@
+ALTER TABLE users ALTER COLUMN passhash DROP NOT NULL;
+
+CREATE TABLE oidc_identities (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	issuer text NOT NULL,
+	subject text NOT NULL,
+	owner integer NOT NULL references users(id),
+	time_created timestamp with time zone NOT NULL,
+	UNIQUE (issuer, subject)
+);
@@ -0,0 +1,14 @@
+CREATE TABLE audit_events (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	time_occurred timestamp with time zone NOT NULL,
+	action text NOT NULL,
+	success boolean NOT NULL,
+	actor integer,
+	ip text,
+	target text,
+	detail text
+);
+
+CREATE INDEX audit_events_time ON audit_events (time_occurred);
+CREATE INDEX audit_events_actor ON audit_events (actor, id);
+CREATE INDEX audit_events_action ON audit_events (action, id);
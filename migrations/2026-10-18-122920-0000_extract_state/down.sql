@@ -0,0 +1,14 @@
+DROP INDEX extracts_unfinished;
+
+ALTER TABLE extracts ADD COLUMN success boolean NOT NULL DEFAULT false;
+UPDATE extracts SET success = (state = 'installed');
+
+ALTER TABLE extracts
+	ALTER COLUMN success DROP DEFAULT,
+	DROP COLUMN state,
+	DROP COLUMN worker,
+	DROP COLUMN ticket,
+	DROP COLUMN dispatches,
+	DROP COLUMN failure,
+	DROP COLUMN time_created,
+	DROP COLUMN time_updated;
@@ -0,0 +1,21 @@
+ALTER TABLE extracts
+	ADD COLUMN state text NOT NULL DEFAULT 'pending',
+	ADD COLUMN worker text,
+	ADD COLUMN ticket uuid,
+	ADD COLUMN dispatches integer NOT NULL DEFAULT 0,
+	ADD COLUMN failure text,
+	ADD COLUMN time_created timestamp with time zone,
+	ADD COLUMN time_updated timestamp with time zone;
+
+UPDATE extracts SET state = CASE WHEN success THEN 'installed' ELSE 'failed' END;
+UPDATE extracts SET time_created = captures.time_initiated, time_updated = captures.time_initiated
+	FROM captures WHERE captures.id = extracts.capture;
+
+ALTER TABLE extracts
+	ALTER COLUMN state DROP DEFAULT,
+	ALTER COLUMN time_created SET NOT NULL,
+	ALTER COLUMN time_updated SET NOT NULL,
+	DROP COLUMN success;
+
+CREATE INDEX extracts_unfinished ON extracts (state)
+	WHERE state IN ('pending', 'dispatched', 'downloading');
@@ -0,0 +1,16 @@
+CREATE TABLE jobs (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	kind text NOT NULL,
+	subject integer NOT NULL,
+	priority integer NOT NULL,
+	attempts integer NOT NULL,
+	time_visible timestamp with time zone NOT NULL,
+	time_created timestamp with time zone NOT NULL,
+	UNIQUE (kind, subject)
+);
+
+CREATE INDEX jobs_ready ON jobs (priority DESC, time_visible);
+
+INSERT INTO jobs (kind, subject, priority, attempts, time_visible, time_created)
+	SELECT 'extract', id, 0, 0, now(), now() FROM extracts
+	WHERE state IN ('pending', 'dispatched', 'downloading');
@@ -0,0 +1,8 @@
+DELETE FROM jobs WHERE kind = 'webhook';
+DROP TABLE webhook_deliveries;
+DROP TABLE webhooks;
+
+ALTER TABLE captures
+	DROP COLUMN callback_url,
+	DROP COLUMN callback_secret,
+	DROP COLUMN time_finished;
@@ -0,0 +1,36 @@
+ALTER TABLE captures
+	ADD COLUMN callback_url text,
+	ADD COLUMN callback_secret text,
+	ADD COLUMN time_finished timestamp with time zone;
+
+UPDATE captures SET time_finished = (
+		SELECT max(extracts.time_updated) FROM extracts WHERE extracts.capture = captures.id
+	)
+	WHERE NOT EXISTS (
+		SELECT 1 FROM extracts WHERE extracts.capture = captures.id
+			AND extracts.state IN ('pending', 'dispatched', 'downloading')
+	);
+
+CREATE TABLE webhooks (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	owner integer NOT NULL REFERENCES users(id),
+	url text NOT NULL,
+	secret text NOT NULL,
+	time_created timestamp with time zone NOT NULL
+);
+
+CREATE TABLE webhook_deliveries (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	capture integer NOT NULL REFERENCES captures(id),
+	webhook integer REFERENCES webhooks(id) ON DELETE CASCADE,
+	url text NOT NULL,
+	payload text NOT NULL,
+	state text NOT NULL,
+	attempts integer NOT NULL,
+	response_status integer,
+	error text,
+	time_created timestamp with time zone NOT NULL,
+	time_updated timestamp with time zone NOT NULL
+);
+
+CREATE INDEX webhook_deliveries_capture ON webhook_deliveries (capture);
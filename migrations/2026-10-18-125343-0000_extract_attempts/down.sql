@@ -0,0 +1 @@
+DROP TABLE extract_attempts;
@@ -0,0 +1,12 @@
+CREATE TABLE extract_attempts (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	extract integer NOT NULL REFERENCES extracts(id),
+	worker text,
+	ticket uuid,
+	dispatches integer NOT NULL,
+	failure text,
+	time_created timestamp with time zone NOT NULL,
+	time_finished timestamp with time zone NOT NULL
+);
+
+CREATE INDEX extract_attempts_extract ON extract_attempts (extract);
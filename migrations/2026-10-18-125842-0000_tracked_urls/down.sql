@@ -0,0 +1,2 @@
+ALTER TABLE captures DROP COLUMN tracked_url;
+DROP TABLE tracked_urls;
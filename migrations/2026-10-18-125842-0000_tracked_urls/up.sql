@@ -0,0 +1,9 @@
+CREATE TABLE tracked_urls (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	url text NOT NULL UNIQUE,
+	time_created timestamp with time zone NOT NULL
+);
+
+-- Existing captures are linked at startup, since normalization is done by the core
+ALTER TABLE captures ADD COLUMN tracked_url integer REFERENCES tracked_urls(id);
+CREATE INDEX captures_tracked_url ON captures (tracked_url, time_initiated);
@@ -0,0 +1,14 @@
+CREATE TABLE schedules (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	owner integer NOT NULL REFERENCES users(id),
+	url text NOT NULL,
+	public boolean NOT NULL,
+	cron text,
+	interval_secs integer,
+	enabled boolean NOT NULL,
+	last_capture integer REFERENCES captures(id) ON DELETE SET NULL,
+	time_created timestamp with time zone NOT NULL,
+	time_next timestamp with time zone NOT NULL,
+	CHECK ((cron IS NULL) <> (interval_secs IS NULL))
+);
+CREATE INDEX schedules_owner ON schedules (owner);
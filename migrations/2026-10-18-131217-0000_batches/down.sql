@@ -0,0 +1,3 @@
+DROP TABLE capture_tags;
+ALTER TABLE captures DROP COLUMN batch;
+DROP TABLE batches;
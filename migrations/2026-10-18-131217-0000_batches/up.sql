@@ -0,0 +1,17 @@
+CREATE TABLE batches (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	uuid uuid NOT NULL UNIQUE,
+	owner integer NOT NULL REFERENCES users(id),
+	public boolean NOT NULL,
+	time_created timestamp with time zone NOT NULL
+);
+
+ALTER TABLE captures ADD COLUMN batch integer REFERENCES batches(id);
+CREATE INDEX captures_batch ON captures (batch);
+
+CREATE TABLE capture_tags (
+	capture integer NOT NULL REFERENCES captures(id) ON DELETE CASCADE,
+	tag text NOT NULL,
+	PRIMARY KEY (capture, tag)
+);
+CREATE INDEX capture_tags_tag ON capture_tags (tag);
@@ -0,0 +1,11 @@
+ALTER TABLE webhook_deliveries
+	DROP CONSTRAINT webhook_deliveries_capture_fkey,
+	ADD CONSTRAINT webhook_deliveries_capture_fkey FOREIGN KEY (capture) REFERENCES captures(id);
+ALTER TABLE extract_attempts
+	DROP CONSTRAINT extract_attempts_extract_fkey,
+	ADD CONSTRAINT extract_attempts_extract_fkey FOREIGN KEY (extract) REFERENCES extracts(id);
+ALTER TABLE extracts
+	DROP CONSTRAINT extracts_capture_fkey,
+	ADD CONSTRAINT extracts_capture_fkey FOREIGN KEY (capture) REFERENCES captures(id);
+
+ALTER TABLE captures DROP COLUMN time_deleted;
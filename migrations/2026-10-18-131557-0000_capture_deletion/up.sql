@@ -0,0 +1,12 @@
+ALTER TABLE captures ADD COLUMN time_deleted timestamp with time zone;
+
+-- Removing a capture removes everything recorded about it
+ALTER TABLE extracts
+	DROP CONSTRAINT extracts_capture_fkey,
+	ADD CONSTRAINT extracts_capture_fkey FOREIGN KEY (capture) REFERENCES captures(id) ON DELETE CASCADE;
+ALTER TABLE extract_attempts
+	DROP CONSTRAINT extract_attempts_extract_fkey,
+	ADD CONSTRAINT extract_attempts_extract_fkey FOREIGN KEY (extract) REFERENCES extracts(id) ON DELETE CASCADE;
+ALTER TABLE webhook_deliveries
+	DROP CONSTRAINT webhook_deliveries_capture_fkey,
+	ADD CONSTRAINT webhook_deliveries_capture_fkey FOREIGN KEY (capture) REFERENCES captures(id) ON DELETE CASCADE;
@@ -0,0 +1,3 @@
+ALTER TABLE captures
+	DROP COLUMN notes,
+	DROP COLUMN title;
@@ -0,0 +1,3 @@
+ALTER TABLE captures
+	ADD COLUMN title text,
+	ADD COLUMN notes text;
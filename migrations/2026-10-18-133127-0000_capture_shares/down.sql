@@ -0,0 +1 @@
+DROP TABLE capture_shares;
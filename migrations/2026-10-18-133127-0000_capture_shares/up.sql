@@ -0,0 +1,10 @@
+CREATE TABLE capture_shares (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	capture integer NOT NULL REFERENCES captures(id) ON DELETE CASCADE,
+	token_hash text NOT NULL UNIQUE,
+	extractor text,
+	time_created timestamp with time zone NOT NULL,
+	time_expires timestamp with time zone,
+	time_last_used timestamp with time zone
+);
+CREATE INDEX capture_shares_capture ON capture_shares (capture);
@@ -0,0 +1,3 @@
+ALTER TABLE captures DROP COLUMN group_id;
+DROP TABLE group_members;
+DROP TABLE groups;
@@ -0,0 +1,17 @@
+CREATE TABLE groups (
+	id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	name text NOT NULL UNIQUE,
+	time_created timestamp with time zone NOT NULL
+);
+
+CREATE TABLE group_members (
+	group_id integer NOT NULL REFERENCES groups(id) ON DELETE CASCADE,
+	member integer NOT NULL REFERENCES users(id),
+	role text NOT NULL CHECK (role IN ('viewer', 'contributor', 'admin')),
+	time_added timestamp with time zone NOT NULL,
+	PRIMARY KEY (group_id, member)
+);
+CREATE INDEX group_members_member ON group_members (member);
+
+ALTER TABLE captures ADD COLUMN group_id integer REFERENCES groups(id) ON DELETE SET NULL;
+CREATE INDEX captures_group_id ON captures (group_id);
@@ -1,27 +1,31 @@
-use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Responder, cookie, get, post, web};
+use actix_web::{
+    App, FromRequest, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder, cookie,
+    delete, get, patch, post, put, web,
+};
 use diesel::prelude::*;
-use diesel::result::DatabaseErrorKind;
 use diesel_async::RunQueryDsl;
+use futures_util::future::LocalBoxFuture;
 use lazy_static::lazy_static;
 use log::*;
 use tera::{Context, Tera};
 
 use webarc::core;
+use webarc::core::audit::AuditEvent;
 use webarc::core::models::*;
 use webarc::core::schema;
 use webarc::msg::clicor;
+use webarc::msg::clicor::{AuditAction, Scope};
 
 lazy_static! {
     pub static ref TEMPLATES: Tera = {
         let path = concat!(env!("CARGO_MANIFEST_DIR"), "/templates/*.html");
-        let tera = match Tera::new(path) {
+        match Tera::new(path) {
             Ok(t) => t,
             Err(e) => {
                 error!("Tera setup error: {e}");
                 std::process::exit(1);
             }
-        };
-        tera
+        }
     };
 }
 
@@ -31,50 +35,297 @@ struct AuthForm {
     pub password: String,
 }
 
+#[derive(serde::Deserialize)]
+struct TotpLoginForm {
+    pub challenge: String,
+    pub code: String,
+}
+
+#[derive(serde::Deserialize)]
+struct TotpForm {
+    pub code: String,
+}
+
+#[derive(serde::Deserialize)]
+struct LogoutForm {}
+
+#[derive(serde::Deserialize)]
+struct ScheduleForm {
+    pub url: url::Url,
+    #[serde(default)]
+    pub public: bool,
+    /// Either a cron expression or a number of minutes between captures
+    pub every: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ScheduleDeleteForm {}
+
+#[derive(serde::Deserialize)]
+struct BatchQuery {
+    #[serde(default)]
+    pub public: bool,
+    /// Comma-separated
+    #[serde(default)]
+    pub tags: String,
+}
+
+#[derive(serde::Deserialize)]
+struct GroupMemberForm {
+    pub username: String,
+    pub role: clicor::GroupRole,
+}
+
+#[derive(serde::Deserialize)]
+struct BrowseQuery {
+    #[serde(default = "first_page")]
+    pub page: u32,
+}
+
+fn first_page() -> u32 {
+    1
+}
+
+#[derive(serde::Deserialize)]
+struct ShareQuery {
+    pub share: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct DeleteQuery {
+    #[serde(default)]
+    pub permanent: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct CaptureForm {}
+
+#[derive(serde::Deserialize)]
+struct RetryForm {
+    pub extractor: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct OidcCallback {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
 /// Extract `token` from `Authorization: Bearer token` header, if able
-fn get_bearer_token(req: &HttpRequest) -> Option<u128> {
+fn get_bearer_token(req: &HttpRequest) -> Option<core::auth::Token> {
     let authorization = req.headers().get("authorization")?.to_str().ok()?;
     if !authorization.starts_with("Bearer ") {
         return None;
     }
-    let token = (&authorization[7..]).to_string();
-    let token = token.parse::<u128>().ok();
-    token
+    authorization[7..].parse::<core::auth::Token>().ok()
+}
+
+/// Extract a session token from the `Authorization` header, ignoring API keys
+fn get_bearer_session(req: &HttpRequest) -> Option<u128> {
+    match get_bearer_token(req)? {
+        core::auth::Token::Session(t) => Some(t),
+        core::auth::Token::ApiKey(_) => None,
+    }
+}
+
+/// Name of the cookie holding the double-submit CSRF token
+const CSRF_COOKIE: &str = "csrf";
+
+/// Build a cookie which scripts can't read and which isn't sent on cross-site subrequests
+fn hardened_cookie(
+    name: &'static str,
+    value: String,
+    state: &core::state::State,
+) -> cookie::Cookie<'static> {
+    let mut cookie = cookie::Cookie::new(name, value);
+    cookie.set_path("/");
+    cookie.set_http_only(true);
+    cookie.set_same_site(cookie::SameSite::Lax);
+    cookie.set_secure(state.secure_cookies());
+    cookie
+}
+
+/// Extract the browser's CSRF token from its cookie, if it has one
+fn get_csrf_cookie(req: &HttpRequest) -> Option<String> {
+    let cookie = req.cookie(CSRF_COOKIE)?;
+    Some(cookie.value().to_string()).filter(|t| !t.is_empty())
+}
+
+/// A urlencoded form whose `csrf` field must match the browser's CSRF cookie
+///
+/// Requests failing the check are rejected with `403 Forbidden` before the
+/// handler runs.
+struct CsrfForm<T>(T);
+
+impl<T> std::ops::Deref for CsrfForm<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: serde::de::DeserializeOwned + 'static> FromRequest for CsrfForm<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let req = req.clone();
+        let body = web::Bytes::from_request(&req, payload);
+        Box::pin(async move {
+            let body = body.await?;
+            let submitted = url::form_urlencoded::parse(&body)
+                .find(|(k, _)| k == "csrf")
+                .map(|(_, v)| v.into_owned());
+            let valid = match (get_csrf_cookie(&req), submitted) {
+                (Some(expected), Some(submitted)) => {
+                    core::auth::secrets_match(&expected, &submitted)
+                }
+                _ => false,
+            };
+            if !valid {
+                debug!("{} rejected form without valid CSRF token", req.path());
+                return Err(actix_web::error::ErrorForbidden(
+                    "Missing or invalid CSRF token",
+                ));
+            }
+            let form =
+                serde_urlencoded::from_bytes(&body).map_err(actix_web::error::ErrorBadRequest)?;
+            Ok(CsrfForm(form))
+        })
+    }
 }
 
 /// Extract token from cookie, if able
 fn get_cookie_token(req: &HttpRequest) -> Option<u128> {
     let cookie = req.cookie("auth")?;
     let token = cookie.value();
-    let token = token.parse::<u128>().ok();
-    token
+    token.parse::<u128>().ok()
 }
 
 /// Extract token by any available methods
-fn get_token(req: &HttpRequest) -> Option<u128> {
-    get_bearer_token(req).or(get_cookie_token(req))
+fn get_token(req: &HttpRequest) -> Option<core::auth::Token> {
+    get_bearer_token(req).or(get_cookie_token(req).map(core::auth::Token::Session))
+}
+
+/// Determine the client's address, honouring proxy headers only if configured to
+fn get_client_ip(req: &HttpRequest, state: &core::state::State) -> Option<String> {
+    if state.trust_proxy_headers() {
+        let info = req.connection_info();
+        info.realip_remote_addr().map(|a| a.to_string())
+    } else {
+        req.peer_addr().map(|a| a.ip().to_string())
+    }
+}
+
+/// Map a failed login onto a response
+fn login_failure_response(e: core::act::LoginError) -> HttpResponse {
+    match e {
+        core::act::LoginError::InvalidCredentialsError => {
+            HttpResponse::Unauthorized().json(clicor::AuthResponse::InvalidCredentials)
+        }
+        core::act::LoginError::AccountDisabledError => {
+            HttpResponse::Forbidden().json(clicor::AuthResponse::AccountDisabled)
+        }
+        core::act::LoginError::SecondFactorRequiredError { .. } => {
+            HttpResponse::Unauthorized().json(clicor::AuthResponse::SecondFactorRequired)
+        }
+        core::act::LoginError::RateLimitedError { retry_after } => HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", retry_after.to_string()))
+            .json(clicor::AuthResponse::RateLimited { retry_after }),
+        e => {
+            error!("/auth login failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: login")
+        }
+    }
+}
+
+/// Extract the `User-Agent` header, if present
+fn get_user_agent(req: &HttpRequest) -> Option<String> {
+    let user_agent = req.headers().get("user-agent")?.to_str().ok()?;
+    Some(user_agent.to_string())
 }
 
 #[get("/version")]
 async fn version() -> impl Responder {
-    format!("{}", env!("CARGO_PKG_VERSION"))
+    env!("CARGO_PKG_VERSION").to_string()
 }
 
-#[get("/login")]
-async fn tera_login() -> impl Responder {
-    let document = TEMPLATES.render("login.html", &Context::new());
-    match document {
+/// Render a template, or an error response if that fails
+///
+/// The browser's CSRF token is made available to the template as `csrf_token`,
+/// and a CSRF cookie is issued if the browser doesn't have one yet.
+fn render(
+    template: &str,
+    mut context: Context,
+    req: &HttpRequest,
+    state: &core::state::State,
+) -> HttpResponse {
+    let (csrf_token, issued) = match get_csrf_cookie(req) {
+        Some(t) => (t, false),
+        None => (core::auth::generate_secret(), true),
+    };
+    context.insert("csrf_token", &csrf_token);
+    let mut response = match TEMPLATES.render(template, &context) {
         Ok(d) => HttpResponse::Ok().body(d),
         Err(e) => {
-            error!("Error rendering login.html: {e}");
-            HttpResponse::InternalServerError().body("Error rendering login.html")
+            error!("Error rendering {template}: {e}");
+            return HttpResponse::InternalServerError().body(format!("Error rendering {template}"));
         }
+    };
+    if issued && let Err(e) = response.add_cookie(&hardened_cookie(CSRF_COOKIE, csrf_token, state))
+    {
+        error!("Error setting CSRF cookie: {e}");
+    }
+    response
+}
+
+/// Start a browser session for a user who has just logged in
+async fn start_form_session(
+    user_id: i32,
+    full_req: &HttpRequest,
+    state: &core::state::State,
+) -> HttpResponse {
+    let new_token = rand::random::<u128>();
+    let user_agent = get_user_agent(full_req);
+    if let Err(e) = state.register_token(new_token, user_id, user_agent).await {
+        error!("/auth register token failed: {e}");
+        return HttpResponse::InternalServerError().body("Internal server error: register token");
+    }
+    let cookie = hardened_cookie("auth", new_token.to_string(), state);
+    HttpResponse::SeeOther()
+        .cookie(cookie)
+        .insert_header(("Location", "/dashboard"))
+        .finish()
+}
+
+/// Name of the cookie tying an OIDC login to the browser which started it
+const OIDC_BINDING_COOKIE: &str = "oidc_binding";
+
+/// Extract the browser's OIDC login binding from its cookie, if it has one
+fn get_oidc_binding(req: &HttpRequest) -> Option<String> {
+    let cookie = req.cookie(OIDC_BINDING_COOKIE)?;
+    Some(cookie.value().to_string()).filter(|b| !b.is_empty())
+}
+
+/// Context shared by every rendering of the login page
+fn login_context(state: &core::state::State) -> Context {
+    let mut context = Context::new();
+    if let Some(oidc) = state.oidc() {
+        context.insert("oidc", oidc.config().display_name());
     }
+    context
+}
+
+#[get("/login")]
+async fn tera_login(full_req: HttpRequest, state: web::Data<core::state::State>) -> impl Responder {
+    render("login.html", login_context(&state), &full_req, &state)
 }
 
 #[get("/dashboard")]
 async fn dashboard(state: web::Data<core::state::State>, full_req: HttpRequest) -> impl Responder {
-    use core::schema::captures;
+    use core::schema::{captures, group_members, groups};
     let bearer = match get_cookie_token(&full_req) {
         Some(t) => t,
         None => {
@@ -99,10 +350,19 @@ async fn dashboard(state: web::Data<core::state::State>, full_req: HttpRequest)
             return HttpResponse::InternalServerError().body("Internal server error: db pool");
         }
     };
-    let captures: Result<Vec<DbCapture>, _> = captures::dsl::captures
-        .filter(captures::dsl::owner.eq(user_id))
-        .order(captures::dsl::time_initiated.desc())
+    let captures: Result<Vec<(DbCapture, Option<String>)>, _> = captures::table
+        .left_join(groups::table)
+        .filter(
+            captures::owner.eq(user_id).or(captures::group_id.eq_any(
+                group_members::table
+                    .filter(group_members::member.eq(user_id))
+                    .select(group_members::group_id.nullable()),
+            )),
+        )
+        .filter(captures::time_deleted.is_null())
+        .order(captures::time_initiated.desc())
         .limit(20)
+        .select((captures::all_columns, groups::name.nullable()))
         .load(&mut conn)
         .await;
     let captures = match captures {
@@ -112,73 +372,179 @@ async fn dashboard(state: web::Data<core::state::State>, full_req: HttpRequest)
             return HttpResponse::InternalServerError().body("Internal server error: query");
         }
     };
-    let captures: Vec<(String, String, String)> = captures
+    let captures: Vec<(String, String, String, String)> = captures
         .into_iter()
-        .map(|c| {
+        .map(|(c, group)| {
             (
                 c.uuid.to_string(),
                 chrono_humanize::HumanTime::from(c.time_initiated).to_string(),
                 c.url.to_string(),
+                group.unwrap_or_default(),
             )
         })
         .collect();
     error!("{:#?}", captures);
     context.insert("captures", &captures);
-    let document = TEMPLATES.render("dashboard.html", &context);
-    match document {
-        Ok(d) => HttpResponse::Ok().body(d),
+    match core::trash::list(&state, user_id).await {
+        Ok(trash) => context.insert("trash", &trash),
         Err(e) => {
-            error!("Error rendering dashboard.html: {e}");
-            HttpResponse::InternalServerError().body("Error rendering dashboard.html")
+            error!("user trash failed: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error: query");
+        }
+    }
+    match core::group::list(&state, user_id).await {
+        Ok(groups) => context.insert("groups", &groups),
+        Err(e) => {
+            error!("user groups failed: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error: query");
+        }
+    }
+    match core::schedule::list(&state, user_id).await {
+        Ok(schedules) => context.insert("schedules", &schedules),
+        Err(e) => {
+            error!("user schedules failed: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error: query");
         }
     }
+    if let Some(oidc) = state.oidc() {
+        context.insert("oidc", oidc.config().display_name());
+    }
+    render("dashboard.html", context, &full_req, &state)
 }
 
 #[post("/user/create")]
 async fn user_create(
     req: web::Json<clicor::CreateUserRequest>,
+    full_req: HttpRequest,
     state: web::Data<core::state::State>,
 ) -> impl Responder {
     let username = req.username();
     let password = req.password();
-    if username.len() == 0 {
+    if username.is_empty() {
         return HttpResponse::BadRequest().json(clicor::CreateUserResponse::InvalidUsername);
     }
-    if password.len() == 0 {
+    if password.is_empty() {
         return HttpResponse::BadRequest().json(clicor::CreateUserResponse::InvalidPassword);
     }
-    let passhash = match bcrypt::hash(password, bcrypt::DEFAULT_COST) {
-        Ok(h) => h,
+    match core::auth::check_password(state.password_policy(), password) {
+        Ok(()) => {}
+        Err(core::auth::PasswordRejection::TooShort { min_length }) => {
+            return HttpResponse::BadRequest()
+                .json(clicor::CreateUserResponse::PasswordTooShort { min_length });
+        }
+        Err(core::auth::PasswordRejection::TooWeak { missing }) => {
+            return HttpResponse::BadRequest()
+                .json(clicor::CreateUserResponse::PasswordTooWeak { missing });
+        }
+    }
+    let result = core::act::create_user(username, password, req.invite(), &state).await;
+    match result {
+        Ok(user_id) => {
+            let ip = get_client_ip(&full_req, &state);
+            let event = AuditEvent::new(AuditAction::UserCreate)
+                .actor(Some(user_id))
+                .ip(ip.as_deref())
+                .target(username);
+            core::audit::record(&state, event).await;
+            HttpResponse::Created().json(clicor::CreateUserResponse::Created)
+        }
+        Err(core::act::CreateUserError::UnavailableUsernameError) => {
+            HttpResponse::Conflict().json(clicor::CreateUserResponse::UnavailableUsername)
+        }
+        Err(core::act::CreateUserError::RegistrationClosedError) => {
+            HttpResponse::Forbidden().json(clicor::CreateUserResponse::RegistrationClosed)
+        }
+        Err(core::act::CreateUserError::InvalidInviteError) => {
+            HttpResponse::Forbidden().json(clicor::CreateUserResponse::InvalidInvite)
+        }
         Err(e) => {
-            error!("bcrypt::hash failed: {e}");
-            return HttpResponse::InternalServerError().body("Internal server error: bcrypt");
+            error!("New user creation failed unexpectedly: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: create user")
+        }
+    }
+}
+
+#[post("/user/password")]
+async fn user_password(
+    req: web::Json<clicor::ChangePasswordRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::ChangePasswordResponse::Unauthenticated);
         }
     };
-    let mut conn = match state.db_pool().await.get().await {
-        Ok(c) => c,
-        Err(e) => {
-            error!("db_pool.get() failed: {e}");
-            return HttpResponse::InternalServerError().body("Internal server error: db pool");
+    let user_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::ChangePasswordResponse::Unauthenticated);
         }
     };
-    let new_user = core::models::InsUser::new(username.to_string(), passhash);
-    let count = diesel::insert_into(core::schema::users::table)
-        .values(new_user)
-        .execute(&mut conn)
-        .await;
-    match count {
-        Ok(1) => HttpResponse::Created().json(clicor::CreateUserResponse::Created),
-        Err(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
-            HttpResponse::Conflict().json(clicor::CreateUserResponse::UnavailableUsername)
+    match core::auth::check_password(state.password_policy(), req.new_password()) {
+        Ok(()) => {}
+        Err(core::auth::PasswordRejection::TooShort { min_length }) => {
+            return HttpResponse::BadRequest()
+                .json(clicor::ChangePasswordResponse::PasswordTooShort { min_length });
+        }
+        Err(core::auth::PasswordRejection::TooWeak { missing }) => {
+            return HttpResponse::BadRequest()
+                .json(clicor::ChangePasswordResponse::PasswordTooWeak { missing });
+        }
+    }
+    let ip = get_client_ip(&full_req, &state);
+    let result = core::act::change_password(
+        user_id,
+        req.old_password(),
+        req.new_password(),
+        ip.as_deref(),
+        &state,
+    )
+    .await;
+    match result {
+        Ok(()) => HttpResponse::Ok().json(clicor::ChangePasswordResponse::Changed),
+        Err(core::act::PasswordError::IncorrectPasswordError) => {
+            HttpResponse::Unauthorized().json(clicor::ChangePasswordResponse::InvalidCredentials)
+        }
+        Err(core::act::PasswordError::PasswordRateLimitedError { retry_after }) => {
+            HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .json(clicor::ChangePasswordResponse::RateLimited { retry_after })
         }
         Err(e) => {
-            error!("New username insertion failed unexpectedly: {e}");
-            HttpResponse::InternalServerError().body("Internal server error: db insert")
+            error!("/user/password change password failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: change password")
+        }
+    }
+}
+
+#[post("/user/password/reset")]
+async fn user_password_reset(
+    req: web::Json<clicor::ResetPasswordRequest>,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    match core::auth::check_password(state.password_policy(), req.new_password()) {
+        Ok(()) => {}
+        Err(core::auth::PasswordRejection::TooShort { min_length }) => {
+            return HttpResponse::BadRequest()
+                .json(clicor::ResetPasswordResponse::PasswordTooShort { min_length });
         }
-        Ok(n) => {
-            error!("New username insertion should affect 1 row but affected {n}");
-            HttpResponse::InternalServerError()
-                .body("Internal server error: db affected too many rows")
+        Err(core::auth::PasswordRejection::TooWeak { missing }) => {
+            return HttpResponse::BadRequest()
+                .json(clicor::ResetPasswordResponse::PasswordTooWeak { missing });
+        }
+    }
+    match core::act::reset_password(req.token(), req.new_password(), &state).await {
+        Ok(()) => HttpResponse::Ok().json(clicor::ResetPasswordResponse::Reset),
+        Err(core::act::PasswordError::InvalidResetTokenError) => {
+            HttpResponse::Unauthorized().json(clicor::ResetPasswordResponse::InvalidToken)
+        }
+        Err(e) => {
+            error!("/user/password/reset reset password failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: reset password")
         }
     }
 }
@@ -186,118 +552,2227 @@ async fn user_create(
 #[post("/auth")]
 async fn auth(
     req: web::Json<clicor::AuthRequest>,
+    full_req: HttpRequest,
     state: web::Data<core::state::State>,
 ) -> impl Responder {
-    use core::schema::users;
-    if req.username().len() == 0 || req.password().len() == 0 {
+    if req.username().is_empty() || req.password().is_empty() {
         return HttpResponse::BadRequest().json(clicor::AuthResponse::UnacceptableCredentials);
     }
-    let mut conn = match state.db_pool().await.get().await {
-        Ok(c) => c,
+    let ip = get_client_ip(&full_req, &state);
+    let result = core::act::login(
+        req.username(),
+        req.password(),
+        req.totp(),
+        ip.as_deref(),
+        &state,
+    )
+    .await;
+    let user_id = match result {
+        Ok(u) => u,
+        Err(e) => return login_failure_response(e),
+    };
+    let new_token = rand::random::<u128>();
+    let user_agent = get_user_agent(&full_req);
+    if let Err(e) = state.register_token(new_token, user_id, user_agent).await {
+        error!("/auth register token failed: {e}");
+        return HttpResponse::InternalServerError().body("Internal server error: register token");
+    }
+
+    HttpResponse::Ok().json(clicor::AuthResponse::Authenticated {
+        token: new_token.to_string(),
+    })
+}
+
+#[post("/auth/form")]
+async fn auth_form(
+    form: CsrfForm<AuthForm>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    if form.username.is_empty() || form.password.is_empty() {
+        return HttpResponse::BadRequest().json(clicor::AuthResponse::UnacceptableCredentials);
+    }
+    let ip = get_client_ip(&full_req, &state);
+    let result =
+        core::act::login(&form.username, &form.password, None, ip.as_deref(), &state).await;
+    match result {
+        Ok(user_id) => start_form_session(user_id, &full_req, &state).await,
+        Err(core::act::LoginError::SecondFactorRequiredError { user_id }) => {
+            let challenge = state.login_challenges().create(user_id).await;
+            let mut context = login_context(&state);
+            context.insert("challenge", &challenge.to_string());
+            render("login.html", context, &full_req, &state)
+        }
+        Err(e) => login_failure_response(e),
+    }
+}
+
+#[post("/auth/form/totp")]
+async fn auth_form_totp(
+    form: CsrfForm<TotpLoginForm>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let challenge = form.challenge.parse::<u128>().ok();
+    let user_id = match challenge {
+        Some(c) => state.login_challenges().user(c).await,
+        None => None,
+    };
+    let (Some(challenge), Some(user_id)) = (challenge, user_id) else {
+        return HttpResponse::SeeOther()
+            .insert_header(("Location", "/login"))
+            .finish();
+    };
+    let ip = get_client_ip(&full_req, &state);
+    match core::act::login_second_factor(user_id, form.code.trim(), ip.as_deref(), &state).await {
+        Ok(user_id) => {
+            state.login_challenges().remove(challenge).await;
+            start_form_session(user_id, &full_req, &state).await
+        }
+        Err(core::act::LoginError::InvalidCredentialsError) => {
+            let mut context = login_context(&state);
+            context.insert("challenge", &challenge.to_string());
+            context.insert("error", "Incorrect code");
+            render("login.html", context, &full_req, &state)
+        }
+        Err(e) => login_failure_response(e),
+    }
+}
+
+#[get("/auth/oidc")]
+async fn auth_oidc(full_req: HttpRequest, state: web::Data<core::state::State>) -> impl Responder {
+    let Some(oidc) = state.oidc() else {
+        return HttpResponse::NotFound().body("OIDC login is not configured");
+    };
+    // A signed-in user starting the flow is linking the identity to their account
+    let link_user = get_cookie_user(&full_req, &state).await;
+    let binding = get_oidc_binding(&full_req).unwrap_or_else(core::auth::generate_secret);
+    match oidc.authorization_url(link_user, &binding).await {
+        Ok(url) => {
+            let mut cookie = hardened_cookie(OIDC_BINDING_COOKIE, binding, &state);
+            cookie.set_path("/auth/oidc");
+            HttpResponse::SeeOther()
+                .cookie(cookie)
+                .insert_header(("Location", url.to_string()))
+                .finish()
+        }
         Err(e) => {
-            error!("db_pool.get() failed: {e}");
-            return HttpResponse::InternalServerError().body("Internal server error: db pool");
+            error!("/auth/oidc start login failed: {e}");
+            HttpResponse::BadGateway().body("Identity provider unavailable")
+        }
+    }
+}
+
+#[get("/auth/oidc/callback")]
+async fn auth_oidc_callback(
+    query: web::Query<OidcCallback>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let Some(oidc) = state.oidc() else {
+        return HttpResponse::NotFound().body("OIDC login is not configured");
+    };
+    let mut context = login_context(&state);
+    if let Some(error) = &query.error {
+        debug!("/auth/oidc/callback provider returned {error}");
+        context.insert(
+            "error",
+            "Sign-in was cancelled or refused by the identity provider",
+        );
+        return render("login.html", context, &full_req, &state);
+    }
+    let (Some(code), Some(oidc_state)) = (&query.code, &query.state) else {
+        return HttpResponse::BadRequest().body("Missing code or state");
+    };
+    let binding = get_oidc_binding(&full_req).unwrap_or_default();
+    let ip = get_client_ip(&full_req, &state);
+    let identity = match oidc.complete(code, oidc_state, &binding).await {
+        Ok(i) => i,
+        Err(e) => {
+            warn!("/auth/oidc/callback rejected login: {e}");
+            context.insert("error", "Sign-in with the identity provider failed");
+            return render("login.html", context, &full_req, &state);
+        }
+    };
+    let linking = identity.link_user.is_some();
+    match core::oidc::login(&state, identity, ip.as_deref()).await {
+        Ok(_) if linking => HttpResponse::SeeOther()
+            .insert_header(("Location", "/dashboard"))
+            .finish(),
+        Ok(user_id) => start_form_session(user_id, &full_req, &state).await,
+        Err(
+            e @ (core::oidc::OidcError::NotProvisionedError
+            | core::oidc::OidcError::MissingUsernameError { .. }
+            | core::oidc::OidcError::UnavailableUsernameError { .. }
+            | core::oidc::OidcError::IdentityInUseError
+            | core::oidc::OidcError::OidcAccountDisabledError),
+        ) => {
+            context.insert("error", &e.to_string());
+            render("login.html", context, &full_req, &state)
+        }
+        Err(e) => {
+            error!("/auth/oidc/callback login failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: oidc login")
+        }
+    }
+}
+
+#[post("/auth/logout")]
+async fn auth_logout(
+    form: Option<CsrfForm<LogoutForm>>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    if let Some(bearer) = get_bearer_token(&full_req) {
+        let core::auth::Token::Session(bearer) = bearer else {
+            return HttpResponse::BadRequest().json(clicor::LogoutResponse::ApiKeyCannotLogOut);
+        };
+        return match state.revoke_token(bearer).await {
+            Ok(true) => HttpResponse::Ok().json(clicor::LogoutResponse::LoggedOut),
+            Ok(false) => HttpResponse::Unauthorized().json(clicor::LogoutResponse::Unauthenticated),
+            Err(e) => {
+                error!("/auth/logout revoke token failed: {e}");
+                HttpResponse::InternalServerError().body("Internal server error: revoke token")
+            }
+        };
+    }
+    if form.is_none() {
+        return HttpResponse::Forbidden().body("Missing or invalid CSRF token");
+    }
+    if let Some(cookie) = get_cookie_token(&full_req)
+        && let Err(e) = state.revoke_token(cookie).await
+    {
+        error!("/auth/logout revoke token failed: {e}");
+        return HttpResponse::InternalServerError().body("Internal server error: revoke token");
+    }
+    let mut removal = hardened_cookie("auth", String::new(), &state);
+    removal.make_removal();
+    HttpResponse::SeeOther()
+        .cookie(removal)
+        .insert_header(("Location", "/login"))
+        .finish()
+}
+
+#[post("/0/totp/enroll")]
+async fn totp_enroll(
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let user_id = match get_bearer_session(&full_req) {
+        Some(t) => state.user_from_token(t).await,
+        None => None,
+    };
+    let Some(user_id) = user_id else {
+        return HttpResponse::Unauthorized().json(clicor::TotpEnrollResponse::Unauthenticated);
+    };
+    match core::totp::begin_enrollment(&state, user_id).await {
+        Ok(e) => HttpResponse::Ok().json(clicor::TotpEnrollResponse::Pending {
+            secret: e.secret,
+            otpauth_uri: e.otpauth_uri,
+            qr_svg: e.qr_svg,
+        }),
+        Err(core::totp::TotpError::AlreadyEnabledError) => {
+            HttpResponse::Conflict().json(clicor::TotpEnrollResponse::AlreadyEnabled)
+        }
+        Err(e) => {
+            error!("/0/totp/enroll begin enrollment failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: totp enroll")
+        }
+    }
+}
+
+#[post("/0/totp/confirm")]
+async fn totp_confirm(
+    req: web::Json<clicor::TotpCodeRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let user_id = match get_bearer_session(&full_req) {
+        Some(t) => state.user_from_token(t).await,
+        None => None,
+    };
+    let Some(user_id) = user_id else {
+        return HttpResponse::Unauthorized().json(clicor::TotpConfirmResponse::Unauthenticated);
+    };
+    match core::totp::confirm_enrollment(&state, user_id, req.code().trim()).await {
+        Ok(recovery_codes) => {
+            HttpResponse::Ok().json(clicor::TotpConfirmResponse::Enabled { recovery_codes })
+        }
+        Err(core::totp::TotpError::AlreadyEnabledError) => {
+            HttpResponse::Conflict().json(clicor::TotpConfirmResponse::AlreadyEnabled)
+        }
+        Err(core::totp::TotpError::NotEnrolledError) => {
+            HttpResponse::Conflict().json(clicor::TotpConfirmResponse::NotEnrolled)
+        }
+        Err(core::totp::TotpError::InvalidCodeError) => {
+            HttpResponse::BadRequest().json(clicor::TotpConfirmResponse::InvalidCode)
+        }
+        Err(e) => {
+            error!("/0/totp/confirm confirm enrollment failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: totp confirm")
+        }
+    }
+}
+
+#[post("/0/totp/disable")]
+async fn totp_disable(
+    req: web::Json<clicor::TotpCodeRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let user_id = match get_bearer_session(&full_req) {
+        Some(t) => state.user_from_token(t).await,
+        None => None,
+    };
+    let Some(user_id) = user_id else {
+        return HttpResponse::Unauthorized().json(clicor::TotpDisableResponse::Unauthenticated);
+    };
+    match core::totp::disable(&state, user_id, req.code().trim()).await {
+        Ok(()) => HttpResponse::Ok().json(clicor::TotpDisableResponse::Disabled),
+        Err(core::totp::TotpError::NotEnabledError) => {
+            HttpResponse::Conflict().json(clicor::TotpDisableResponse::NotEnabled)
+        }
+        Err(core::totp::TotpError::InvalidCodeError) => {
+            HttpResponse::BadRequest().json(clicor::TotpDisableResponse::InvalidCode)
+        }
+        Err(e) => {
+            error!("/0/totp/disable disable failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: totp disable")
+        }
+    }
+}
+
+/// Look up the user behind a browser session, if any
+async fn get_cookie_user(req: &HttpRequest, state: &core::state::State) -> Option<i32> {
+    state.user_from_token(get_cookie_token(req)?).await
+}
+
+#[get("/account/totp")]
+async fn account_totp(
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let Some(user_id) = get_cookie_user(&full_req, &state).await else {
+        return HttpResponse::SeeOther()
+            .insert_header(("Location", "/login"))
+            .finish();
+    };
+    let mut context = Context::new();
+    match core::totp::begin_enrollment(&state, user_id).await {
+        Ok(e) => {
+            context.insert("secret", &e.secret);
+            context.insert("qr_svg", &e.qr_svg);
+        }
+        Err(core::totp::TotpError::AlreadyEnabledError) => context.insert("enabled", &true),
+        Err(e) => {
+            error!("/account/totp begin enrollment failed: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error: totp enroll");
+        }
+    }
+    render("totp.html", context, &full_req, &state)
+}
+
+#[post("/account/totp/confirm")]
+async fn account_totp_confirm(
+    form: CsrfForm<TotpForm>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let Some(user_id) = get_cookie_user(&full_req, &state).await else {
+        return HttpResponse::SeeOther()
+            .insert_header(("Location", "/login"))
+            .finish();
+    };
+    let mut context = Context::new();
+    match core::totp::confirm_enrollment(&state, user_id, form.code.trim()).await {
+        Ok(recovery_codes) => context.insert("recovery_codes", &recovery_codes),
+        Err(core::totp::TotpError::AlreadyEnabledError) => context.insert("enabled", &true),
+        Err(core::totp::TotpError::InvalidCodeError | core::totp::TotpError::NotEnrolledError) => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/account/totp"))
+                .finish();
+        }
+        Err(e) => {
+            error!("/account/totp/confirm confirm enrollment failed: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error: totp confirm");
+        }
+    }
+    render("totp.html", context, &full_req, &state)
+}
+
+#[post("/account/totp/disable")]
+async fn account_totp_disable(
+    form: CsrfForm<TotpForm>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let Some(user_id) = get_cookie_user(&full_req, &state).await else {
+        return HttpResponse::SeeOther()
+            .insert_header(("Location", "/login"))
+            .finish();
+    };
+    let mut context = Context::new();
+    match core::totp::disable(&state, user_id, form.code.trim()).await {
+        Ok(()) => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/dashboard"))
+                .finish();
+        }
+        Err(core::totp::TotpError::InvalidCodeError) => {
+            context.insert("enabled", &true);
+            context.insert("error", "Incorrect code");
+        }
+        Err(core::totp::TotpError::NotEnabledError) => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/account/totp"))
+                .finish();
+        }
+        Err(e) => {
+            error!("/account/totp/disable disable failed: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error: totp disable");
+        }
+    }
+    render("totp.html", context, &full_req, &state)
+}
+
+#[get("/0/sessions")]
+async fn sessions_list(
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    match state.list_sessions(user_id, bearer).await {
+        Ok(s) => HttpResponse::Ok().json(s),
+        Err(e) => {
+            error!("/0/sessions list sessions failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: list sessions")
+        }
+    }
+}
+
+#[delete("/0/sessions/{id}")]
+async fn sessions_revoke(
+    id: web::Path<i32>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::RevokeSessionResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::RevokeSessionResponse::Unauthenticated);
+        }
+    };
+    match state.revoke_session(user_id, id.into_inner()).await {
+        Ok(true) => HttpResponse::Ok().json(clicor::RevokeSessionResponse::Revoked),
+        Ok(false) => HttpResponse::NotFound().json(clicor::RevokeSessionResponse::NoSuchSession),
+        Err(e) => {
+            error!("/0/sessions revoke session failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: revoke session")
+        }
+    }
+}
+
+#[post("/0/keys")]
+async fn keys_create(
+    req: web::Json<clicor::CreateApiKeyRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateApiKeyResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateApiKeyResponse::Unauthenticated);
+        }
+    };
+    if req.name().is_empty() {
+        return HttpResponse::BadRequest().json(clicor::CreateApiKeyResponse::InvalidName);
+    }
+    if req.scopes().is_empty() {
+        return HttpResponse::BadRequest().json(clicor::CreateApiKeyResponse::NoScopes);
+    }
+    let result = state
+        .create_api_key(user_id, req.name(), req.scopes(), req.expires().cloned())
+        .await;
+    match result {
+        Ok((id, key)) => {
+            HttpResponse::Created().json(clicor::CreateApiKeyResponse::Created { id, key })
+        }
+        Err(e) => {
+            error!("/0/keys create key failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: create key")
+        }
+    }
+}
+
+#[get("/0/keys")]
+async fn keys_list(full_req: HttpRequest, state: web::Data<core::state::State>) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    match state.list_api_keys(user_id).await {
+        Ok(k) => HttpResponse::Ok().json(k),
+        Err(e) => {
+            error!("/0/keys list keys failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: list keys")
+        }
+    }
+}
+
+#[delete("/0/keys/{id}")]
+async fn keys_revoke(
+    id: web::Path<i32>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::RevokeApiKeyResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::RevokeApiKeyResponse::Unauthenticated);
+        }
+    };
+    match state.revoke_api_key(user_id, id.into_inner()).await {
+        Ok(true) => HttpResponse::Ok().json(clicor::RevokeApiKeyResponse::Revoked),
+        Ok(false) => HttpResponse::NotFound().json(clicor::RevokeApiKeyResponse::NoSuchKey),
+        Err(e) => {
+            error!("/0/keys revoke key failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: revoke key")
+        }
+    }
+}
+
+#[post("/0/webhooks")]
+async fn webhooks_create(
+    req: web::Json<clicor::CreateWebhookRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateWebhookResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateWebhookResponse::Unauthenticated);
+        }
+    };
+    if !core::webhook::is_deliverable(&state, req.url()).await {
+        return HttpResponse::BadRequest().json(clicor::CreateWebhookResponse::InvalidUrl);
+    }
+    match core::webhook::create(&state, user_id, req.url()).await {
+        Ok((id, secret)) => {
+            HttpResponse::Created().json(clicor::CreateWebhookResponse::Created { id, secret })
+        }
+        Err(e) => {
+            error!("/0/webhooks create webhook failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: create webhook")
+        }
+    }
+}
+
+#[get("/0/webhooks")]
+async fn webhooks_list(
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    match core::webhook::list(&state, user_id).await {
+        Ok(w) => HttpResponse::Ok().json(w),
+        Err(e) => {
+            error!("/0/webhooks list webhooks failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: list webhooks")
+        }
+    }
+}
+
+#[delete("/0/webhooks/{id}")]
+async fn webhooks_delete(
+    id: web::Path<i32>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::DeleteWebhookResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::DeleteWebhookResponse::Unauthenticated);
+        }
+    };
+    match core::webhook::delete(&state, user_id, id.into_inner()).await {
+        Ok(true) => HttpResponse::Ok().json(clicor::DeleteWebhookResponse::Deleted),
+        Ok(false) => HttpResponse::NotFound().json(clicor::DeleteWebhookResponse::NoSuchWebhook),
+        Err(e) => {
+            error!("/0/webhooks delete webhook failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: delete webhook")
+        }
+    }
+}
+
+#[get("/0/webhooks/deliveries")]
+async fn webhooks_deliveries(
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    match core::webhook::deliveries(&state, user_id).await {
+        Ok(d) => HttpResponse::Ok().json(d),
+        Err(e) => {
+            error!("/0/webhooks/deliveries list deliveries failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: list deliveries")
+        }
+    }
+}
+
+#[get("/0/admin/users")]
+async fn admin_users_list(
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    let admin_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    if !state.is_admin(admin_id).await {
+        return HttpResponse::Forbidden().body("Forbidden");
+    }
+    match core::admin::list_users(&state).await {
+        Ok(u) => HttpResponse::Ok().json(u),
+        Err(e) => {
+            error!("/0/admin/users list users failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: list users")
+        }
+    }
+}
+
+#[get("/0/admin/audit")]
+async fn admin_audit(
+    query: web::Query<clicor::AuditQuery>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    let admin_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    if !state.is_admin(admin_id).await {
+        return HttpResponse::Forbidden().body("Forbidden");
+    }
+    match core::audit::query(&state, &query).await {
+        Ok(p) => HttpResponse::Ok().json(p),
+        Err(e) => {
+            error!("/0/admin/audit query failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: audit query")
+        }
+    }
+}
+
+/// Map the outcome of a user modification onto a response
+fn modify_user_response(
+    result: Result<bool, core::admin::AdminError>,
+    success: clicor::ModifyUserResponse,
+) -> HttpResponse {
+    match result {
+        Ok(true) => HttpResponse::Ok().json(success),
+        Ok(false) => HttpResponse::NotFound().json(clicor::ModifyUserResponse::NoSuchUser),
+        Err(core::admin::AdminError::SelfModificationError) => {
+            HttpResponse::BadRequest().json(clicor::ModifyUserResponse::SelfModification)
+        }
+        Err(core::admin::AdminError::OwnsCapturesError) => {
+            HttpResponse::Conflict().json(clicor::ModifyUserResponse::OwnsCaptures)
+        }
+        Err(core::admin::AdminError::LastGroupAdminError) => {
+            HttpResponse::Conflict().json(clicor::ModifyUserResponse::LastGroupAdmin)
+        }
+        Err(e) => {
+            error!("Modifying user failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: modify user")
+        }
+    }
+}
+
+/// Record an admin's action on a user in the audit log
+async fn audit_admin_action(
+    full_req: &HttpRequest,
+    state: &core::state::State,
+    action: AuditAction,
+    admin_id: i32,
+    user_id: i32,
+) {
+    let ip = get_client_ip(full_req, state);
+    let event = AuditEvent::new(action)
+        .actor(Some(admin_id))
+        .ip(ip.as_deref())
+        .target(user_id);
+    core::audit::record(state, event).await;
+}
+
+#[post("/0/admin/users/{id}/disable")]
+async fn admin_users_disable(
+    id: web::Path<i32>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::ModifyUserResponse::Unauthenticated);
+        }
+    };
+    let admin_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::ModifyUserResponse::Unauthenticated);
+        }
+    };
+    if !state.is_admin(admin_id).await {
+        return HttpResponse::Forbidden().json(clicor::ModifyUserResponse::Forbidden);
+    }
+    let user_id = id.into_inner();
+    let result = core::admin::set_disabled(&state, admin_id, user_id, true).await;
+    if let Ok(true) = result {
+        audit_admin_action(
+            &full_req,
+            &state,
+            AuditAction::UserDisable,
+            admin_id,
+            user_id,
+        )
+        .await;
+    }
+    modify_user_response(result, clicor::ModifyUserResponse::Disabled)
+}
+
+#[post("/0/admin/users/{id}/enable")]
+async fn admin_users_enable(
+    id: web::Path<i32>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::ModifyUserResponse::Unauthenticated);
+        }
+    };
+    let admin_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::ModifyUserResponse::Unauthenticated);
+        }
+    };
+    if !state.is_admin(admin_id).await {
+        return HttpResponse::Forbidden().json(clicor::ModifyUserResponse::Forbidden);
+    }
+    let user_id = id.into_inner();
+    let result = core::admin::set_disabled(&state, admin_id, user_id, false).await;
+    if let Ok(true) = result {
+        audit_admin_action(
+            &full_req,
+            &state,
+            AuditAction::UserEnable,
+            admin_id,
+            user_id,
+        )
+        .await;
+    }
+    modify_user_response(result, clicor::ModifyUserResponse::Enabled)
+}
+
+#[delete("/0/admin/users/{id}")]
+async fn admin_users_delete(
+    id: web::Path<i32>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::ModifyUserResponse::Unauthenticated);
+        }
+    };
+    let admin_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::ModifyUserResponse::Unauthenticated);
+        }
+    };
+    if !state.is_admin(admin_id).await {
+        return HttpResponse::Forbidden().json(clicor::ModifyUserResponse::Forbidden);
+    }
+    let user_id = id.into_inner();
+    let result = core::admin::delete_user(&state, admin_id, user_id).await;
+    if let Ok(true) = result {
+        audit_admin_action(
+            &full_req,
+            &state,
+            AuditAction::UserDelete,
+            admin_id,
+            user_id,
+        )
+        .await;
+    }
+    modify_user_response(result, clicor::ModifyUserResponse::Deleted)
+}
+
+#[post("/0/admin/users/{id}/reset")]
+async fn admin_users_reset(
+    id: web::Path<i32>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreatePasswordResetResponse::Unauthenticated);
+        }
+    };
+    let admin_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreatePasswordResetResponse::Unauthenticated);
+        }
+    };
+    if !state.is_admin(admin_id).await {
+        return HttpResponse::Forbidden().json(clicor::CreatePasswordResetResponse::Forbidden);
+    }
+    let user_id = id.into_inner();
+    match core::admin::create_password_reset(&state, admin_id, user_id).await {
+        Ok(Some((token, expires))) => {
+            audit_admin_action(
+                &full_req,
+                &state,
+                AuditAction::PasswordReset,
+                admin_id,
+                user_id,
+            )
+            .await;
+            HttpResponse::Created()
+                .json(clicor::CreatePasswordResetResponse::Created { token, expires })
+        }
+        Ok(None) => HttpResponse::NotFound().json(clicor::CreatePasswordResetResponse::NoSuchUser),
+        Err(e) => {
+            error!("/0/admin/users reset password failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: create reset")
+        }
+    }
+}
+
+#[post("/0/admin/invites")]
+async fn admin_invites_create(
+    req: web::Json<clicor::CreateInviteRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateInviteResponse::Unauthenticated);
+        }
+    };
+    let admin_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateInviteResponse::Unauthenticated);
+        }
+    };
+    if !state.is_admin(admin_id).await {
+        return HttpResponse::Forbidden().json(clicor::CreateInviteResponse::Forbidden);
+    }
+    match core::admin::create_invite(&state, admin_id, req.expires().cloned()).await {
+        Ok(code) => HttpResponse::Created().json(clicor::CreateInviteResponse::Created { code }),
+        Err(e) => {
+            error!("/0/admin/invites create invite failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: create invite")
+        }
+    }
+}
+
+#[get("/0/admin/invites")]
+async fn admin_invites_list(
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_session(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    let admin_id = match state.user_from_token(bearer).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    if !state.is_admin(admin_id).await {
+        return HttpResponse::Forbidden().body("Forbidden");
+    }
+    match core::admin::list_invites(&state).await {
+        Ok(i) => HttpResponse::Ok().json(i),
+        Err(e) => {
+            error!("/0/admin/invites list invites failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: list invites")
+        }
+    }
+}
+
+/// Record a newly created capture in the audit log
+async fn audit_capture_create(
+    req: &HttpRequest,
+    state: &core::state::State,
+    user_id: i32,
+    uuid: &uuid::Uuid,
+    url: impl ToString,
+) {
+    let ip = get_client_ip(req, state);
+    let event = AuditEvent::new(AuditAction::CaptureCreate)
+        .actor(Some(user_id))
+        .ip(ip.as_deref())
+        .target(uuid)
+        .detail(url);
+    core::audit::record(state, event).await;
+}
+
+#[post("/0/capture/create")]
+async fn capture_create(
+    req: web::Json<clicor::CreateCaptureRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::CaptureCreate).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    let callback = match req.callback_url() {
+        Some(u) => match core::webhook::Callback::new(&state, u.clone()).await {
+            Some(c) => Some(c),
+            None => {
+                return HttpResponse::BadRequest()
+                    .json(clicor::CreateCaptureResponse::InvalidCallback);
+            }
+        },
+        None => None,
+    };
+
+    let result = core::act::create_capture(
+        req.url().clone(),
+        user_id,
+        req.public(),
+        req.group(),
+        callback.as_ref(),
+        core::queue::PRIORITY_INTERACTIVE,
+        &state,
+    )
+    .await;
+
+    match result {
+        Ok(uuid) => {
+            audit_capture_create(&full_req, &state, user_id, &uuid, req.url()).await;
+            HttpResponse::Accepted().json(clicor::CreateCaptureResponse::Initiated {
+                capture_id: uuid,
+                callback_secret: callback.map(|c| c.secret().to_string()),
+            })
+        }
+        Err(core::act::CreateCaptureError::NoAppropriateExtractorsError) => {
+            HttpResponse::BadRequest().json(clicor::CreateCaptureResponse::NoExtractors)
+        }
+        Err(core::act::CreateCaptureError::CaptureGroupForbiddenError) => {
+            HttpResponse::Forbidden().json(clicor::CreateCaptureResponse::GroupForbidden)
+        }
+        Err(e) => {
+            error!("Error in create_capture: {e}");
+            HttpResponse::InternalServerError().body("Internal server error")
+        }
+    }
+}
+
+/// Interpret a batch capture request body according to its content type
+fn parse_batch(
+    content_type: &str,
+    body: &[u8],
+    query: &BatchQuery,
+) -> Result<clicor::BatchCaptureRequest, String> {
+    let tags: Vec<String> = query
+        .tags
+        .split(',')
+        .filter(|t| !t.trim().is_empty())
+        .map(str::to_string)
+        .collect();
+    if content_type == "application/json" {
+        if let Ok(urls) = serde_json::from_slice::<Vec<String>>(body) {
+            return Ok(clicor::BatchCaptureRequest::new(urls, query.public, tags));
+        }
+        return serde_json::from_slice(body).map_err(|e| e.to_string());
+    }
+    let body = std::str::from_utf8(body).map_err(|e| e.to_string())?;
+    let urls = body
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect();
+    Ok(clicor::BatchCaptureRequest::new(urls, query.public, tags))
+}
+
+#[post("/0/capture/batch")]
+async fn capture_batch(
+    query: web::Query<BatchQuery>,
+    body: web::Bytes,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::BatchCaptureResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::CaptureCreate).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::BatchCaptureResponse::Unauthenticated);
+        }
+    };
+    let req = match parse_batch(full_req.content_type(), &body, &query) {
+        Ok(r) => r,
+        Err(reason) => {
+            return HttpResponse::BadRequest()
+                .json(clicor::BatchCaptureResponse::InvalidBatch { reason });
+        }
+    };
+
+    let result =
+        core::act::create_batch(req.urls(), user_id, req.public(), req.tags(), &state).await;
+    match result {
+        Ok((batch_id, results)) => {
+            for item in results.iter() {
+                if let clicor::BatchCaptureOutcome::Initiated { capture_id } = item.outcome() {
+                    audit_capture_create(&full_req, &state, user_id, capture_id, item.url()).await;
+                }
+            }
+            HttpResponse::Accepted()
+                .json(clicor::BatchCaptureResponse::Created { batch_id, results })
+        }
+        Err(
+            e @ (core::act::BatchError::EmptyBatchError
+            | core::act::BatchError::BatchTooLargeError { .. }
+            | core::act::BatchError::InvalidTagError),
+        ) => HttpResponse::BadRequest().json(clicor::BatchCaptureResponse::InvalidBatch {
+            reason: e.to_string(),
+        }),
+        Err(e) => {
+            error!("Error in create_batch: {e}");
+            HttpResponse::InternalServerError().body("Internal server error")
+        }
+    }
+}
+
+#[get("/0/capture/batch/{uuid}")]
+async fn capture_batch_status(
+    uuid: web::Path<uuid::Uuid>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::CaptureRead).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    match core::act::batch_status(&uuid, user_id, &state).await {
+        Ok(Some(progress)) => HttpResponse::Ok().json(progress),
+        Ok(None) => HttpResponse::NotFound().body("Not found"),
+        Err(e) => {
+            error!("Error in batch_status: {e}");
+            HttpResponse::InternalServerError().body("Internal server error")
+        }
+    }
+}
+
+#[post("/capture/create/form")]
+async fn capture_create_form(
+    form: CsrfForm<clicor::CreateCaptureRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let cookie = match get_cookie_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    let user_id = match state.user_from_token(cookie).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+
+    let url = form.url().clone();
+    let result = core::act::create_capture(
+        url,
+        user_id,
+        form.public(),
+        form.group(),
+        None,
+        core::queue::PRIORITY_INTERACTIVE,
+        &state,
+    )
+    .await;
+
+    match result {
+        Ok(uuid) => {
+            audit_capture_create(&full_req, &state, user_id, &uuid, form.url()).await;
+            let destination = format!("/capture/{uuid}/progress");
+            HttpResponse::SeeOther()
+                .insert_header(("Location", destination))
+                .finish()
+        }
+        Err(core::act::CreateCaptureError::NoAppropriateExtractorsError) => {
+            HttpResponse::BadRequest().body("No appropriate extractors for this URL")
+        }
+        Err(core::act::CreateCaptureError::CaptureGroupForbiddenError) => {
+            HttpResponse::Forbidden().body("Only contributors may file captures under a group")
+        }
+        Err(e) => {
+            error!("Error in create_capture: {e}");
+            HttpResponse::InternalServerError().body("Internal server error")
+        }
+    }
+}
+
+#[post("/0/capture/{uuid}/retry")]
+async fn capture_retry(
+    uuid: web::Path<uuid::Uuid>,
+    req: Option<web::Json<clicor::RetryCaptureRequest>>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::RetryCaptureResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::CaptureCreate).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::RetryCaptureResponse::Unauthenticated);
+        }
+    };
+    let req = req.map(|r| r.into_inner()).unwrap_or_default();
+    match core::act::retry_capture(&uuid, user_id, req.extractors(), &state).await {
+        Ok(extractors) => {
+            HttpResponse::Accepted().json(clicor::RetryCaptureResponse::Retrying { extractors })
+        }
+        Err(core::act::RetryCaptureError::NoSuchCaptureError) => {
+            HttpResponse::NotFound().json(clicor::RetryCaptureResponse::NoSuchCapture)
+        }
+        Err(core::act::RetryCaptureError::NothingToRetryError) => {
+            HttpResponse::Conflict().json(clicor::RetryCaptureResponse::NothingToRetry)
+        }
+        Err(core::act::RetryCaptureError::RetryForbiddenError) => {
+            HttpResponse::Forbidden().json(clicor::RetryCaptureResponse::Forbidden)
+        }
+        Err(e) => {
+            error!("Error in retry_capture: {e}");
+            HttpResponse::InternalServerError().body("Internal server error")
+        }
+    }
+}
+
+#[post("/capture/{uuid}/retry/form")]
+async fn capture_retry_form(
+    uuid: web::Path<uuid::Uuid>,
+    form: CsrfForm<RetryForm>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let cookie = match get_cookie_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    let user_id = match state.user_from_token(cookie).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    let extractors: Vec<String> = form.extractor.iter().cloned().collect();
+    match core::act::retry_capture(&uuid, user_id, &extractors, &state).await {
+        Ok(_) | Err(core::act::RetryCaptureError::NothingToRetryError) => {
+            let destination = format!("/capture/{uuid}/progress");
+            HttpResponse::SeeOther()
+                .insert_header(("Location", destination))
+                .finish()
+        }
+        Err(core::act::RetryCaptureError::NoSuchCaptureError) => {
+            HttpResponse::NotFound().body("Not found")
+        }
+        Err(e @ core::act::RetryCaptureError::RetryForbiddenError) => {
+            HttpResponse::Forbidden().body(e.to_string())
+        }
+        Err(e) => {
+            error!("Error in retry_capture: {e}");
+            HttpResponse::InternalServerError().body("Internal server error")
+        }
+    }
+}
+
+/// Respond to an attempt to create or update a schedule
+fn schedule_saved(
+    result: Result<clicor::ScheduleDescription, core::schedule::ScheduleError>,
+) -> HttpResponse {
+    use core::schedule::ScheduleError;
+    match result {
+        Ok(schedule) => HttpResponse::Ok().json(clicor::SaveScheduleResponse::Saved { schedule }),
+        Err(ScheduleError::InvalidScheduleError { reason }) => HttpResponse::BadRequest()
+            .json(clicor::SaveScheduleResponse::InvalidSchedule { reason }),
+        Err(ScheduleError::ScheduleNoExtractorsError) => {
+            HttpResponse::BadRequest().json(clicor::SaveScheduleResponse::NoExtractors)
+        }
+        Err(ScheduleError::NoSuchScheduleError) => {
+            HttpResponse::NotFound().json(clicor::SaveScheduleResponse::NoSuchSchedule)
+        }
+        Err(e) => {
+            error!("/0/schedules save schedule failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: save schedule")
+        }
+    }
+}
+
+#[post("/0/schedules")]
+async fn schedules_create(
+    req: web::Json<clicor::ScheduleRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::SaveScheduleResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::ScheduleWrite).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::SaveScheduleResponse::Unauthenticated);
+        }
+    };
+    let mut response = schedule_saved(core::schedule::create(&state, user_id, &req).await);
+    if response.status() == actix_web::http::StatusCode::OK {
+        *response.status_mut() = actix_web::http::StatusCode::CREATED;
+    }
+    response
+}
+
+#[get("/0/schedules")]
+async fn schedules_list(
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::CaptureRead).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    match core::schedule::list(&state, user_id).await {
+        Ok(s) => HttpResponse::Ok().json(s),
+        Err(e) => {
+            error!("/0/schedules list schedules failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: list schedules")
+        }
+    }
+}
+
+#[put("/0/schedules/{id}")]
+async fn schedules_update(
+    id: web::Path<i32>,
+    req: web::Json<clicor::ScheduleRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::SaveScheduleResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::ScheduleWrite).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::SaveScheduleResponse::Unauthenticated);
+        }
+    };
+    schedule_saved(core::schedule::update(&state, user_id, id.into_inner(), &req).await)
+}
+
+#[delete("/0/schedules/{id}")]
+async fn schedules_delete(
+    id: web::Path<i32>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::DeleteScheduleResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::ScheduleWrite).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::DeleteScheduleResponse::Unauthenticated);
+        }
+    };
+    match core::schedule::delete(&state, user_id, id.into_inner()).await {
+        Ok(true) => HttpResponse::Ok().json(clicor::DeleteScheduleResponse::Deleted),
+        Ok(false) => HttpResponse::NotFound().json(clicor::DeleteScheduleResponse::NoSuchSchedule),
+        Err(e) => {
+            error!("/0/schedules delete schedule failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: delete schedule")
+        }
+    }
+}
+
+#[post("/schedules/create/form")]
+async fn schedules_create_form(
+    form: CsrfForm<ScheduleForm>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let cookie = match get_cookie_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    let user_id = match state.user_from_token(cookie).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    let every = form.every.trim();
+    let req = match every.parse::<u32>() {
+        Ok(minutes) => clicor::ScheduleRequest::new(
+            form.url.clone(),
+            form.public,
+            None,
+            Some(minutes.saturating_mul(60)),
+            true,
+        ),
+        Err(_) => clicor::ScheduleRequest::new(
+            form.url.clone(),
+            form.public,
+            Some(every.to_string()),
+            None,
+            true,
+        ),
+    };
+    match core::schedule::create(&state, user_id, &req).await {
+        Ok(_) => HttpResponse::SeeOther()
+            .insert_header(("Location", "/dashboard"))
+            .finish(),
+        Err(core::schedule::ScheduleError::InvalidScheduleError { reason }) => {
+            HttpResponse::BadRequest().body(format!("Invalid schedule: {reason}"))
+        }
+        Err(core::schedule::ScheduleError::ScheduleNoExtractorsError) => {
+            HttpResponse::BadRequest().body("No appropriate extractors for this URL")
+        }
+        Err(e) => {
+            error!("/schedules/create/form create schedule failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: create schedule")
+        }
+    }
+}
+
+#[post("/schedules/{id}/delete/form")]
+async fn schedules_delete_form(
+    id: web::Path<i32>,
+    _form: CsrfForm<ScheduleDeleteForm>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let cookie = match get_cookie_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    let user_id = match state.user_from_token(cookie).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    match core::schedule::delete(&state, user_id, id.into_inner()).await {
+        Ok(_) => HttpResponse::SeeOther()
+            .insert_header(("Location", "/dashboard"))
+            .finish(),
+        Err(e) => {
+            error!("/schedules delete schedule failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: delete schedule")
+        }
+    }
+}
+
+async fn audit_group_membership(
+    req: &HttpRequest,
+    state: &core::state::State,
+    user_id: i32,
+    group_id: i32,
+    detail: String,
+) {
+    let ip = get_client_ip(req, state);
+    let event = AuditEvent::new(AuditAction::GroupMembership)
+        .actor(Some(user_id))
+        .ip(ip.as_deref())
+        .target(format!("group:{group_id}"))
+        .detail(detail);
+    core::audit::record(state, event).await;
+}
+
+#[post("/0/groups")]
+async fn groups_create(
+    req: web::Json<clicor::CreateGroupRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    use core::group::GroupError;
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::CreateGroupResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::GroupWrite).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::CreateGroupResponse::Unauthenticated);
+        }
+    };
+    match core::group::create(&state, user_id, req.name()).await {
+        Ok(group) => {
+            audit_group_membership(&full_req, &state, user_id, group.id(), "created".into()).await;
+            HttpResponse::Created().json(clicor::CreateGroupResponse::Created { group })
+        }
+        Err(GroupError::InvalidGroupNameError) => {
+            HttpResponse::BadRequest().json(clicor::CreateGroupResponse::InvalidName)
+        }
+        Err(GroupError::GroupNameTakenError) => {
+            HttpResponse::Conflict().json(clicor::CreateGroupResponse::NameTaken)
+        }
+        Err(e) => {
+            error!("/0/groups create group failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: create group")
+        }
+    }
+}
+
+#[get("/0/groups")]
+async fn groups_list(
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::CaptureRead).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    match core::group::list(&state, user_id).await {
+        Ok(g) => HttpResponse::Ok().json(g),
+        Err(e) => {
+            error!("/0/groups list groups failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: list groups")
+        }
+    }
+}
+
+#[delete("/0/groups/{id}")]
+async fn groups_delete(
+    id: web::Path<i32>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    use core::group::GroupError;
+    let group_id = id.into_inner();
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::DeleteGroupResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::GroupWrite).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::DeleteGroupResponse::Unauthenticated);
+        }
+    };
+    match core::group::delete(&state, user_id, group_id).await {
+        Ok(()) => {
+            audit_group_membership(&full_req, &state, user_id, group_id, "deleted".into()).await;
+            HttpResponse::Ok().json(clicor::DeleteGroupResponse::Deleted)
+        }
+        Err(GroupError::NoSuchGroupError) => {
+            HttpResponse::NotFound().json(clicor::DeleteGroupResponse::NoSuchGroup)
+        }
+        Err(GroupError::GroupForbiddenError) => {
+            HttpResponse::Forbidden().json(clicor::DeleteGroupResponse::Forbidden)
+        }
+        Err(e) => {
+            error!("/0/groups delete group failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: delete group")
+        }
+    }
+}
+
+#[get("/0/groups/{id}/members")]
+async fn groups_members(
+    id: web::Path<i32>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    use core::group::GroupError;
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::GroupMemberResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::CaptureRead).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::GroupMemberResponse::Unauthenticated);
+        }
+    };
+    match core::group::members(&state, user_id, id.into_inner()).await {
+        Ok(m) => HttpResponse::Ok().json(m),
+        Err(GroupError::NoSuchGroupError) => {
+            HttpResponse::NotFound().json(clicor::GroupMemberResponse::NoSuchGroup)
+        }
+        Err(e) => {
+            error!("/0/groups list members failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: list members")
+        }
+    }
+}
+
+/// Map the outcome of a change to a group's members onto a response
+fn group_member_changed(
+    result: Result<(), core::group::GroupError>,
+    done: clicor::GroupMemberResponse,
+) -> HttpResponse {
+    use core::group::GroupError;
+    match result {
+        Ok(()) => HttpResponse::Ok().json(done),
+        Err(GroupError::NoSuchGroupError) => {
+            HttpResponse::NotFound().json(clicor::GroupMemberResponse::NoSuchGroup)
+        }
+        Err(GroupError::GroupNoSuchUserError) => {
+            HttpResponse::NotFound().json(clicor::GroupMemberResponse::NoSuchUser)
+        }
+        Err(GroupError::NoSuchMemberError) => {
+            HttpResponse::NotFound().json(clicor::GroupMemberResponse::NoSuchMember)
+        }
+        Err(GroupError::GroupForbiddenError) => {
+            HttpResponse::Forbidden().json(clicor::GroupMemberResponse::Forbidden)
+        }
+        Err(GroupError::LastAdminError) => {
+            HttpResponse::Conflict().json(clicor::GroupMemberResponse::LastAdmin)
+        }
+        Err(e) => {
+            error!("/0/groups change members failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: change members")
+        }
+    }
+}
+
+#[put("/0/groups/{id}/members/{username}")]
+async fn groups_member_set(
+    path: web::Path<(i32, String)>,
+    req: web::Json<clicor::SetMemberRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let (group_id, username) = path.into_inner();
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::GroupMemberResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::GroupWrite).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::GroupMemberResponse::Unauthenticated);
+        }
+    };
+    let result = core::group::set_member(&state, user_id, group_id, &username, req.role()).await;
+    if result.is_ok() {
+        let detail = format!("{username}:{}", req.role().as_str());
+        audit_group_membership(&full_req, &state, user_id, group_id, detail).await;
+    }
+    group_member_changed(result, clicor::GroupMemberResponse::Saved)
+}
+
+#[delete("/0/groups/{id}/members/{username}")]
+async fn groups_member_remove(
+    path: web::Path<(i32, String)>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let (group_id, username) = path.into_inner();
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::GroupMemberResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::GroupWrite).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::GroupMemberResponse::Unauthenticated);
+        }
+    };
+    let result = core::group::remove_member(&state, user_id, group_id, &username).await;
+    if result.is_ok() {
+        let detail = format!("{username}:removed");
+        audit_group_membership(&full_req, &state, user_id, group_id, detail).await;
+    }
+    group_member_changed(result, clicor::GroupMemberResponse::Removed)
+}
+
+#[post("/groups/create/form")]
+async fn groups_create_form(
+    form: CsrfForm<clicor::CreateGroupRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let cookie = match get_cookie_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    let user_id = match state.user_from_token(cookie).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    match core::group::create(&state, user_id, form.name()).await {
+        Ok(group) => {
+            audit_group_membership(&full_req, &state, user_id, group.id(), "created".into()).await;
+        }
+        Err(
+            e @ (core::group::GroupError::InvalidGroupNameError
+            | core::group::GroupError::GroupNameTakenError),
+        ) => {
+            return HttpResponse::BadRequest().body(e.to_string());
+        }
+        Err(e) => {
+            error!("/groups create group failed: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error: create group");
+        }
+    }
+    HttpResponse::SeeOther()
+        .insert_header(("Location", "/dashboard"))
+        .finish()
+}
+
+#[post("/groups/{id}/members/form")]
+async fn groups_member_form(
+    id: web::Path<i32>,
+    form: CsrfForm<GroupMemberForm>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let group_id = id.into_inner();
+    let cookie = match get_cookie_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    let user_id = match state.user_from_token(cookie).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    let result =
+        core::group::set_member(&state, user_id, group_id, &form.username, form.role).await;
+    match result {
+        Ok(()) => {
+            let detail = format!("{}:{}", form.username, form.role.as_str());
+            audit_group_membership(&full_req, &state, user_id, group_id, detail).await;
+            HttpResponse::SeeOther()
+                .insert_header(("Location", "/dashboard"))
+                .finish()
+        }
+        Err(
+            e @ (core::group::GroupError::GroupNoSuchUserError
+            | core::group::GroupError::LastAdminError),
+        ) => HttpResponse::BadRequest().body(e.to_string()),
+        Err(
+            e @ (core::group::GroupError::NoSuchGroupError
+            | core::group::GroupError::GroupForbiddenError),
+        ) => HttpResponse::Forbidden().body(e.to_string()),
+        Err(e) => {
+            error!("/groups add member failed: {e}");
+            HttpResponse::InternalServerError().body("Internal server error: add member")
+        }
+    }
+}
+
+#[get("/0/timeline")]
+async fn timeline(
+    query: web::Query<clicor::TimelineRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::TimelineResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::CaptureRead).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::TimelineResponse::Unauthenticated);
+        }
+    };
+    match core::timeline::timeline(&state, query.url(), user_id).await {
+        Ok(captures) => HttpResponse::Ok().json(clicor::TimelineResponse::Timeline {
+            url: core::timeline::normalize(query.url()),
+            captures,
+        }),
+        Err(e) => {
+            error!("Error in timeline: {e}");
+            HttpResponse::InternalServerError().body("Internal server error")
+        }
+    }
+}
+
+#[get("/timeline")]
+async fn timeline_page(
+    query: web::Query<clicor::TimelineRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let cookie = match get_cookie_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    let user_id = match state.user_from_token(cookie).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    let captures = match core::timeline::timeline(&state, query.url(), user_id).await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Error in timeline: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error");
+        }
+    };
+    let mut context = Context::new();
+    context.insert("url", core::timeline::normalize(query.url()).as_str());
+    context.insert("years", &core::timeline::calendar(&captures));
+    context.insert("captures", &captures);
+    render("timeline.html", context, &full_req, &state)
+}
+
+async fn audit_capture_delete(
+    req: &HttpRequest,
+    state: &core::state::State,
+    user_id: i32,
+    uuid: &uuid::Uuid,
+    detail: &str,
+) {
+    let ip = get_client_ip(req, state);
+    let event = AuditEvent::new(AuditAction::CaptureDelete)
+        .actor(Some(user_id))
+        .ip(ip.as_deref())
+        .target(uuid)
+        .detail(detail);
+    core::audit::record(state, event).await;
+}
+
+#[delete("/0/capture/{uuid}")]
+async fn capture_delete(
+    uuid: web::Path<uuid::Uuid>,
+    query: web::Query<DeleteQuery>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    use core::trash::{Deletion, TrashError};
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::DeleteCaptureResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::CaptureDelete).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::DeleteCaptureResponse::Unauthenticated);
+        }
+    };
+    match core::trash::delete(&state, &uuid, user_id, query.permanent).await {
+        Ok(Deletion::Trashed(time_purge)) => {
+            audit_capture_delete(&full_req, &state, user_id, &uuid, "trashed").await;
+            HttpResponse::Ok().json(clicor::DeleteCaptureResponse::Trashed { time_purge })
+        }
+        Ok(Deletion::Purged) => {
+            audit_capture_delete(&full_req, &state, user_id, &uuid, "purged").await;
+            HttpResponse::Ok().json(clicor::DeleteCaptureResponse::Deleted)
+        }
+        Err(TrashError::TrashNoSuchCaptureError) => {
+            HttpResponse::NotFound().json(clicor::DeleteCaptureResponse::NoSuchCapture)
+        }
+        Err(TrashError::TrashForbiddenError) => {
+            HttpResponse::Forbidden().json(clicor::DeleteCaptureResponse::Forbidden)
+        }
+        Err(TrashError::CaptureInProgressError) => {
+            HttpResponse::Conflict().json(clicor::DeleteCaptureResponse::CaptureInProgress)
+        }
+        Err(e) => {
+            error!("Error deleting capture {uuid}: {e}");
+            HttpResponse::InternalServerError().body("Internal server error")
+        }
+    }
+}
+
+#[patch("/0/capture/{uuid}")]
+async fn capture_update(
+    uuid: web::Path<uuid::Uuid>,
+    req: web::Json<clicor::UpdateCaptureRequest>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    use core::act::UpdateCaptureError;
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::UpdateCaptureResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::CaptureManage).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::UpdateCaptureResponse::Unauthenticated);
+        }
+    };
+    match core::act::update_capture(&uuid, user_id, &req, &state).await {
+        Ok(update) => {
+            let ip = get_client_ip(&full_req, &state);
+            for change in update.access_changes.iter() {
+                let event = AuditEvent::new(AuditAction::CaptureVisibility)
+                    .actor(Some(user_id))
+                    .ip(ip.as_deref())
+                    .target(*uuid)
+                    .detail(change);
+                core::audit::record(&state, event).await;
+            }
+            HttpResponse::Ok().json(clicor::UpdateCaptureResponse::Updated {
+                capture: update.capture,
+            })
+        }
+        Err(UpdateCaptureError::UpdateNoSuchCaptureError) => {
+            HttpResponse::NotFound().json(clicor::UpdateCaptureResponse::NoSuchCapture)
+        }
+        Err(UpdateCaptureError::UpdateForbiddenError) => {
+            HttpResponse::Forbidden().json(clicor::UpdateCaptureResponse::Forbidden)
+        }
+        Err(UpdateCaptureError::UpdateGroupForbiddenError) => {
+            HttpResponse::Forbidden().json(clicor::UpdateCaptureResponse::GroupForbidden)
+        }
+        Err(UpdateCaptureError::NoSuchOwnerError) => {
+            HttpResponse::UnprocessableEntity().json(clicor::UpdateCaptureResponse::NoSuchUser)
+        }
+        Err(UpdateCaptureError::InvalidUpdateError { reason }) => {
+            HttpResponse::BadRequest().json(clicor::UpdateCaptureResponse::InvalidUpdate { reason })
+        }
+        Err(e) => {
+            error!("Error updating capture {uuid}: {e}");
+            HttpResponse::InternalServerError().body("Internal server error")
+        }
+    }
+}
+
+#[post("/0/capture/{uuid}/shares")]
+async fn capture_share_create(
+    uuid: web::Path<uuid::Uuid>,
+    req: Option<web::Json<clicor::CreateShareRequest>>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    use core::share::ShareError;
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::CreateShareResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::CaptureShare).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::CreateShareResponse::Unauthenticated);
+        }
+    };
+    let req = req.map(|r| r.into_inner()).unwrap_or_default();
+    match core::share::create(&state, &uuid, user_id, &req).await {
+        Ok(share) => {
+            let ip = get_client_ip(&full_req, &state);
+            let event = AuditEvent::new(AuditAction::CaptureVisibility)
+                .actor(Some(user_id))
+                .ip(ip.as_deref())
+                .target(*uuid)
+                .detail(format!("share:{}", share.id));
+            core::audit::record(&state, event).await;
+            HttpResponse::Created().json(clicor::CreateShareResponse::Created {
+                id: share.id,
+                token: share.token,
+                location: share.location,
+            })
+        }
+        Err(ShareError::ShareNoSuchCaptureError) => {
+            HttpResponse::NotFound().json(clicor::CreateShareResponse::NoSuchCapture)
+        }
+        Err(ShareError::ShareForbiddenError) => {
+            HttpResponse::Forbidden().json(clicor::CreateShareResponse::Forbidden)
+        }
+        Err(ShareError::ShareNoSuchExtractorError) => {
+            HttpResponse::BadRequest().json(clicor::CreateShareResponse::NoSuchExtractor)
+        }
+        Err(ShareError::AlreadyExpiredError) => {
+            HttpResponse::BadRequest().json(clicor::CreateShareResponse::AlreadyExpired)
+        }
+        Err(e) => {
+            error!("Error sharing capture {uuid}: {e}");
+            HttpResponse::InternalServerError().body("Internal server error")
+        }
+    }
+}
+
+#[get("/0/capture/{uuid}/shares")]
+async fn capture_share_list(
+    uuid: web::Path<uuid::Uuid>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    use core::share::ShareError;
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    let user_id = match state.authenticate(&bearer, Scope::CaptureRead).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::CreateCaptureResponse::Unauthenticated);
+        }
+    };
+    match core::share::list(&state, &uuid, user_id).await {
+        Ok(shares) => HttpResponse::Ok().json(shares),
+        Err(ShareError::ShareNoSuchCaptureError) => HttpResponse::NotFound().body("Not found"),
+        Err(ShareError::ShareForbiddenError) => HttpResponse::Forbidden().body("Forbidden"),
+        Err(e) => {
+            error!("Error listing shares of {uuid}: {e}");
+            HttpResponse::InternalServerError().body("Internal server error")
+        }
+    }
+}
+
+#[delete("/0/capture/{uuid}/shares/{id}")]
+async fn capture_share_revoke(
+    pair: web::Path<(uuid::Uuid, i32)>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    use core::share::ShareError;
+    let (uuid, share_id) = pair.into_inner();
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::RevokeShareResponse::Unauthenticated);
         }
     };
-
-    let user: Result<Vec<DbUser>, _> = users::dsl::users
-        .filter(users::dsl::username.eq(req.username()))
-        .load(&mut conn)
-        .await;
-    let user = match user {
-        Ok(mut v) => match v.len() {
-            0 => {
-                return HttpResponse::Unauthorized().json(clicor::AuthResponse::InvalidCredentials);
-            }
-            1 => v.remove(0),
-            _ => {
-                error!("/auth multiple users with same username");
-                return HttpResponse::InternalServerError().body("Internal server error: get user");
-            }
-        },
-        Err(e) => {
-            error!("/auth get user failed: {e}");
-            return HttpResponse::InternalServerError().body("Internal server error: get user");
+    let user_id = match state.authenticate(&bearer, Scope::CaptureShare).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized().json(clicor::RevokeShareResponse::Unauthenticated);
         }
     };
-    match bcrypt::verify(req.password(), &user.passhash) {
-        Ok(true) => {}
-        Ok(false) => {
-            return HttpResponse::Unauthorized().json(clicor::AuthResponse::InvalidCredentials);
+    match core::share::revoke(&state, &uuid, user_id, share_id).await {
+        Ok(true) => {
+            let ip = get_client_ip(&full_req, &state);
+            let event = AuditEvent::new(AuditAction::CaptureVisibility)
+                .actor(Some(user_id))
+                .ip(ip.as_deref())
+                .target(uuid)
+                .detail(format!("unshare:{share_id}"));
+            core::audit::record(&state, event).await;
+            HttpResponse::Ok().json(clicor::RevokeShareResponse::Revoked)
+        }
+        Ok(false) => HttpResponse::NotFound().json(clicor::RevokeShareResponse::NoSuchShare),
+        Err(ShareError::ShareNoSuchCaptureError) => {
+            HttpResponse::NotFound().json(clicor::RevokeShareResponse::NoSuchCapture)
+        }
+        Err(ShareError::ShareForbiddenError) => {
+            HttpResponse::Forbidden().json(clicor::RevokeShareResponse::Forbidden)
         }
         Err(e) => {
-            error!("/auth verify hash failed: {e}");
-            return HttpResponse::InternalServerError().body("Internal server error: verify user");
+            error!("Error revoking share {share_id} of {uuid}: {e}");
+            HttpResponse::InternalServerError().body("Internal server error")
         }
-    };
-    let new_token = rand::random::<u128>();
-    state.register_token(new_token, user.id).await;
-
-    HttpResponse::Ok().json(clicor::AuthResponse::Authenticated {
-        token: new_token.to_string(),
-    })
+    }
 }
 
-#[post("/auth/form")]
-async fn auth_form(
-    form: web::Form<AuthForm>,
+#[post("/0/capture/{uuid}/restore")]
+async fn capture_restore(
+    uuid: web::Path<uuid::Uuid>,
+    full_req: HttpRequest,
     state: web::Data<core::state::State>,
 ) -> impl Responder {
-    use core::schema::users;
-    if form.username.len() == 0 || form.password.len() == 0 {
-        return HttpResponse::BadRequest().json(clicor::AuthResponse::UnacceptableCredentials);
-    }
-    let mut conn = match state.db_pool().await.get().await {
-        Ok(c) => c,
-        Err(e) => {
-            error!("db_pool.get() failed: {e}");
-            return HttpResponse::InternalServerError().body("Internal server error: db pool");
+    use core::trash::TrashError;
+    let bearer = match get_bearer_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::RestoreCaptureResponse::Unauthenticated);
         }
     };
-
-    let user: Result<Vec<DbUser>, _> = users::dsl::users
-        .filter(users::dsl::username.eq(&form.username))
-        .load(&mut conn)
-        .await;
-    let user = match user {
-        Ok(mut v) => match v.len() {
-            0 => {
-                return HttpResponse::Unauthorized().json(clicor::AuthResponse::InvalidCredentials);
-            }
-            1 => v.remove(0),
-            _ => {
-                error!("/auth multiple users with same username");
-                return HttpResponse::InternalServerError().body("Internal server error: get user");
-            }
-        },
-        Err(e) => {
-            error!("/auth get user failed: {e}");
-            return HttpResponse::InternalServerError().body("Internal server error: get user");
+    let user_id = match state.authenticate(&bearer, Scope::CaptureDelete).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(clicor::RestoreCaptureResponse::Unauthenticated);
         }
     };
-    match bcrypt::verify(&form.password, &user.passhash) {
-        Ok(true) => {}
-        Ok(false) => {
-            return HttpResponse::Unauthorized().json(clicor::AuthResponse::InvalidCredentials);
+    match core::trash::restore(&state, &uuid, user_id).await {
+        Ok(()) => {
+            audit_capture_delete(&full_req, &state, user_id, &uuid, "restored").await;
+            HttpResponse::Ok().json(clicor::RestoreCaptureResponse::Restored)
+        }
+        Err(TrashError::TrashNoSuchCaptureError) => {
+            HttpResponse::NotFound().json(clicor::RestoreCaptureResponse::NoSuchCapture)
+        }
+        Err(TrashError::TrashForbiddenError) => {
+            HttpResponse::Forbidden().json(clicor::RestoreCaptureResponse::Forbidden)
+        }
+        Err(TrashError::NotTrashedError) => {
+            HttpResponse::Conflict().json(clicor::RestoreCaptureResponse::NotTrashed)
         }
         Err(e) => {
-            error!("/auth verify hash failed: {e}");
-            return HttpResponse::InternalServerError().body("Internal server error: verify user");
+            error!("Error restoring capture {uuid}: {e}");
+            HttpResponse::InternalServerError().body("Internal server error")
         }
-    };
-    let new_token = rand::random::<u128>();
-    state.register_token(new_token, user.id).await;
-    let mut cookie = cookie::Cookie::new("auth", new_token.to_string());
-    cookie.set_path("/");
-    HttpResponse::SeeOther()
-        .cookie(cookie)
-        .insert_header(("Location", "/dashboard"))
-        .finish()
+    }
 }
 
-#[post("/0/capture/create")]
-async fn capture_create(
-    req: web::Json<clicor::CreateCaptureRequest>,
+#[get("/0/capture/trash")]
+async fn capture_trash(
     full_req: HttpRequest,
     state: web::Data<core::state::State>,
 ) -> impl Responder {
@@ -308,32 +2783,26 @@ async fn capture_create(
                 .json(clicor::CreateCaptureResponse::Unauthenticated);
         }
     };
-    let user_id = match state.user_from_token(bearer).await {
+    let user_id = match state.authenticate(&bearer, Scope::CaptureRead).await {
         Some(u) => u,
         None => {
             return HttpResponse::Unauthorized()
                 .json(clicor::CreateCaptureResponse::Unauthenticated);
         }
     };
-
-    let result = core::act::create_capture(req.url().clone(), user_id, req.public(), state).await;
-
-    match result {
-        Ok(uuid) => HttpResponse::Accepted()
-            .json(clicor::CreateCaptureResponse::Initiated { capture_id: uuid }),
-        Err(core::act::CreateCaptureError::NoAppropriateExtractorsError) => {
-            HttpResponse::BadRequest().json(clicor::CreateCaptureResponse::NoExtractors)
-        }
+    match core::trash::list(&state, user_id).await {
+        Ok(t) => HttpResponse::Ok().json(t),
         Err(e) => {
-            error!("Error in create_capture: {e}");
+            error!("Error listing trash: {e}");
             HttpResponse::InternalServerError().body("Internal server error")
         }
     }
 }
 
-#[post("/capture/create/form")]
-async fn capture_create_form(
-    form: web::Form<clicor::CreateCaptureRequest>,
+#[post("/capture/{uuid}/delete/form")]
+async fn capture_delete_form(
+    uuid: web::Path<uuid::Uuid>,
+    _form: CsrfForm<CaptureForm>,
     full_req: HttpRequest,
     state: web::Data<core::state::State>,
 ) -> impl Responder {
@@ -353,22 +2822,110 @@ async fn capture_create_form(
                 .finish();
         }
     };
+    let detail = match core::trash::delete(&state, &uuid, user_id, false).await {
+        Ok(core::trash::Deletion::Trashed(_)) => "trashed",
+        Ok(core::trash::Deletion::Purged) => "purged",
+        Err(core::trash::TrashError::CaptureInProgressError) => {
+            return HttpResponse::Conflict().body("Capture is still in progress");
+        }
+        Err(core::trash::TrashError::TrashNoSuchCaptureError) => {
+            return HttpResponse::NotFound().body("Not found");
+        }
+        Err(core::trash::TrashError::TrashForbiddenError) => {
+            return HttpResponse::Forbidden().body("Forbidden");
+        }
+        Err(e) => {
+            error!("Error deleting capture {uuid}: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error");
+        }
+    };
+    audit_capture_delete(&full_req, &state, user_id, &uuid, detail).await;
+    HttpResponse::SeeOther()
+        .insert_header(("Location", "/dashboard"))
+        .finish()
+}
 
-    let url = form.url().clone();
-    let result = core::act::create_capture(url, user_id, form.public(), state).await;
+#[post("/capture/{uuid}/restore/form")]
+async fn capture_restore_form(
+    uuid: web::Path<uuid::Uuid>,
+    _form: CsrfForm<CaptureForm>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let cookie = match get_cookie_token(&full_req) {
+        Some(t) => t,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    let user_id = match state.user_from_token(cookie).await {
+        Some(u) => u,
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish();
+        }
+    };
+    match core::trash::restore(&state, &uuid, user_id).await {
+        Ok(()) => audit_capture_delete(&full_req, &state, user_id, &uuid, "restored").await,
+        Err(core::trash::TrashError::NotTrashedError) => {}
+        Err(core::trash::TrashError::TrashNoSuchCaptureError) => {
+            return HttpResponse::NotFound().body("Not found");
+        }
+        Err(core::trash::TrashError::TrashForbiddenError) => {
+            return HttpResponse::Forbidden().body("Forbidden");
+        }
+        Err(e) => {
+            error!("Error restoring capture {uuid}: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error");
+        }
+    }
+    HttpResponse::SeeOther()
+        .insert_header(("Location", "/dashboard"))
+        .finish()
+}
 
-    match result {
-        Ok(uuid) => {
-            let destination = format!("/capture/{uuid}/progress");
-            HttpResponse::SeeOther()
-                .insert_header(("Location", destination))
-                .finish()
+#[get("/capture/{uuid}")]
+async fn capture_landing(
+    uuid: web::Path<uuid::Uuid>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let viewer = match get_cookie_token(&full_req) {
+        Some(t) => state.user_from_token(t).await,
+        None => None,
+    };
+    let (capture, extracts) = match core::public::landing(&state, &uuid, viewer).await {
+        Ok(Some(l)) => l,
+        Ok(None) => return HttpResponse::NotFound().body("Not found"),
+        Err(e) => {
+            error!("Error loading capture {uuid}: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error");
         }
-        Err(core::act::CreateCaptureError::NoAppropriateExtractorsError) => {
-            HttpResponse::BadRequest().body("No appropriate extractors for this URL")
+    };
+    let mut context = Context::new();
+    context.insert("capture", &capture);
+    context.insert("extracts", &extracts);
+    context.insert("signed_in", &viewer.is_some());
+    render("landing.html", context, &full_req, &state)
+}
+
+#[get("/browse")]
+async fn browse(
+    query: web::Query<BrowseQuery>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    match core::public::browse(&state, query.page).await {
+        Ok(page) => {
+            let mut context = Context::new();
+            context.insert("listing", &page);
+            render("browse.html", context, &full_req, &state)
         }
         Err(e) => {
-            error!("Error in create_capture: {e}");
+            error!("Error browsing public captures: {e}");
             HttpResponse::InternalServerError().body("Internal server error")
         }
     }
@@ -387,20 +2944,31 @@ async fn capture_status(
                 .json(clicor::CreateCaptureResponse::Unauthenticated);
         }
     };
-    let user_id = match state.user_from_token(bearer).await {
+    let user_id = match state.authenticate(&bearer, Scope::CaptureRead).await {
         Some(u) => u,
         None => {
             return HttpResponse::Unauthorized()
                 .json(clicor::CreateCaptureResponse::Unauthenticated);
         }
     };
-    let status = match state.capture_map().await.get_status(&uuid).await {
-        Some(a) => a,
-        None => {
+    let status = match core::act::capture_status(&uuid, &state).await {
+        Ok(Some(a)) => a,
+        Ok(None) => {
             return HttpResponse::NotFound().body("Not found");
         }
+        Err(e) => {
+            error!("Error in capture_status: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error");
+        }
+    };
+    let groups = match core::group::memberships(&state, user_id).await {
+        Ok(g) => g,
+        Err(e) => {
+            error!("Error loading group memberships: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error");
+        }
     };
-    if status.allows_user(user_id) {
+    if status.allows_user(user_id, &groups) {
         HttpResponse::Ok().json(status.get_progress())
     } else {
         HttpResponse::Unauthorized().body("Unauthorized")
@@ -413,7 +2981,6 @@ async fn capture_progress(
     full_req: HttpRequest,
     state: web::Data<core::state::State>,
 ) -> impl Responder {
-    use core::schema::{captures, extracts};
     let bearer = match get_cookie_token(&full_req) {
         Some(t) => t,
         None => {
@@ -430,90 +2997,144 @@ async fn capture_progress(
                 .finish();
         }
     };
-    let status = match state.capture_map().await.get_status(&uuid).await {
-        Some(a) => a,
-        None => {
+    let status = match core::act::capture_status(&uuid, &state).await {
+        Ok(Some(a)) => a,
+        Ok(None) => {
             return HttpResponse::NotFound().body("Not found");
         }
-    };
-    let progress = if status.allows_user(user_id) {
-        status.get_progress()
-    } else {
-        return HttpResponse::Unauthorized().body("Unauthorized");
-    };
-    let mut context = Context::new();
-    /*
-    let mut conn = match state.db_pool().await.get().await {
-        Ok(c) => c,
         Err(e) => {
-            error!("db_pool.get() failed: {e}");
-            return HttpResponse::InternalServerError().body("Internal server error: db pool");
+            error!("Error in capture_status: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error");
         }
     };
-    let uuid: uuid::Uuid = uuid.into_inner();
-    let capture: Result<DbCapture, _> = captures::dsl::captures
-        .filter(captures::dsl::uuid.eq(uuid))
-        .get_result(&mut conn)
-        .await;
-    let capture = match capture {
-        Ok(c) => c,
+    let groups = match core::group::memberships(&state, user_id).await {
+        Ok(g) => g,
         Err(e) => {
-            debug!("Capture {uuid} does not exist");
-            return HttpResponse::NotFound().body("Capture does not exist");
+            error!("Error loading group memberships: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error");
         }
     };
-    let extracts: Result<Vec<DbExtract>, _> = extracts::dsl::extracts
-        .filter(extracts::dsl::capture.eq(capture.id))
-        .order(extracts::dsl::extractor.asc())
-        .load(&mut conn)
-        .await;
-    let extracts = match extracts {
-        Ok(c) => c,
-        Err(e) => {
-            error!("capture progress failed: {e}");
-            return HttpResponse::InternalServerError().body("Internal server error: query");
-        }
+    let progress = if status.allows_user(user_id, &groups) {
+        status.get_progress()
+    } else {
+        return HttpResponse::Unauthorized().body("Unauthorized");
     };
-    let extracts: Vec<(String, String)> = extracts
-        .into_iter()
-        .map(|e| (e.extractor, e.success.to_string()))
-        .collect();
-    error!("{:#?}", extracts);
-    */
+    let mut context = Context::new();
     context.insert("in_progress", &progress.in_progress());
     context.insert("completed", &progress.completed());
     context.insert("failed", &progress.failed());
-    let document = TEMPLATES.render("capture.html", &context);
-    match document {
-        Ok(d) => HttpResponse::Ok().body(d),
-        Err(e) => {
-            error!("Error rendering capture.html: {e}");
-            HttpResponse::InternalServerError().body("Error rendering capture.html")
-        }
-    }
+    context.insert("extracts", progress.extracts());
+    render("capture.html", context, &full_req, &state)
 }
 
-#[get("/resource/{uuid}/{tail:.*}")]
-async fn resource(
-    pair: web::Path<(uuid::Uuid, std::path::PathBuf)>,
+/// Format a server-sent event carrying a JSON payload
+fn sse_event<T: serde::Serialize>(name: &str, data: &T) -> web::Bytes {
+    let data = serde_json::to_string(data).unwrap_or_default();
+    web::Bytes::from(format!("event: {name}\ndata: {data}\n\n"))
+}
+
+/// Interval between comments sent to keep idle event streams open
+const SSE_KEEPALIVE: std::time::Duration = std::time::Duration::from_secs(15);
+
+#[get("/capture/{uuid}/events")]
+async fn capture_events(
+    uuid: web::Path<uuid::Uuid>,
     full_req: HttpRequest,
     state: web::Data<core::state::State>,
 ) -> impl Responder {
-    let (uuid, tail) = pair.into_inner();
-    let bearer = match get_token(&full_req) {
+    let token = match get_token(&full_req) {
         Some(t) => t,
         None => {
             return HttpResponse::Unauthorized()
                 .json(clicor::CreateCaptureResponse::Unauthenticated);
         }
     };
-    let user_id = match state.user_from_token(bearer).await {
+    let user_id = match state.authenticate(&token, Scope::CaptureRead).await {
         Some(u) => u,
         None => {
             return HttpResponse::Unauthorized()
                 .json(clicor::CreateCaptureResponse::Unauthenticated);
         }
     };
+
+    // Subscribe before taking the snapshot so that no transition falls between them
+    let mut receiver = state.capture_map().await.subscribe(&uuid).await;
+    let status = match core::act::capture_status(&uuid, &state).await {
+        Ok(Some(a)) => a,
+        Ok(None) => {
+            return HttpResponse::NotFound().body("Not found");
+        }
+        Err(e) => {
+            error!("Error in capture_status: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error");
+        }
+    };
+    let groups = match core::group::memberships(&state, user_id).await {
+        Ok(g) => g,
+        Err(e) => {
+            error!("Error loading group memberships: {e}");
+            return HttpResponse::InternalServerError().body("Internal server error");
+        }
+    };
+    if !status.allows_user(user_id, &groups) {
+        return HttpResponse::Unauthorized().body("Unauthorized");
+    }
+    let progress = status.get_progress();
+    if progress.in_progress() == 0 {
+        receiver = None;
+    }
+    let stream = async_stream::stream! {
+        yield Ok::<_, actix_web::Error>(sse_event("progress", &progress));
+        let Some(mut receiver) = receiver else {
+            let finished = clicor::CaptureEvent::CaptureFinished {
+                completed: progress.completed(),
+                failed: progress.failed(),
+            };
+            yield Ok(sse_event(finished.name(), &finished));
+            return;
+        };
+        let mut keepalive = tokio::time::interval(SSE_KEEPALIVE);
+        keepalive.tick().await;
+        loop {
+            tokio::select! {
+                event = receiver.recv() => match event {
+                    Ok(event) => yield Ok(sse_event(event.name(), &event)),
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        debug!("Event stream for {uuid} skipped {n} events");
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+                _ = keepalive.tick() => yield Ok(web::Bytes::from_static(b": keepalive\n\n")),
+            }
+        }
+    };
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream)
+}
+
+#[get("/resource/{uuid}/{tail:.*}")]
+async fn resource(
+    pair: web::Path<(uuid::Uuid, std::path::PathBuf)>,
+    query: web::Query<ShareQuery>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let (uuid, tail) = pair.into_inner();
+    if let Some(token) = &query.share {
+        return match core::share::resolve(&state, token).await {
+            Some(share) if share.capture_uuid == uuid => {
+                shared_resource(share, tail, &full_req, &state).await
+            }
+            _ => HttpResponse::Unauthorized().body("Invalid or expired share"),
+        };
+    }
+    // Public captures are open to anyone, so a missing or invalid token only matters later
+    let user_id = match get_token(&full_req) {
+        Some(t) => state.authenticate(&t, Scope::ResourceRead).await,
+        None => None,
+    };
     let mut conn = match state.db_pool().await.get().await {
         Ok(c) => c,
         Err(e) => {
@@ -523,6 +3144,7 @@ async fn resource(
     };
     let capture: Result<core::models::DbCapture, _> = schema::captures::table
         .filter(schema::captures::uuid.eq(&uuid))
+        .filter(schema::captures::time_deleted.is_null())
         .get_result(&mut conn)
         .await;
     let capture = match capture {
@@ -535,8 +3157,112 @@ async fn resource(
             return HttpResponse::InternalServerError().body("Internal server error");
         }
     };
-    if (!capture.public) && (capture.owner != user_id) {
-        return HttpResponse::Unauthorized().body("Not authorized to view capture");
+    if !capture.public {
+        let ip = get_client_ip(&full_req, &state);
+        let event = AuditEvent::new(AuditAction::ResourceAccess)
+            .actor(user_id)
+            .ip(ip.as_deref())
+            .target(format!("{uuid}/{}", tail.display()));
+        let allowed = match core::group::can_view(&state, &capture, user_id).await {
+            Ok(a) => a,
+            Err(e) => {
+                error!("Error checking access to /capture/{uuid}: {e}");
+                return HttpResponse::InternalServerError().body("Internal server error");
+            }
+        };
+        if !allowed {
+            core::audit::record(&state, event.failed()).await;
+            return HttpResponse::Unauthorized().body("Not authorized to view capture");
+        }
+        core::audit::record(&state, event).await;
+    }
+    serve_resource(uuid, tail, &full_req, &state).await
+}
+
+#[get("/share/{token}/{tail:.*}")]
+async fn share_resource(
+    pair: web::Path<(String, std::path::PathBuf)>,
+    full_req: HttpRequest,
+    state: web::Data<core::state::State>,
+) -> impl Responder {
+    let (token, tail) = pair.into_inner();
+    match core::share::resolve(&state, &token).await {
+        Some(share) => shared_resource(share, tail, &full_req, &state).await,
+        None => HttpResponse::NotFound().body("Invalid or expired share"),
+    }
+}
+
+/// Serve a resource through a share token, within the share's extractor if limited to one
+async fn shared_resource(
+    share: core::share::Share,
+    tail: std::path::PathBuf,
+    full_req: &HttpRequest,
+    state: &core::state::State,
+) -> HttpResponse {
+    let ip = get_client_ip(full_req, state);
+    let event = AuditEvent::new(AuditAction::ResourceAccess)
+        .ip(ip.as_deref())
+        .target(format!("{}/{}", share.capture_uuid, tail.display()))
+        .detail(format!("share:{}", share.id));
+    if !share.allows(&tail) {
+        core::audit::record(state, event.failed()).await;
+        return HttpResponse::Unauthorized().body("Not covered by share");
+    }
+    core::audit::record(state, event).await;
+    serve_resource(share.capture_uuid, tail, full_req, state).await
+}
+
+/// Serve a file or directory listing from a capture's storage
+async fn serve_resource(
+    uuid: uuid::Uuid,
+    tail: std::path::PathBuf,
+    full_req: &HttpRequest,
+    state: &core::state::State,
+) -> HttpResponse {
+    // Only plain names, so the path can't climb out of the capture's directory
+    if !tail
+        .components()
+        .all(|c| matches!(c, std::path::Component::Normal(_)))
+    {
+        return HttpResponse::NotFound().body("Not found");
+    }
+    let listing = state
+        .storage_manager()
+        .asset_listing(&uuid, tail.clone())
+        .await;
+    if let Some(entries) = listing {
+        // Carry a share token in the query along to everything linked from the listing
+        let query = match full_req.query_string() {
+            "" => String::new(),
+            q => format!("?{q}"),
+        };
+        // Relative links in the listing only resolve against a trailing slash
+        if !full_req.path().ends_with('/') {
+            return HttpResponse::MovedPermanently()
+                .insert_header(("Location", format!("{}/{query}", full_req.path())))
+                .finish();
+        }
+        let entries: Vec<_> = entries
+            .into_iter()
+            .map(|(name, is_dir)| {
+                let mut entry = std::collections::HashMap::new();
+                let slash = if is_dir { "/" } else { "" };
+                entry.insert("href", format!("{name}{slash}{query}"));
+                entry.insert("name", name);
+                entry
+            })
+            .collect();
+        let mut context = Context::new();
+        context.insert("path", full_req.path());
+        context.insert("query", &query);
+        context.insert("entries", &entries);
+        return match TEMPLATES.render("listing.html", &context) {
+            Ok(d) => HttpResponse::Ok().body(d),
+            Err(e) => {
+                error!("Error rendering listing.html: {e}");
+                HttpResponse::InternalServerError().body("Error rendering listing.html")
+            }
+        };
     }
     let mime = state
         .storage_manager()
@@ -562,7 +3288,19 @@ async fn resource(
 }
 
 async fn server(config: core::config::CoreConfig) -> std::io::Result<()> {
+    // The listener itself never speaks TLS, so Secure cookies need a proxy in front
+    if config.secure_cookies() {
+        warn!(
+            "Cookies are marked Secure, so browsers only keep them over HTTPS; \
+             serve webarc-core behind a TLS-terminating proxy, or set `secure_cookies: false` \
+             to use the web interface over plain HTTP"
+        );
+    }
     let data = web::Data::new(core::state::State::from_config(config.clone()).await);
+    core::extract::recover(&data).await;
+    core::timeline::backfill(&data).await;
+    core::queue::run(data.clone()).await;
+    tokio::spawn(core::task::sweep_sessions(data.clone()));
     HttpServer::new(move || {
         App::new()
             .app_data(data.clone())
@@ -570,13 +3308,75 @@ async fn server(config: core::config::CoreConfig) -> std::io::Result<()> {
             .service(tera_login)
             .service(dashboard)
             .service(user_create)
+            .service(user_password)
+            .service(user_password_reset)
             .service(auth)
             .service(auth_form)
+            .service(auth_form_totp)
+            .service(auth_oidc)
+            .service(auth_oidc_callback)
+            .service(auth_logout)
+            .service(totp_enroll)
+            .service(totp_confirm)
+            .service(totp_disable)
+            .service(account_totp)
+            .service(account_totp_confirm)
+            .service(account_totp_disable)
+            .service(sessions_list)
+            .service(sessions_revoke)
+            .service(keys_create)
+            .service(keys_list)
+            .service(keys_revoke)
+            .service(webhooks_deliveries)
+            .service(webhooks_create)
+            .service(webhooks_list)
+            .service(webhooks_delete)
+            .service(admin_users_list)
+            .service(admin_users_disable)
+            .service(admin_users_enable)
+            .service(admin_users_delete)
+            .service(admin_users_reset)
+            .service(admin_invites_create)
+            .service(admin_invites_list)
+            .service(admin_audit)
             .service(capture_create)
             .service(capture_create_form)
+            .service(capture_batch)
+            .service(capture_batch_status)
+            .service(capture_retry)
+            .service(capture_retry_form)
+            .service(capture_trash)
+            .service(capture_delete)
+            .service(capture_update)
+            .service(capture_share_create)
+            .service(capture_share_list)
+            .service(capture_share_revoke)
+            .service(capture_restore)
+            .service(capture_delete_form)
+            .service(capture_restore_form)
+            .service(groups_create)
+            .service(groups_list)
+            .service(groups_delete)
+            .service(groups_members)
+            .service(groups_member_set)
+            .service(groups_member_remove)
+            .service(groups_create_form)
+            .service(groups_member_form)
+            .service(capture_landing)
+            .service(browse)
             .service(capture_status)
             .service(capture_progress)
+            .service(timeline)
+            .service(timeline_page)
+            .service(schedules_create)
+            .service(schedules_list)
+            .service(schedules_update)
+            .service(schedules_delete)
+            .service(schedules_create_form)
+            .service(schedules_delete_form)
+            .service(capture_events)
             .service(resource)
+            .service(share_resource)
     })
     .bind(config.listen())?
     .run()
@@ -13,13 +13,13 @@ fn get_bearer_token(req: &HttpRequest) -> Option<String> {
     if !authorization.starts_with("Bearer ") {
         return None;
     }
-    let token = (&authorization[7..]).to_string();
+    let token = authorization[7..].to_string();
     Some(token)
 }
 
 #[get("/version")]
 async fn version() -> impl Responder {
-    format!("{}", env!("CARGO_PKG_VERSION"))
+    env!("CARGO_PKG_VERSION").to_string()
 }
 
 #[post("/extract/create")]
@@ -1,10 +1,15 @@
-use actix_web::web;
-use diesel_async::RunQueryDsl;
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use log::*;
 use snafu::prelude::*;
 
 use crate::core;
-use crate::core::extract;
+use crate::core::config::Registration;
+use crate::core::queue;
+use crate::core::throttle;
+use crate::msg::clicor::{self, AuditAction, QueryCaptureResponse};
 
 #[derive(Debug, Snafu)]
 pub enum CreateCaptureError {
@@ -23,29 +28,39 @@ pub enum CreateCaptureError {
     UnableToRegisterError {
         source: crate::core::state::StorageError,
     },
+
+    #[snafu(display("Only contributors may file captures under a group"))]
+    CaptureGroupForbiddenError,
 }
 
 pub async fn create_capture(
     url: url::Url,
     user_id: i32,
     public: bool,
-    state: web::Data<core::state::State>,
+    group: Option<i32>,
+    callback: Option<&core::webhook::Callback>,
+    priority: i32,
+    state: &core::state::State,
 ) -> Result<uuid::Uuid, CreateCaptureError> {
     // Determine appropriate extractors for URL
     let extractors = state.extractor_map().await.extractors_for_url(&url).await;
     debug!("Extractors for {}: {:?}", &url, extractors);
-    if extractors.len() == 0 {
+    if extractors.is_empty() {
         return Err(CreateCaptureError::NoAppropriateExtractorsError);
     }
 
     // Build and insert the capture
-    let capture_uuid = uuid::Uuid::new_v4();
     let new_capture = core::models::InsCapture {
-        uuid: capture_uuid,
-        url: url.clone(),
+        uuid: uuid::Uuid::new_v4(),
+        url,
         time_initiated: chrono::Utc::now(),
         owner: user_id,
         public,
+        callback_url: callback.map(|c| c.url().to_string()),
+        callback_secret: callback.map(|c| c.secret().to_string()),
+        tracked_url: None,
+        batch: None,
+        group_id: group,
     };
     let mut conn = state
         .db_pool()
@@ -53,17 +68,79 @@ pub async fn create_capture(
         .get()
         .await
         .context(MysteriousDatabaseSnafu)?;
-    let new_capture: core::models::DbCapture = diesel::insert_into(core::schema::captures::table)
-        .values(new_capture)
-        .get_result(&mut conn)
-        .await
-        .context(UnableToInsertSnafu)?;
+    if let Some(group_id) = group {
+        let is_admin = state.is_admin(user_id).await;
+        let allowed = core::group::may_contribute(&mut conn, group_id, user_id, is_admin)
+            .await
+            .context(UnableToInsertSnafu)?;
+        if !allowed {
+            return Err(CreateCaptureError::CaptureGroupForbiddenError);
+        }
+    }
+    let capture_uuid = initiate(&mut conn, new_capture, extractors, &[], priority, state).await?;
+    state.job_queue().wake();
+
+    Ok(capture_uuid)
+}
+
+/// Insert a capture with a pending extract per extractor and start tracking its progress
+///
+/// The caller is responsible for waking the job queue.
+async fn initiate(
+    conn: &mut AsyncPgConnection,
+    mut new_capture: core::models::InsCapture,
+    extractors: Vec<String>,
+    tags: &[String],
+    priority: i32,
+    state: &core::state::State,
+) -> Result<uuid::Uuid, CreateCaptureError> {
+    let capture_uuid = new_capture.uuid;
+    let access = core::state::CaptureAccess {
+        owner: new_capture.owner,
+        public: new_capture.public,
+        group: new_capture.group_id,
+    };
+    let extractor_count = extractors.len();
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            new_capture.tracked_url = Some(core::timeline::track(conn, &new_capture.url).await?);
+            let new_capture: core::models::DbCapture =
+                diesel::insert_into(core::schema::captures::table)
+                    .values(new_capture)
+                    .get_result(conn)
+                    .await?;
+            let new_extracts: Vec<core::models::InsExtract> = extractors
+                .into_iter()
+                .map(|e| core::models::InsExtract::pending(new_capture.id, e))
+                .collect();
+            let extract_ids: Vec<i32> = diesel::insert_into(core::schema::extracts::table)
+                .values(new_extracts)
+                .returning(core::schema::extracts::id)
+                .get_results(conn)
+                .await?;
+            let new_tags: Vec<core::models::InsCaptureTag> = tags
+                .iter()
+                .map(|t| core::models::InsCaptureTag {
+                    capture: new_capture.id,
+                    tag: t.clone(),
+                })
+                .collect();
+            diesel::insert_into(core::schema::capture_tags::table)
+                .values(new_tags)
+                .execute(conn)
+                .await?;
+            queue::enqueue(conn, queue::JobKind::Extract, &extract_ids, priority).await
+        }
+        .scope_boxed()
+    })
+    .await
+    .context(UnableToInsertSnafu)?;
 
     // Recordkeeping
     state
         .capture_map()
         .await
-        .new_status(&capture_uuid, extractors.len(), user_id, public)
+        .new_status(&capture_uuid, extractor_count, access)
         .await;
     state
         .storage_manager()
@@ -71,20 +148,1016 @@ pub async fn create_capture(
         .await
         .context(UnableToRegisterSnafu)?;
 
-    // Spawn extraction tasks
-    for extractor in extractors.iter() {
-        let state = state.clone();
-        let extractor = extractor.clone();
-        let url = url.clone();
-        let db_capid = new_capture.id;
-        tokio::spawn(extract::extract(
-            state,
-            extractor,
+    Ok(capture_uuid)
+}
+
+/// Most URLs accepted in a single batch
+pub const MAX_BATCH_SIZE: usize = 1000;
+
+/// Longest tag accepted, in characters
+pub const MAX_TAG_LENGTH: usize = 64;
+
+#[derive(Debug, Snafu)]
+pub enum BatchError {
+    #[snafu(display("Batch contains no URLs"))]
+    EmptyBatchError,
+
+    #[snafu(display("Batch contains more than {limit} URLs"))]
+    BatchTooLargeError { limit: usize },
+
+    #[snafu(display("Tags must be between 1 and {MAX_TAG_LENGTH} characters"))]
+    InvalidTagError,
+
+    #[snafu(display("Mysterious database error"))]
+    BatchPoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Batch query failed"))]
+    BatchQueryError { source: diesel::result::Error },
+}
+
+/// Initiate a capture of every URL in a batch, sharing visibility and tags
+///
+/// URLs which fail to parse, have no appropriate extractors or can't be
+/// initiated are reported alongside the captures that were, rather than
+/// failing the batch.
+pub async fn create_batch(
+    urls: &[String],
+    user_id: i32,
+    public: bool,
+    tags: &[String],
+    state: &core::state::State,
+) -> Result<(uuid::Uuid, Vec<clicor::BatchCaptureItem>), BatchError> {
+    use clicor::BatchCaptureOutcome;
+    use core::schema::batches;
+
+    if urls.is_empty() {
+        return Err(BatchError::EmptyBatchError);
+    }
+    if urls.len() > MAX_BATCH_SIZE {
+        return Err(BatchError::BatchTooLargeError {
+            limit: MAX_BATCH_SIZE,
+        });
+    }
+    let mut tags: Vec<String> = tags.iter().map(|t| t.trim().to_string()).collect();
+    if tags
+        .iter()
+        .any(|t| t.is_empty() || t.chars().count() > MAX_TAG_LENGTH)
+    {
+        return Err(BatchError::InvalidTagError);
+    }
+    tags.sort();
+    tags.dedup();
+
+    let mut conn = state.db_pool().await.get().await.context(BatchPoolSnafu)?;
+    let now = chrono::Utc::now();
+    let new_batch = core::models::InsBatch {
+        uuid: uuid::Uuid::new_v4(),
+        owner: user_id,
+        public,
+        time_created: now,
+    };
+    let batch: core::models::DbBatch = diesel::insert_into(batches::table)
+        .values(new_batch)
+        .get_result(&mut conn)
+        .await
+        .context(BatchQuerySnafu)?;
+
+    let mut results = Vec::with_capacity(urls.len());
+    for raw in urls {
+        let Ok(url) = url::Url::parse(raw.trim()) else {
+            results.push(clicor::BatchCaptureItem::new(
+                raw.clone(),
+                BatchCaptureOutcome::Invalid,
+            ));
+            continue;
+        };
+        let extractors = state.extractor_map().await.extractors_for_url(&url).await;
+        if extractors.is_empty() {
+            results.push(clicor::BatchCaptureItem::new(
+                raw.clone(),
+                BatchCaptureOutcome::NoExtractors,
+            ));
+            continue;
+        }
+        let new_capture = core::models::InsCapture {
+            uuid: uuid::Uuid::new_v4(),
             url,
-            db_capid,
-            capture_uuid.clone(),
-        ));
+            time_initiated: now,
+            owner: user_id,
+            public,
+            callback_url: None,
+            callback_secret: None,
+            tracked_url: None,
+            batch: Some(batch.id),
+            group_id: None,
+        };
+        let outcome = match initiate(
+            &mut conn,
+            new_capture,
+            extractors,
+            &tags,
+            queue::PRIORITY_INTERACTIVE,
+            state,
+        )
+        .await
+        {
+            Ok(capture_id) => BatchCaptureOutcome::Initiated { capture_id },
+            Err(e) => {
+                error!("Unable to initiate {raw} in batch {}: {e}", batch.uuid);
+                BatchCaptureOutcome::Failed
+            }
+        };
+        results.push(clicor::BatchCaptureItem::new(raw.clone(), outcome));
     }
+    state.job_queue().wake();
 
-    Ok(capture_uuid)
+    Ok((batch.uuid, results))
+}
+
+/// Aggregate the progress of every capture in one of a user's batches
+pub async fn batch_status(
+    batch_uuid: &uuid::Uuid,
+    user_id: i32,
+    state: &core::state::State,
+) -> Result<Option<clicor::BatchProgress>, BatchError> {
+    use core::extract::ExtractState;
+    use core::schema::{batches, captures, extracts};
+
+    let mut conn = state.db_pool().await.get().await.context(BatchPoolSnafu)?;
+    let batch: Option<core::models::DbBatch> = batches::table
+        .filter(batches::uuid.eq(batch_uuid))
+        .filter(batches::owner.eq(user_id))
+        .get_result(&mut conn)
+        .await
+        .optional()
+        .context(BatchQuerySnafu)?;
+    let Some(batch) = batch else {
+        return Ok(None);
+    };
+    let rows: Vec<(uuid::Uuid, String, bool, String)> = captures::table
+        .inner_join(extracts::table)
+        .filter(captures::batch.eq(batch.id))
+        .filter(captures::time_deleted.is_null())
+        .order((captures::id, extracts::id))
+        .select((
+            captures::uuid,
+            captures::url,
+            captures::time_finished.is_not_null(),
+            extracts::state,
+        ))
+        .load(&mut conn)
+        .await
+        .context(BatchQuerySnafu)?;
+    let mut progress = clicor::BatchProgress::new(batch.uuid, batch.public, batch.time_created);
+    for (capture_uuid, url, finished, extract_state) in rows {
+        let extract_state = extract_state.parse().unwrap_or(ExtractState::Failed);
+        progress.add_extract(capture_uuid, url, finished, extract_state.into());
+    }
+    Ok(Some(progress))
+}
+
+#[derive(Debug, Snafu)]
+pub enum CaptureStatusError {
+    #[snafu(display("Unable to get a database connection"))]
+    CaptureStatusPoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Capture status query failed"))]
+    CaptureStatusQueryError { source: diesel::result::Error },
+}
+
+/// Describe the progress of a capture, including a breakdown by extractor
+///
+/// Captures still tracked in the [`CaptureMap`] report their live counts;
+/// any other capture is described entirely from the database.
+///
+/// [`CaptureMap`]: core::state::CaptureMap
+pub async fn capture_status(
+    capture_uuid: &uuid::Uuid,
+    state: &core::state::State,
+) -> Result<Option<core::state::CaptureStatus>, CaptureStatusError> {
+    use core::schema::{captures, extracts};
+
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(CaptureStatusPoolSnafu)?;
+    let capture: Option<core::models::DbCapture> = captures::table
+        .filter(captures::uuid.eq(capture_uuid))
+        .filter(captures::time_deleted.is_null())
+        .get_result(&mut conn)
+        .await
+        .optional()
+        .context(CaptureStatusQuerySnafu)?;
+    let Some(capture) = capture else {
+        return Ok(None);
+    };
+    let descriptions: Vec<_> = extracts::table
+        .filter(extracts::capture.eq(capture.id))
+        .order(extracts::extractor.asc())
+        .load::<core::models::DbExtract>(&mut conn)
+        .await
+        .context(CaptureStatusQuerySnafu)?
+        .into_iter()
+        .map(|e| core::extract::describe(e, capture_uuid))
+        .collect();
+    let status = match state.capture_map().await.get_status(capture_uuid).await {
+        Some(mut live) => {
+            live.set_extracts(descriptions);
+            live
+        }
+        None => core::state::CaptureStatus::from_progress(
+            QueryCaptureResponse::from_extracts(descriptions),
+            core::state::CaptureAccess::from(&capture),
+        ),
+    };
+    Ok(Some(status))
+}
+
+#[derive(Debug, Snafu)]
+pub enum RetryCaptureError {
+    #[snafu(display("No such capture"))]
+    NoSuchCaptureError,
+
+    #[snafu(display("No failed extracts to retry"))]
+    NothingToRetryError,
+
+    #[snafu(display("Only the capture's owner, an admin or an admin of its group may retry it"))]
+    RetryForbiddenError,
+
+    #[snafu(display("Mysterious database error"))]
+    RetryPoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Unable to reset extracts"))]
+    RetryQueryError { source: diesel::result::Error },
+}
+
+impl From<diesel::result::Error> for RetryCaptureError {
+    fn from(source: diesel::result::Error) -> Self {
+        RetryCaptureError::RetryQueryError { source }
+    }
+}
+
+/// Dispatch the failed extracts of a capture again, returning their extractors
+///
+/// Each failed attempt is archived to `extract_attempts` before its extract is
+/// reset to pending, so the capture keeps one extract per extractor. Only the
+/// named extractors are retried, unless none are named. Anyone who may change
+/// the capture may retry it.
+pub async fn retry_capture(
+    capture_uuid: &uuid::Uuid,
+    user_id: i32,
+    extractors: &[String],
+    state: &core::state::State,
+) -> Result<Vec<String>, RetryCaptureError> {
+    use core::extract::ExtractState;
+    use core::schema::{captures, extract_attempts, extracts, jobs};
+
+    let is_admin = state.is_admin(user_id).await;
+
+    let mut conn = state.db_pool().await.get().await.context(RetryPoolSnafu)?;
+    let (capture, retried) = conn
+        .transaction::<_, RetryCaptureError, _>(|conn| {
+            async move {
+                let capture: core::models::DbCapture = captures::table
+                    .filter(captures::uuid.eq(capture_uuid))
+                    .filter(captures::time_deleted.is_null())
+                    .for_update()
+                    .get_result(conn)
+                    .await
+                    .optional()?
+                    .ok_or(RetryCaptureError::NoSuchCaptureError)?;
+                let group_role = match capture.group_id {
+                    Some(g) => core::group::role(conn, g, user_id).await?,
+                    None => None,
+                };
+                if capture.owner != user_id
+                    && !is_admin
+                    && group_role != Some(clicor::GroupRole::Admin)
+                {
+                    return Err(RetryCaptureError::RetryForbiddenError);
+                }
+                let failed: Vec<core::models::DbExtract> = extracts::table
+                    .filter(extracts::capture.eq(capture.id))
+                    .filter(extracts::state.eq(ExtractState::Failed.as_str()))
+                    .for_update()
+                    .load::<core::models::DbExtract>(conn)
+                    .await?
+                    .into_iter()
+                    .filter(|e| extractors.is_empty() || extractors.contains(&e.extractor))
+                    .collect();
+                if failed.is_empty() {
+                    return Err(RetryCaptureError::NothingToRetryError);
+                }
+                let attempts: Vec<core::models::InsExtractAttempt> = failed
+                    .iter()
+                    .map(core::models::InsExtractAttempt::from_extract)
+                    .collect();
+                diesel::insert_into(extract_attempts::table)
+                    .values(attempts)
+                    .execute(conn)
+                    .await?;
+                let ids: Vec<i32> = failed.iter().map(|e| e.id).collect();
+                let now = chrono::Utc::now();
+                diesel::update(extracts::table.filter(extracts::id.eq_any(&ids)))
+                    .set((
+                        extracts::state.eq(ExtractState::Pending.as_str()),
+                        extracts::worker.eq(None::<String>),
+                        extracts::ticket.eq(None::<uuid::Uuid>),
+                        extracts::dispatches.eq(0),
+                        extracts::failure.eq(None::<String>),
+                        extracts::time_created.eq(now),
+                        extracts::time_updated.eq(now),
+                    ))
+                    .execute(conn)
+                    .await?;
+                diesel::update(captures::table.filter(captures::id.eq(capture.id)))
+                    .set(captures::time_finished.eq(None::<chrono::DateTime<chrono::Utc>>))
+                    .execute(conn)
+                    .await?;
+                // A job left behind by an abandoned attempt would collide with the new one
+                diesel::delete(
+                    jobs::table
+                        .filter(jobs::kind.eq(queue::JobKind::Extract.as_str()))
+                        .filter(jobs::subject.eq_any(&ids)),
+                )
+                .execute(conn)
+                .await?;
+                queue::enqueue(
+                    conn,
+                    queue::JobKind::Extract,
+                    &ids,
+                    queue::PRIORITY_INTERACTIVE,
+                )
+                .await?;
+                let retried = failed.into_iter().map(|e| e.extractor).collect();
+                Ok((capture, retried))
+            }
+            .scope_boxed()
+        })
+        .await?;
+    state.job_queue().wake();
+
+    // Reinstate the capture in the map so progress can be followed again
+    let descriptions: Vec<_> = extracts::table
+        .filter(extracts::capture.eq(capture.id))
+        .load::<core::models::DbExtract>(&mut conn)
+        .await?
+        .into_iter()
+        .map(|e| core::extract::describe(e, capture_uuid))
+        .collect();
+    state
+        .capture_map()
+        .await
+        .restore_status(
+            capture_uuid,
+            QueryCaptureResponse::from_extracts(descriptions),
+            core::state::CaptureAccess::from(&capture),
+        )
+        .await;
+    Ok(retried)
+}
+
+/// Maximum length in characters of a capture's title
+pub const MAX_TITLE_LENGTH: usize = 256;
+
+/// Maximum length in characters of a capture's notes
+pub const MAX_NOTES_LENGTH: usize = 10_000;
+
+#[derive(Debug, Snafu)]
+pub enum UpdateCaptureError {
+    #[snafu(display("No such capture"))]
+    UpdateNoSuchCaptureError,
+
+    #[snafu(display("Only the capture's owner, an admin or an admin of its group may change it"))]
+    UpdateForbiddenError,
+
+    #[snafu(display("Only contributors may file captures under a group"))]
+    UpdateGroupForbiddenError,
+
+    #[snafu(display("No such user to transfer the capture to"))]
+    NoSuchOwnerError,
+
+    #[snafu(display("Invalid update: {reason}"))]
+    InvalidUpdateError { reason: String },
+
+    #[snafu(display("Mysterious database error"))]
+    UpdatePoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Unable to update capture"))]
+    UpdateQueryError { source: diesel::result::Error },
+}
+
+impl From<diesel::result::Error> for UpdateCaptureError {
+    fn from(source: diesel::result::Error) -> Self {
+        UpdateCaptureError::UpdateQueryError { source }
+    }
+}
+
+/// The outcome of changing a capture
+#[derive(Debug)]
+pub struct CaptureUpdate {
+    pub capture: clicor::CaptureDescription,
+    /// Audit details for each change to who may view the capture
+    pub access_changes: Vec<String>,
+}
+
+/// Change the visibility, owner, group, title or notes of a capture
+///
+/// Only its owner, an admin or an admin of its group may change a capture,
+/// and only into a group they contribute to. Empty titles and notes clear them.
+pub async fn update_capture(
+    capture_uuid: &uuid::Uuid,
+    user_id: i32,
+    request: &clicor::UpdateCaptureRequest,
+    state: &core::state::State,
+) -> Result<CaptureUpdate, UpdateCaptureError> {
+    use core::schema::{captures, users};
+
+    let title = request.title().map(str::trim);
+    if title.is_some_and(|t| t.chars().count() > MAX_TITLE_LENGTH) {
+        return Err(UpdateCaptureError::InvalidUpdateError {
+            reason: format!("Titles may be at most {MAX_TITLE_LENGTH} characters"),
+        });
+    }
+    if request
+        .notes()
+        .is_some_and(|n| n.chars().count() > MAX_NOTES_LENGTH)
+    {
+        return Err(UpdateCaptureError::InvalidUpdateError {
+            reason: format!("Notes may be at most {MAX_NOTES_LENGTH} characters"),
+        });
+    }
+    let is_admin = state.is_admin(user_id).await;
+
+    let mut conn = state.db_pool().await.get().await.context(UpdatePoolSnafu)?;
+    let (capture, owner_name, access_changes) = conn
+        .transaction::<_, UpdateCaptureError, _>(|conn| {
+            async move {
+                let mut capture: core::models::DbCapture = captures::table
+                    .filter(captures::uuid.eq(capture_uuid))
+                    .filter(captures::time_deleted.is_null())
+                    .for_update()
+                    .get_result(conn)
+                    .await
+                    .optional()?
+                    .ok_or(UpdateCaptureError::UpdateNoSuchCaptureError)?;
+                let group_role = match capture.group_id {
+                    Some(g) => core::group::role(conn, g, user_id).await?,
+                    None => None,
+                };
+                if capture.owner != user_id
+                    && !is_admin
+                    && group_role != Some(clicor::GroupRole::Admin)
+                {
+                    return Err(UpdateCaptureError::UpdateForbiddenError);
+                }
+                let mut access_changes = vec![];
+                if let Some(public) = request.public()
+                    && public != capture.public
+                {
+                    access_changes.push(if public { "public" } else { "private" }.to_owned());
+                    capture.public = public;
+                }
+                let owner_name = match request.owner() {
+                    Some(name) => {
+                        let owner: i32 = users::table
+                            .filter(users::username.eq(name))
+                            .filter(users::disabled.eq(false))
+                            .select(users::id)
+                            .get_result(conn)
+                            .await
+                            .optional()?
+                            .ok_or(UpdateCaptureError::NoSuchOwnerError)?;
+                        if owner != capture.owner {
+                            access_changes.push(format!("owner:{name}"));
+                            capture.owner = owner;
+                        }
+                        name.to_owned()
+                    }
+                    None => {
+                        users::table
+                            .filter(users::id.eq(capture.owner))
+                            .select(users::username)
+                            .get_result(conn)
+                            .await?
+                    }
+                };
+                if let Some(group) = request.group()
+                    && group != capture.group_id
+                {
+                    if let Some(g) = group
+                        && !core::group::may_contribute(conn, g, user_id, is_admin).await?
+                    {
+                        return Err(UpdateCaptureError::UpdateGroupForbiddenError);
+                    }
+                    access_changes.push(match group {
+                        Some(g) => format!("group:{g}"),
+                        None => "group:none".to_owned(),
+                    });
+                    capture.group_id = group;
+                }
+                if let Some(title) = title {
+                    capture.title = (!title.is_empty()).then(|| title.to_owned());
+                }
+                if let Some(notes) = request.notes() {
+                    capture.notes = (!notes.is_empty()).then(|| notes.to_owned());
+                }
+                diesel::update(captures::table.filter(captures::id.eq(capture.id)))
+                    .set((
+                        captures::public.eq(capture.public),
+                        captures::owner.eq(capture.owner),
+                        captures::group_id.eq(capture.group_id),
+                        captures::title.eq(&capture.title),
+                        captures::notes.eq(&capture.notes),
+                    ))
+                    .execute(conn)
+                    .await?;
+                Ok((capture, owner_name, access_changes))
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    if !access_changes.is_empty() {
+        state
+            .capture_map()
+            .await
+            .set_access(capture_uuid, core::state::CaptureAccess::from(&capture))
+            .await;
+    }
+    Ok(CaptureUpdate {
+        capture: clicor::CaptureDescription::new(
+            capture.uuid,
+            capture.url,
+            owner_name,
+            capture.public,
+            capture.title,
+            capture.notes,
+            capture.group_id,
+        ),
+        access_changes,
+    })
+}
+
+#[derive(Debug, Snafu)]
+pub enum CreateUserError {
+    #[snafu(display("Registration is closed"))]
+    RegistrationClosedError,
+
+    #[snafu(display("Invite code is missing, invalid, expired or already redeemed"))]
+    InvalidInviteError,
+
+    #[snafu(display("Username is already taken"))]
+    UnavailableUsernameError,
+
+    #[snafu(display("Unable to hash password"))]
+    PasswordHashError { source: bcrypt::BcryptError },
+
+    #[snafu(display("Mysterious database error"))]
+    UserDatabasePoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Unable to insert user"))]
+    UserQueryError { source: diesel::result::Error },
+}
+
+impl From<diesel::result::Error> for CreateUserError {
+    fn from(source: diesel::result::Error) -> Self {
+        CreateUserError::UserQueryError { source }
+    }
+}
+
+/// Create a user, subject to the configured registration mode
+///
+/// The first user of a fresh deployment is always allowed to register and
+/// becomes an admin.
+pub async fn create_user(
+    username: &str,
+    password: &str,
+    invite: Option<&str>,
+    state: &core::state::State,
+) -> Result<i32, CreateUserError> {
+    use core::schema::{invites, users};
+    let passhash = bcrypt::hash(password, bcrypt::DEFAULT_COST).context(PasswordHashSnafu)?;
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(UserDatabasePoolSnafu)?;
+    let registration = state.registration();
+    let invite_hash = invite.map(core::auth::hash_secret);
+    conn.transaction::<_, CreateUserError, _>(|conn| {
+        async move {
+            let user_count: i64 = users::table.count().get_result(conn).await?;
+            let bootstrap = user_count == 0;
+            if !bootstrap {
+                match registration {
+                    Registration::Open => {}
+                    Registration::InviteOnly => {
+                        if invite_hash.is_none() {
+                            return Err(CreateUserError::InvalidInviteError);
+                        }
+                    }
+                    Registration::Closed => return Err(CreateUserError::RegistrationClosedError),
+                }
+            }
+            let new_user =
+                core::models::InsUser::new(username.to_string(), Some(passhash), bootstrap);
+            let user_id: i32 = match diesel::insert_into(users::table)
+                .values(new_user)
+                .returning(users::id)
+                .get_result(conn)
+                .await
+            {
+                Ok(i) => i,
+                Err(diesel::result::Error::DatabaseError(
+                    DatabaseErrorKind::UniqueViolation,
+                    _,
+                )) => {
+                    return Err(CreateUserError::UnavailableUsernameError);
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if registration == Registration::InviteOnly
+                && !bootstrap
+                && let Some(invite_hash) = invite_hash
+            {
+                let now = chrono::Utc::now();
+                let invite = invites::table
+                    .filter(invites::code_hash.eq(invite_hash))
+                    .filter(invites::time_redeemed.is_null())
+                    .filter(
+                        invites::time_expires
+                            .is_null()
+                            .or(invites::time_expires.gt(now)),
+                    );
+                let redeemed = diesel::update(invite)
+                    .set((
+                        invites::redeemer.eq(user_id),
+                        invites::time_redeemed.eq(now),
+                    ))
+                    .execute(conn)
+                    .await?;
+                if redeemed != 1 {
+                    return Err(CreateUserError::InvalidInviteError);
+                }
+            }
+            Ok(user_id)
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[derive(Debug, Snafu)]
+pub enum PasswordError {
+    #[snafu(display("Old password is incorrect"))]
+    IncorrectPasswordError,
+
+    #[snafu(display("Reset token is invalid or expired"))]
+    InvalidResetTokenError,
+
+    #[snafu(display("Too many failed attempts; retry after {retry_after}s"))]
+    PasswordRateLimitedError { retry_after: u64 },
+
+    #[snafu(display("Password throttling failed"))]
+    PasswordThrottleError { source: throttle::ThrottleError },
+
+    #[snafu(display("Unable to verify password"))]
+    PasswordVerifyError { source: bcrypt::BcryptError },
+
+    #[snafu(display("Unable to hash password"))]
+    PasswordRehashError { source: bcrypt::BcryptError },
+
+    #[snafu(display("Mysterious database error"))]
+    PasswordDatabasePoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Password query failed"))]
+    PasswordQueryError { source: diesel::result::Error },
+}
+
+impl From<diesel::result::Error> for PasswordError {
+    fn from(source: diesel::result::Error) -> Self {
+        PasswordError::PasswordQueryError { source }
+    }
+}
+
+/// Replace a user's password hash and revoke all of their sessions
+///
+/// API keys are left alone, since automation holding them shouldn't break on
+/// a password change; they are revoked individually.
+async fn replace_password(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    passhash: String,
+) -> Result<(), diesel::result::Error> {
+    use core::schema::{sessions, users};
+    diesel::update(users::table.filter(users::id.eq(user_id)))
+        .set(users::passhash.eq(passhash))
+        .execute(conn)
+        .await?;
+    diesel::delete(sessions::table.filter(sessions::owner.eq(user_id)))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Change a user's password after confirming their current one
+///
+/// Guesses at the current password are throttled together with logins, so a
+/// stolen session can't be used to brute-force it.
+pub async fn change_password(
+    user_id: i32,
+    old_password: &str,
+    new_password: &str,
+    ip: Option<&str>,
+    state: &core::state::State,
+) -> Result<(), PasswordError> {
+    use core::schema::users;
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(PasswordDatabasePoolSnafu)?;
+    let (username, old_passhash): (String, Option<String>) = users::table
+        .filter(users::id.eq(user_id))
+        .select((users::username, users::passhash))
+        .get_result(&mut conn)
+        .await?;
+    let Some(old_passhash) = old_passhash else {
+        return Err(PasswordError::IncorrectPasswordError);
+    };
+    let admission = throttle::begin_attempt(state, &username, ip)
+        .await
+        .context(PasswordThrottleSnafu)?;
+    let attempt = match admission {
+        throttle::Admission::Admitted(attempt) => attempt,
+        throttle::Admission::Throttled(retry_after) => {
+            return Err(PasswordError::PasswordRateLimitedError { retry_after });
+        }
+    };
+    if !bcrypt::verify(old_password, &old_passhash).context(PasswordVerifySnafu)? {
+        return Err(PasswordError::IncorrectPasswordError);
+    }
+    attempt
+        .succeed(state)
+        .await
+        .context(PasswordThrottleSnafu)?;
+    let passhash = bcrypt::hash(new_password, bcrypt::DEFAULT_COST).context(PasswordRehashSnafu)?;
+    conn.transaction::<_, PasswordError, _>(|conn| {
+        async move {
+            replace_password(conn, user_id, passhash).await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Set a new password by redeeming a one-time reset token
+pub async fn reset_password(
+    token: &str,
+    new_password: &str,
+    state: &core::state::State,
+) -> Result<(), PasswordError> {
+    use core::schema::password_resets;
+    let passhash = bcrypt::hash(new_password, bcrypt::DEFAULT_COST).context(PasswordRehashSnafu)?;
+    let token_hash = core::auth::hash_secret(token);
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(PasswordDatabasePoolSnafu)?;
+    conn.transaction::<_, PasswordError, _>(|conn| {
+        async move {
+            let reset = password_resets::table
+                .filter(password_resets::token_hash.eq(token_hash))
+                .filter(password_resets::time_expires.gt(chrono::Utc::now()));
+            let user_id: i32 = diesel::delete(reset)
+                .returning(password_resets::owner)
+                .get_result(conn)
+                .await
+                .optional()?
+                .ok_or(PasswordError::InvalidResetTokenError)?;
+            replace_password(conn, user_id, passhash).await?;
+            diesel::delete(password_resets::table.filter(password_resets::owner.eq(user_id)))
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[derive(Debug, Snafu)]
+pub enum LoginError {
+    #[snafu(display("Username or password is incorrect"))]
+    InvalidCredentialsError,
+
+    #[snafu(display("Account is disabled"))]
+    AccountDisabledError,
+
+    #[snafu(display("A second factor is required"))]
+    SecondFactorRequiredError { user_id: i32 },
+
+    #[snafu(display("Too many failed attempts; retry after {retry_after}s"))]
+    RateLimitedError { retry_after: u64 },
+
+    #[snafu(display("Unable to verify password"))]
+    LoginVerifyError { source: bcrypt::BcryptError },
+
+    #[snafu(display("Unable to verify second factor"))]
+    LoginTotpError { source: core::totp::TotpError },
+
+    #[snafu(display("Login throttling failed"))]
+    LoginThrottleError { source: throttle::ThrottleError },
+
+    #[snafu(display("Mysterious database error"))]
+    LoginDatabasePoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Unable to look up user"))]
+    LoginQueryError { source: diesel::result::Error },
+}
+
+/// Record a login attempt, refusing it while the username or client address is throttled
+async fn begin_attempt(
+    username: &str,
+    ip: Option<&str>,
+    state: &core::state::State,
+) -> Result<throttle::Attempt, LoginError> {
+    let admission = throttle::begin_attempt(state, username, ip)
+        .await
+        .context(LoginThrottleSnafu)?;
+    match admission {
+        throttle::Admission::Admitted(attempt) => Ok(attempt),
+        throttle::Admission::Throttled(retry_after) => {
+            debug!("Login for {username} from {ip:?} throttled for {retry_after}s");
+            Err(LoginError::RateLimitedError { retry_after })
+        }
+    }
+}
+
+/// Check a second factor for a user whose password has already been verified
+async fn finish_second_factor(
+    user: &core::models::DbUser,
+    code: &str,
+    attempt: throttle::Attempt,
+    state: &core::state::State,
+) -> Result<i32, LoginError> {
+    let accepted = core::totp::check_code(state, user, code)
+        .await
+        .context(LoginTotpSnafu)?;
+    if !accepted {
+        return Err(LoginError::InvalidCredentialsError);
+    }
+    attempt.succeed(state).await.context(LoginThrottleSnafu)?;
+    Ok(user.id)
+}
+
+/// Abandon an attempt which ended without a verdict on the credentials
+async fn abandon_attempt(
+    attempt: throttle::Attempt,
+    error: LoginError,
+    state: &core::state::State,
+) -> Result<i32, LoginError> {
+    attempt.abandon(state).await.context(LoginThrottleSnafu)?;
+    Err(error)
+}
+
+/// Record the outcome of a login attempt in the audit log
+async fn audit_login(
+    username: &str,
+    ip: Option<&str>,
+    result: &Result<i32, LoginError>,
+    state: &core::state::State,
+) {
+    let event = core::audit::AuditEvent::new(AuditAction::Login)
+        .ip(ip)
+        .target(username);
+    let event = match result {
+        Ok(user_id) => event.actor(Some(*user_id)),
+        // Not an outcome yet; the attempt is recorded once the second factor is checked
+        Err(LoginError::SecondFactorRequiredError { .. }) => return,
+        Err(LoginError::InvalidCredentialsError) => event.failed().detail("invalid_credentials"),
+        Err(LoginError::AccountDisabledError) => event.failed().detail("account_disabled"),
+        Err(LoginError::RateLimitedError { .. }) => event.failed().detail("rate_limited"),
+        Err(_) => event.failed().detail("error"),
+    };
+    core::audit::record(state, event).await;
+}
+
+/// Verify a username and password, returning the user's ID
+///
+/// Every attempt is recorded before it is evaluated, and refused without
+/// checking the password while the username or client address is throttled.
+/// Users with two-factor authentication enabled must also supply a TOTP or
+/// recovery code; without one, [`LoginError::SecondFactorRequiredError`] is
+/// returned and nothing is recorded.
+pub async fn login(
+    username: &str,
+    password: &str,
+    totp: Option<&str>,
+    ip: Option<&str>,
+    state: &core::state::State,
+) -> Result<i32, LoginError> {
+    let result = check_login(username, password, totp, ip, state).await;
+    audit_login(username, ip, &result, state).await;
+    result
+}
+
+async fn check_login(
+    username: &str,
+    password: &str,
+    totp: Option<&str>,
+    ip: Option<&str>,
+    state: &core::state::State,
+) -> Result<i32, LoginError> {
+    use core::schema::users;
+    let attempt = begin_attempt(username, ip, state).await?;
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(LoginDatabasePoolSnafu)?;
+    let user: Option<core::models::DbUser> = users::table
+        .filter(users::username.eq(username))
+        .get_result(&mut conn)
+        .await
+        .optional()
+        .context(LoginQuerySnafu)?;
+    // Accounts provisioned through OIDC have no password to check against
+    let verified = match user.as_ref().and_then(|u| u.passhash.as_deref()) {
+        Some(passhash) => bcrypt::verify(password, passhash).context(LoginVerifySnafu)?,
+        None => false,
+    };
+    let user = match user {
+        Some(u) if verified => u,
+        // The attempt has already been recorded as a failure
+        _ => return Err(LoginError::InvalidCredentialsError),
+    };
+    if user.disabled {
+        return abandon_attempt(attempt, LoginError::AccountDisabledError, state).await;
+    }
+    if user.totp_enabled {
+        return match totp {
+            Some(code) => finish_second_factor(&user, code, attempt, state).await,
+            None => {
+                let error = LoginError::SecondFactorRequiredError { user_id: user.id };
+                abandon_attempt(attempt, error, state).await
+            }
+        };
+    }
+    attempt.succeed(state).await.context(LoginThrottleSnafu)?;
+    Ok(user.id)
+}
+
+/// Complete a login which was interrupted by [`LoginError::SecondFactorRequiredError`]
+pub async fn login_second_factor(
+    user_id: i32,
+    code: &str,
+    ip: Option<&str>,
+    state: &core::state::State,
+) -> Result<i32, LoginError> {
+    use core::schema::users;
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(LoginDatabasePoolSnafu)?;
+    let user: core::models::DbUser = users::table
+        .filter(users::id.eq(user_id))
+        .get_result(&mut conn)
+        .await
+        .context(LoginQuerySnafu)?;
+    let result = async {
+        let attempt = begin_attempt(&user.username, ip, state).await?;
+        if user.disabled {
+            return abandon_attempt(attempt, LoginError::AccountDisabledError, state).await;
+        }
+        finish_second_factor(&user, code, attempt, state).await
+    }
+    .await;
+    audit_login(&user.username, ip, &result, state).await;
+    result
 }
@@ -0,0 +1,259 @@
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use snafu::prelude::*;
+
+use crate::core::auth;
+use crate::core::models::{DbInvite, DbUser, InsInvite, InsPasswordReset};
+use crate::core::queue::{self, JobKind};
+use crate::core::schema::{
+    api_keys, batches, captures, group_members, invites, oidc_identities, password_resets,
+    recovery_codes, schedules, sessions, users, webhooks,
+};
+use crate::core::state::State;
+use crate::msg::clicor;
+
+/// How long a password reset token remains redeemable
+const PASSWORD_RESET_LIFETIME: chrono::TimeDelta = chrono::TimeDelta::hours(24);
+
+#[derive(Debug, Snafu)]
+pub enum AdminError {
+    #[snafu(display("Admins may not disable or delete themselves"))]
+    SelfModificationError,
+
+    #[snafu(display("User still owns captures"))]
+    OwnsCapturesError,
+
+    #[snafu(display("User is the only admin of a group"))]
+    LastGroupAdminError,
+
+    #[snafu(display("Unable to get a database connection"))]
+    AdminPoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Admin query failed"))]
+    AdminQueryError { source: diesel::result::Error },
+}
+
+impl From<diesel::result::Error> for AdminError {
+    fn from(source: diesel::result::Error) -> Self {
+        AdminError::AdminQueryError { source }
+    }
+}
+
+/// Describe every user
+pub async fn list_users(state: &State) -> Result<Vec<clicor::UserDescription>, AdminError> {
+    let mut conn = state.db_pool().await.get().await.context(AdminPoolSnafu)?;
+    let users: Vec<DbUser> = users::table
+        .order(users::id.asc())
+        .load(&mut conn)
+        .await
+        .context(AdminQuerySnafu)?;
+    let users = users
+        .into_iter()
+        .map(|u| clicor::UserDescription::new(u.id, u.username, u.is_admin, u.disabled))
+        .collect();
+    Ok(users)
+}
+
+/// Disable or re-enable a user, returning whether they exist
+///
+/// A disabled user's sessions and API keys stop resolving but are kept, so
+/// re-enabling them restores access.
+pub async fn set_disabled(
+    state: &State,
+    admin_id: i32,
+    user_id: i32,
+    disabled: bool,
+) -> Result<bool, AdminError> {
+    if admin_id == user_id {
+        return Err(AdminError::SelfModificationError);
+    }
+    let mut conn = state.db_pool().await.get().await.context(AdminPoolSnafu)?;
+    let count = diesel::update(users::table.filter(users::id.eq(user_id)))
+        .set(users::disabled.eq(disabled))
+        .execute(&mut conn)
+        .await
+        .context(AdminQuerySnafu)?;
+    Ok(count > 0)
+}
+
+/// Delete a user along with their credentials, returning whether they existed
+///
+/// Users who still own captures are refused rather than orphaning them, as are
+/// users who are the only admin of a group, which nobody could manage after.
+pub async fn delete_user(state: &State, admin_id: i32, user_id: i32) -> Result<bool, AdminError> {
+    if admin_id == user_id {
+        return Err(AdminError::SelfModificationError);
+    }
+    let mut conn = state.db_pool().await.get().await.context(AdminPoolSnafu)?;
+    conn.transaction::<_, AdminError, _>(|conn| {
+        async move {
+            let owned: i64 = captures::table
+                .filter(captures::owner.eq(user_id))
+                .count()
+                .get_result(conn)
+                .await?;
+            if owned > 0 {
+                return Err(AdminError::OwnsCapturesError);
+            }
+            let admin_role = clicor::GroupRole::Admin.as_str();
+            let led: Vec<i32> = group_members::table
+                .filter(group_members::member.eq(user_id))
+                .filter(group_members::role.eq(admin_role))
+                .select(group_members::group_id)
+                .load(conn)
+                .await?;
+            let co_led: Vec<i32> = group_members::table
+                .filter(group_members::group_id.eq_any(&led))
+                .filter(group_members::role.eq(admin_role))
+                .filter(group_members::member.ne(user_id))
+                .select(group_members::group_id)
+                .load(conn)
+                .await?;
+            if led.iter().any(|g| !co_led.contains(g)) {
+                return Err(AdminError::LastGroupAdminError);
+            }
+            diesel::delete(sessions::table.filter(sessions::owner.eq(user_id)))
+                .execute(conn)
+                .await?;
+            diesel::delete(api_keys::table.filter(api_keys::owner.eq(user_id)))
+                .execute(conn)
+                .await?;
+            let resets = password_resets::table.filter(
+                password_resets::owner
+                    .eq(user_id)
+                    .or(password_resets::creator.eq(user_id)),
+            );
+            diesel::delete(resets).execute(conn).await?;
+            diesel::delete(recovery_codes::table.filter(recovery_codes::owner.eq(user_id)))
+                .execute(conn)
+                .await?;
+            diesel::delete(oidc_identities::table.filter(oidc_identities::owner.eq(user_id)))
+                .execute(conn)
+                .await?;
+            diesel::delete(webhooks::table.filter(webhooks::owner.eq(user_id)))
+                .execute(conn)
+                .await?;
+            diesel::delete(group_members::table.filter(group_members::member.eq(user_id)))
+                .execute(conn)
+                .await?;
+            let schedule_ids: Vec<i32> =
+                diesel::delete(schedules::table.filter(schedules::owner.eq(user_id)))
+                    .returning(schedules::id)
+                    .get_results(conn)
+                    .await?;
+            for id in schedule_ids {
+                queue::dequeue(conn, JobKind::Schedule, id).await?;
+            }
+            diesel::delete(invites::table.filter(invites::creator.eq(user_id)))
+                .execute(conn)
+                .await?;
+            // Captures given to other users since keep existing outside the batch
+            let batch_ids = batches::table
+                .filter(batches::owner.eq(user_id))
+                .select(batches::id.nullable());
+            diesel::update(captures::table.filter(captures::batch.eq_any(batch_ids)))
+                .set(captures::batch.eq(None::<i32>))
+                .execute(conn)
+                .await?;
+            diesel::delete(batches::table.filter(batches::owner.eq(user_id)))
+                .execute(conn)
+                .await?;
+            // Redemption time is kept, so the invite stays spent
+            diesel::update(invites::table.filter(invites::redeemer.eq(user_id)))
+                .set(invites::redeemer.eq(None::<i32>))
+                .execute(conn)
+                .await?;
+            let count = diesel::delete(users::table.filter(users::id.eq(user_id)))
+                .execute(conn)
+                .await?;
+            Ok(count > 0)
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Mint a one-time password reset token for a user, returning it and its expiry
+///
+/// Returns `None` if the user does not exist.
+pub async fn create_password_reset(
+    state: &State,
+    admin_id: i32,
+    user_id: i32,
+) -> Result<Option<(String, chrono::DateTime<chrono::Utc>)>, AdminError> {
+    let mut conn = state.db_pool().await.get().await.context(AdminPoolSnafu)?;
+    let exists: i64 = users::table
+        .filter(users::id.eq(user_id))
+        .count()
+        .get_result(&mut conn)
+        .await
+        .context(AdminQuerySnafu)?;
+    if exists == 0 {
+        return Ok(None);
+    }
+    let token = auth::generate_secret();
+    let now = chrono::Utc::now();
+    let expires = now + PASSWORD_RESET_LIFETIME;
+    let new_reset = InsPasswordReset {
+        token_hash: auth::hash_secret(&token),
+        owner: user_id,
+        creator: admin_id,
+        time_created: now,
+        time_expires: expires,
+    };
+    diesel::insert_into(password_resets::table)
+        .values(new_reset)
+        .execute(&mut conn)
+        .await
+        .context(AdminQuerySnafu)?;
+    Ok(Some((token, expires)))
+}
+
+/// Mint an invite code, returning the code itself
+pub async fn create_invite(
+    state: &State,
+    admin_id: i32,
+    expires: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<String, AdminError> {
+    let code = auth::generate_secret();
+    let new_invite = InsInvite {
+        code_hash: auth::hash_secret(&code),
+        creator: admin_id,
+        time_created: chrono::Utc::now(),
+        time_expires: expires,
+    };
+    let mut conn = state.db_pool().await.get().await.context(AdminPoolSnafu)?;
+    diesel::insert_into(invites::table)
+        .values(new_invite)
+        .execute(&mut conn)
+        .await
+        .context(AdminQuerySnafu)?;
+    Ok(code)
+}
+
+/// Describe every invite, redeemed or not
+pub async fn list_invites(state: &State) -> Result<Vec<clicor::InviteDescription>, AdminError> {
+    let mut conn = state.db_pool().await.get().await.context(AdminPoolSnafu)?;
+    let invites: Vec<DbInvite> = invites::table
+        .order(invites::time_created.desc())
+        .load(&mut conn)
+        .await
+        .context(AdminQuerySnafu)?;
+    let invites = invites
+        .into_iter()
+        .map(|i| {
+            clicor::InviteDescription::new(
+                i.id,
+                i.creator,
+                i.time_created,
+                i.time_expires,
+                i.redeemer,
+                i.time_redeemed,
+            )
+        })
+        .collect();
+    Ok(invites)
+}
@@ -0,0 +1,172 @@
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use log::*;
+use snafu::prelude::*;
+
+use crate::core::models::{DbAuditEvent, InsAuditEvent};
+use crate::core::schema::audit_events;
+use crate::core::state::State;
+use crate::msg::clicor::{self, AuditAction};
+
+/// Page size when a query doesn't ask for one
+const DEFAULT_PAGE_SIZE: i64 = 50;
+
+/// Largest page a query may ask for
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Debug, Snafu)]
+pub enum AuditError {
+    #[snafu(display("Unable to get a database connection"))]
+    AuditPoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Audit query failed"))]
+    AuditQueryError { source: diesel::result::Error },
+}
+
+/// A security-relevant action, built up before being recorded
+#[derive(Debug)]
+pub struct AuditEvent {
+    action: AuditAction,
+    success: bool,
+    actor: Option<i32>,
+    ip: Option<String>,
+    target: Option<String>,
+    detail: Option<String>,
+}
+
+impl AuditEvent {
+    /// Describe a successful action with no further details
+    pub fn new(action: AuditAction) -> Self {
+        Self {
+            action,
+            success: true,
+            actor: None,
+            ip: None,
+            target: None,
+            detail: None,
+        }
+    }
+
+    /// Mark the action as having been refused or failed
+    pub fn failed(mut self) -> Self {
+        self.success = false;
+        self
+    }
+
+    /// User who performed the action, if known
+    pub fn actor(mut self, actor: Option<i32>) -> Self {
+        self.actor = actor;
+        self
+    }
+
+    /// Address the action was requested from, if known
+    pub fn ip(mut self, ip: Option<&str>) -> Self {
+        self.ip = ip.map(|i| i.to_string());
+        self
+    }
+
+    /// What the action was performed on, such as a username or capture UUID
+    pub fn target(mut self, target: impl ToString) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    /// Free-form details, such as why a login failed
+    pub fn detail(mut self, detail: impl ToString) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+}
+
+/// Append an event to the audit log
+///
+/// Failures are logged rather than returned, so that an unavailable audit log
+/// never changes the outcome of the action being recorded.
+pub async fn record(state: &State, event: AuditEvent) {
+    if let Err(e) = try_record(state, event).await {
+        error!("Unable to record audit event: {e}");
+    }
+}
+
+async fn try_record(state: &State, event: AuditEvent) -> Result<(), AuditError> {
+    let mut conn = state.db_pool().await.get().await.context(AuditPoolSnafu)?;
+    let new_event = InsAuditEvent {
+        time_occurred: chrono::Utc::now(),
+        action: event.action.as_str().to_string(),
+        success: event.success,
+        actor: event.actor,
+        ip: event.ip,
+        target: event.target,
+        detail: event.detail,
+    };
+    diesel::insert_into(audit_events::table)
+        .values(new_event)
+        .execute(&mut conn)
+        .await
+        .context(AuditQuerySnafu)?;
+    Ok(())
+}
+
+/// Fetch a page of events matching a query, newest first
+pub async fn query(
+    state: &State,
+    query: &clicor::AuditQuery,
+) -> Result<clicor::AuditPage, AuditError> {
+    let mut conn = state.db_pool().await.get().await.context(AuditPoolSnafu)?;
+    let limit = query
+        .limit()
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let mut events = audit_events::table
+        .order(audit_events::id.desc())
+        .limit(limit + 1)
+        .into_boxed();
+    if let Some(actor) = query.actor() {
+        events = events.filter(audit_events::actor.eq(actor));
+    }
+    if let Some(action) = query.action() {
+        events = events.filter(audit_events::action.eq(action.as_str()));
+    }
+    if let Some(target) = query.target() {
+        events = events.filter(audit_events::target.eq(target.to_string()));
+    }
+    if let Some(success) = query.success() {
+        events = events.filter(audit_events::success.eq(success));
+    }
+    if let Some(since) = query.since() {
+        events = events.filter(audit_events::time_occurred.ge(since));
+    }
+    if let Some(until) = query.until() {
+        events = events.filter(audit_events::time_occurred.lt(until));
+    }
+    if let Some(before) = query.before() {
+        events = events.filter(audit_events::id.lt(before));
+    }
+    let mut events: Vec<DbAuditEvent> = events.load(&mut conn).await.context(AuditQuerySnafu)?;
+    let next = if events.len() as i64 > limit {
+        events.truncate(limit as usize);
+        events.last().map(|e| e.id)
+    } else {
+        None
+    };
+    let events = events
+        .into_iter()
+        .filter_map(|e| {
+            // Actions unknown to this version are skipped rather than misreported
+            let action = e.action.parse().ok()?;
+            Some(clicor::AuditEventDescription::new(
+                e.id,
+                e.time_occurred,
+                action,
+                e.success,
+                e.actor,
+                e.ip,
+                e.target,
+                e.detail,
+            ))
+        })
+        .collect();
+    Ok(clicor::AuditPage::new(events, next))
+}
@@ -0,0 +1,152 @@
+use std::str::FromStr;
+
+use sha2::Digest;
+
+use crate::core::config::PasswordPolicy;
+use crate::msg::clicor::PasswordRequirement;
+
+/// Prefix distinguishing API keys from session tokens
+pub const API_KEY_PREFIX: &str = "wak_";
+
+/// A credential presented by a client
+#[derive(Clone, Debug)]
+pub enum Token {
+    /// Token minted by logging in with a password
+    Session(u128),
+    /// Long-lived key created for automation
+    ApiKey(String),
+}
+
+impl FromStr for Token {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with(API_KEY_PREFIX) {
+            Ok(Token::ApiKey(s.to_string()))
+        } else {
+            s.parse::<u128>().map(Token::Session)
+        }
+    }
+}
+
+/// Generate a random secret suitable for use as a one-off code
+pub fn generate_secret() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+/// Compare two secrets without revealing where they first differ
+pub fn secrets_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Generate a fresh API key
+pub fn generate_api_key() -> String {
+    format!("{API_KEY_PREFIX}{}", generate_secret())
+}
+
+/// Hash a secret for storage, so that a database leak doesn't leak live credentials
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(sha2::Sha256::digest(secret))
+}
+
+/// Reason a password fails the configured policy
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PasswordRejection {
+    TooShort { min_length: usize },
+    TooWeak { missing: Vec<PasswordRequirement> },
+}
+
+/// Check a prospective password against a policy
+pub fn check_password(policy: &PasswordPolicy, password: &str) -> Result<(), PasswordRejection> {
+    if password.chars().count() < policy.min_length() {
+        return Err(PasswordRejection::TooShort {
+            min_length: policy.min_length(),
+        });
+    }
+    let mut missing = Vec::new();
+    let mixed_case =
+        password.chars().any(|c| c.is_lowercase()) && password.chars().any(|c| c.is_uppercase());
+    if policy.require_mixed_case() && !mixed_case {
+        missing.push(PasswordRequirement::MixedCase);
+    }
+    if policy.require_digit() && !password.chars().any(|c| c.is_numeric()) {
+        missing.push(PasswordRequirement::Digit);
+    }
+    if policy.require_symbol() && password.chars().all(|c| c.is_alphanumeric()) {
+        missing.push(PasswordRequirement::Symbol);
+    }
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(PasswordRejection::TooWeak { missing })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strict() -> PasswordPolicy {
+        ron::from_str(
+            "(min_length: 10, require_mixed_case: true, require_digit: true, require_symbol: true)",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn default_policy_only_demands_length() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(
+            check_password(&policy, "short"),
+            Err(PasswordRejection::TooShort { min_length: 8 })
+        );
+        assert_eq!(check_password(&policy, "lowercase"), Ok(()));
+    }
+
+    #[test]
+    fn length_counts_characters_not_bytes() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(
+            check_password(&policy, "ééééééé"),
+            Err(PasswordRejection::TooShort { min_length: 8 })
+        );
+        assert_eq!(check_password(&policy, "éééééééé"), Ok(()));
+    }
+
+    #[test]
+    fn length_is_checked_before_character_classes() {
+        assert_eq!(
+            check_password(&strict(), "abc"),
+            Err(PasswordRejection::TooShort { min_length: 10 })
+        );
+    }
+
+    #[test]
+    fn every_missing_class_is_reported() {
+        assert_eq!(
+            check_password(&strict(), "alllowercase"),
+            Err(PasswordRejection::TooWeak {
+                missing: vec![
+                    PasswordRequirement::MixedCase,
+                    PasswordRequirement::Digit,
+                    PasswordRequirement::Symbol,
+                ]
+            })
+        );
+        assert_eq!(
+            check_password(&strict(), "MixedCase123"),
+            Err(PasswordRejection::TooWeak {
+                missing: vec![PasswordRequirement::Symbol]
+            })
+        );
+    }
+
+    #[test]
+    fn password_meeting_every_requirement_is_accepted() {
+        assert_eq!(check_password(&strict(), "Mixed-Case-123"), Ok(()));
+    }
+}
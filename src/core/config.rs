@@ -10,6 +10,253 @@ pub struct CoreConfig {
     extractors: Vec<(String, String)>,
     workers: Vec<(String, String, url::Url)>,
     storage_path: PathBuf,
+    #[serde(default = "default_session_lifetime")]
+    session_lifetime: u64,
+    #[serde(default)]
+    registration: Registration,
+    #[serde(default)]
+    password_policy: PasswordPolicy,
+    #[serde(default)]
+    login_throttle: LoginThrottle,
+    #[serde(default)]
+    trust_proxy_headers: bool,
+    #[serde(default = "default_secure_cookies")]
+    secure_cookies: bool,
+    #[serde(default)]
+    oidc: Option<OidcConfig>,
+    #[serde(default)]
+    job_queue: JobQueueConfig,
+    #[serde(default)]
+    trash_period: u64,
+}
+
+/// Who may create an account through `/user/create`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum Registration {
+    /// Anyone may register
+    #[default]
+    Open,
+    /// Registration requires an invite code minted by an admin
+    InviteOnly,
+    /// Nobody may register, except the very first user
+    Closed,
+}
+
+fn default_session_lifetime() -> u64 {
+    60 * 60 * 24 * 30
+}
+
+fn default_secure_cookies() -> bool {
+    true
+}
+
+/// Rules which new passwords must satisfy
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    require_mixed_case: bool,
+    require_digit: bool,
+    require_symbol: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            require_mixed_case: false,
+            require_digit: false,
+            require_symbol: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn min_length(&self) -> usize {
+        self.min_length
+    }
+
+    pub fn require_mixed_case(&self) -> bool {
+        self.require_mixed_case
+    }
+
+    pub fn require_digit(&self) -> bool {
+        self.require_digit
+    }
+
+    pub fn require_symbol(&self) -> bool {
+        self.require_symbol
+    }
+}
+
+/// Limits on repeated failed logins, applied per username and per client address
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LoginThrottle {
+    window: u64,
+    free_attempts: u32,
+    base_delay: u64,
+    lockout_threshold: u32,
+    lockout_duration: u64,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        Self {
+            window: 60 * 60,
+            free_attempts: 3,
+            base_delay: 2,
+            lockout_threshold: 10,
+            lockout_duration: 15 * 60,
+        }
+    }
+}
+
+impl LoginThrottle {
+    /// Seconds over which failed attempts are counted
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+
+    /// Failures tolerated before backoff begins
+    pub fn free_attempts(&self) -> u32 {
+        self.free_attempts
+    }
+
+    /// Seconds of backoff after the first penalised failure, doubling with each further one
+    pub fn base_delay(&self) -> u64 {
+        self.base_delay
+    }
+
+    /// Failures after which further attempts are refused outright
+    pub fn lockout_threshold(&self) -> u32 {
+        self.lockout_threshold
+    }
+
+    /// Seconds a lockout lasts after the most recent failure
+    pub fn lockout_duration(&self) -> u64 {
+        self.lockout_duration
+    }
+}
+
+/// How the core works through its queue of background jobs
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct JobQueueConfig {
+    concurrency: usize,
+    visibility_timeout: u64,
+    max_attempts: i32,
+    poll_interval: u64,
+    job_timeout: u64,
+}
+
+impl Default for JobQueueConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 16,
+            visibility_timeout: 5 * 60,
+            max_attempts: 7,
+            poll_interval: 3,
+            job_timeout: 30 * 60,
+        }
+    }
+}
+
+impl JobQueueConfig {
+    /// Jobs which may be worked on at once
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.max(1)
+    }
+
+    /// Seconds a claimed job stays hidden from other runners without a sign of life
+    pub fn visibility_timeout(&self) -> u64 {
+        self.visibility_timeout.max(2)
+    }
+
+    /// Consecutive failed attempts after which a job is given up on
+    pub fn max_attempts(&self) -> i32 {
+        self.max_attempts
+    }
+
+    /// Seconds between progress checks on an extract a worker is producing
+    pub fn poll_interval(&self) -> u64 {
+        self.poll_interval
+    }
+
+    /// Seconds a single attempt at a job may run before it is abandoned and retried
+    pub fn job_timeout(&self) -> u64 {
+        self.job_timeout.max(1)
+    }
+}
+
+/// An OpenID Connect provider users may sign in with
+#[derive(Clone, Debug, Deserialize)]
+pub struct OidcConfig {
+    issuer: String,
+    client_id: String,
+    #[serde(default)]
+    client_secret: Option<String>,
+    redirect_url: url::Url,
+    #[serde(default = "default_oidc_display_name")]
+    display_name: String,
+    #[serde(default = "default_oidc_scopes")]
+    scopes: Vec<String>,
+    #[serde(default = "default_oidc_username_claim")]
+    username_claim: String,
+    #[serde(default)]
+    auto_provision: bool,
+}
+
+fn default_oidc_display_name() -> String {
+    "single sign-on".to_string()
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec!["openid".to_string(), "profile".to_string()]
+}
+
+fn default_oidc_username_claim() -> String {
+    "preferred_username".to_string()
+}
+
+impl OidcConfig {
+    /// Issuer identifier, exactly as the provider reports it; discovery is performed relative to it
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Secret for confidential clients; public clients rely on PKCE alone
+    pub fn client_secret(&self) -> Option<&str> {
+        self.client_secret.as_deref()
+    }
+
+    /// Externally reachable URL of `/auth/oidc/callback`
+    pub fn redirect_url(&self) -> &url::Url {
+        &self.redirect_url
+    }
+
+    /// Provider name shown on the login page
+    pub fn display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    pub fn scopes(&self) -> &[String] {
+        &self.scopes
+    }
+
+    /// ID token claim used as the username of provisioned accounts
+    pub fn username_claim(&self) -> &str {
+        &self.username_claim
+    }
+
+    /// Whether unknown subjects get a new account rather than being refused
+    pub fn auto_provision(&self) -> bool {
+        self.auto_provision
+    }
 }
 
 impl CoreConfig {
@@ -42,6 +289,46 @@ impl CoreConfig {
     pub fn storage_path(&self) -> &Path {
         &self.storage_path
     }
+
+    /// Seconds of inactivity after which a session token expires
+    pub fn session_lifetime(&self) -> u64 {
+        self.session_lifetime
+    }
+
+    pub fn registration(&self) -> Registration {
+        self.registration
+    }
+
+    pub fn password_policy(&self) -> &PasswordPolicy {
+        &self.password_policy
+    }
+
+    pub fn login_throttle(&self) -> &LoginThrottle {
+        &self.login_throttle
+    }
+
+    /// Whether to take client addresses from `Forwarded`/`X-Forwarded-For` headers
+    pub fn trust_proxy_headers(&self) -> bool {
+        self.trust_proxy_headers
+    }
+
+    /// Whether cookies are marked `Secure`; only disable when serving over plain HTTP
+    pub fn secure_cookies(&self) -> bool {
+        self.secure_cookies
+    }
+
+    pub fn oidc(&self) -> Option<&OidcConfig> {
+        self.oidc.as_ref()
+    }
+
+    pub fn job_queue(&self) -> &JobQueueConfig {
+        &self.job_queue
+    }
+
+    /// Seconds a deleted capture is kept in the trash before being removed; 0 removes immediately
+    pub fn trash_period(&self) -> u64 {
+        self.trash_period
+    }
 }
 
 #[derive(Debug, Snafu)]
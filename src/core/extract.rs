@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+
+use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
 use log::*;
 use sha2::Digest;
@@ -5,27 +8,267 @@ use snafu::prelude::*;
 use tokio::io::AsyncWriteExt;
 use tokio_stream::StreamExt;
 
-use crate::{core, core::models::InsExtract, msg::corwrk};
+use crate::{
+    core::models::{DbCapture, DbExtract, DbJob},
+    core::queue::Outcome,
+    core::schema::{captures, extracts},
+    core::state::{CaptureAccess, State},
+    core::webhook,
+    msg::{clicor, corwrk},
+};
 
-pub async fn extract(
-    mut state: actix_web::web::Data<crate::core::state::State>,
-    extractor: String,
-    url: url::Url,
-    db_capid: i32,
-    capture_uuid: uuid::Uuid,
-) {
-    let result = try_extract(&mut state, extractor, url, db_capid, capture_uuid).await;
-    let cm = state.capture_map().await;
-    let new_extract = match result {
-        Ok(r) => {
-            let _ = cm.incr_completed(&capture_uuid).await;
-            r
+/// Times an extract may be sent to a worker before it is given up on
+const MAX_DISPATCHES: i32 = 3;
+
+/// Lifecycle of a single extract, as persisted in `extracts.state`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExtractState {
+    /// Waiting to be sent to a worker
+    Pending,
+    /// Accepted by a worker, which is producing it under a ticket
+    Dispatched,
+    /// Finished by the worker and being fetched from it
+    Downloading,
+    /// Stored under the capture's directory
+    Installed,
+    Failed,
+}
+
+impl ExtractState {
+    /// Persisted states of extracts which still have work ahead of them
+    pub const UNFINISHED: [&'static str; 3] = [
+        ExtractState::Pending.as_str(),
+        ExtractState::Dispatched.as_str(),
+        ExtractState::Downloading.as_str(),
+    ];
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            ExtractState::Pending => "pending",
+            ExtractState::Dispatched => "dispatched",
+            ExtractState::Downloading => "downloading",
+            ExtractState::Installed => "installed",
+            ExtractState::Failed => "failed",
+        }
+    }
+
+    /// Whether no further work will happen on the extract
+    pub fn is_finished(&self) -> bool {
+        matches!(self, ExtractState::Installed | ExtractState::Failed)
+    }
+}
+
+impl std::str::FromStr for ExtractState {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(ExtractState::Pending),
+            "dispatched" => Ok(ExtractState::Dispatched),
+            "downloading" => Ok(ExtractState::Downloading),
+            "installed" => Ok(ExtractState::Installed),
+            "failed" => Ok(ExtractState::Failed),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<ExtractState> for clicor::ExtractPhase {
+    fn from(state: ExtractState) -> Self {
+        match state {
+            ExtractState::Pending => clicor::ExtractPhase::Pending,
+            ExtractState::Dispatched => clicor::ExtractPhase::Dispatched,
+            ExtractState::Downloading => clicor::ExtractPhase::Downloading,
+            ExtractState::Installed => clicor::ExtractPhase::Installed,
+            ExtractState::Failed => clicor::ExtractPhase::Failed,
+        }
+    }
+}
+
+/// Summarise an extract of a capture for clients
+pub fn describe(extract: DbExtract, capture_uuid: &uuid::Uuid) -> clicor::ExtractDescription {
+    let (extract_state, failure) = match extract.state.parse() {
+        Ok(s) => (s, extract.failure),
+        Err(()) => (
+            ExtractState::Failed,
+            Some(format!("unknown state {}", extract.state)),
+        ),
+    };
+    let until = match extract_state.is_finished() {
+        true => extract.time_updated,
+        false => chrono::Utc::now(),
+    };
+    let elapsed = (until - extract.time_created).num_seconds().max(0) as u64;
+    let location = (extract_state == ExtractState::Installed)
+        .then(|| format!("/resource/{capture_uuid}/{}/", extract.extractor));
+    clicor::ExtractDescription::new(
+        extract.extractor,
+        extract_state.into(),
+        failure,
+        extract.worker,
+        extract.time_created,
+        extract.time_updated,
+        elapsed,
+        location,
+    )
+}
+
+/// Why a step of an extract did not succeed
+#[derive(Debug)]
+enum Failure {
+    /// Worth trying again, until the job runs out of attempts
+    Transient(String),
+    /// Trying again would not help
+    Permanent(String),
+}
+
+/// The next piece of work an extract needs, judged from its persisted state
+#[derive(Debug, PartialEq, Eq)]
+enum Step<'a> {
+    /// Send the extract to a worker
+    Dispatch,
+    /// Ask the worker producing the extract whether it is done
+    Poll { worker: &'a str, ticket: uuid::Uuid },
+    /// Download the extract from the worker which produced it
+    Fetch { worker: &'a str, ticket: uuid::Uuid },
+    /// Nothing; the extract is finished
+    Finish,
+    /// The state can't be acted on, so the extract fails
+    GiveUp(String),
+}
+
+/// Decide what an extract needs next
+fn step(extract: &DbExtract) -> Step<'_> {
+    let state = extract.state.parse();
+    let assignment = extract.worker.as_deref().zip(extract.ticket);
+    match (state, assignment) {
+        (Ok(ExtractState::Pending), _) => Step::Dispatch,
+        (Ok(ExtractState::Dispatched), Some((worker, ticket))) => Step::Poll { worker, ticket },
+        (Ok(ExtractState::Downloading), Some((worker, ticket))) => Step::Fetch { worker, ticket },
+        (Ok(ExtractState::Dispatched | ExtractState::Downloading), None) => {
+            Step::GiveUp("dispatched without a worker or ticket".to_string())
+        }
+        (Ok(ExtractState::Installed | ExtractState::Failed), _) => Step::Finish,
+        (Err(()), _) => Step::GiveUp(format!("unknown state {}", extract.state)),
+    }
+}
+
+/// What becomes of an extract's job after one of its steps
+#[derive(Debug)]
+enum Verdict {
+    Settle(Outcome),
+    /// The step failed but may succeed if tried again
+    Retry(String),
+    /// The extract fails for good
+    GiveUp(String),
+}
+
+/// Judge the result of a step, given whether the job has run out of attempts
+fn verdict(result: Result<Outcome, Failure>, exhausted: bool) -> Verdict {
+    match result {
+        Ok(outcome) => Verdict::Settle(outcome),
+        Err(Failure::Transient(reason)) if !exhausted => Verdict::Retry(reason),
+        Err(Failure::Transient(reason)) | Err(Failure::Permanent(reason)) => {
+            Verdict::GiveUp(reason)
+        }
+    }
+}
+
+/// Advance an extract by one step, as the work of a queued job
+///
+/// Each call does a bounded amount of work against a worker — a dispatch, a
+/// single progress check, or a download — and persists the resulting state,
+/// leaving the queue to schedule the next step.
+pub async fn advance(state: &State, job: &DbJob) -> Outcome {
+    let (extract, capture) = match load(state, job.subject).await {
+        Ok(Some(s)) => s,
+        Ok(None) => {
+            debug!(
+                "Extract {} no longer exists, discarding its job",
+                job.subject
+            );
+            return Outcome::Finished;
         }
-        Err(r) => {
-            let _ = cm.incr_failed(&capture_uuid).await;
-            r
+        Err(e) => {
+            error!("Unable to load extract {}: {e}", job.subject);
+            return Outcome::Retry;
+        }
+    };
+    let exhausted = job.attempts >= state.job_queue().config().max_attempts();
+    let result = match step(&extract) {
+        Step::Dispatch => dispatch(state, &extract, &capture).await,
+        Step::Poll { worker, ticket } => {
+            match state.worker_dispatch().describe_worker(worker).await {
+                Some(descriptor) => {
+                    poll(state, &extract, &capture, &descriptor, &ticket, exhausted).await
+                }
+                None => Ok(redispatch(state, &extract, &capture, worker).await),
+            }
         }
+        Step::Fetch { worker, ticket } => {
+            match state.worker_dispatch().describe_worker(worker).await {
+                Some(descriptor) => fetch(state, &extract, &capture, &descriptor, &ticket).await,
+                None => Ok(redispatch(state, &extract, &capture, worker).await),
+            }
+        }
+        Step::Finish => Ok(Outcome::Finished),
+        Step::GiveUp(reason) => Err(Failure::Permanent(reason)),
     };
+    match verdict(result, exhausted) {
+        Verdict::Settle(outcome) => outcome,
+        Verdict::Retry(reason) => {
+            debug!(
+                "Extract {} / {} attempt {} failed: {reason}",
+                extract.extractor, capture.url, job.attempts
+            );
+            Outcome::Retry
+        }
+        Verdict::GiveUp(reason) => give_up(state, &extract, &capture, &reason).await,
+    }
+}
+
+/// Send an extract back to be dispatched, because its worker is no longer configured
+async fn redispatch(
+    state: &State,
+    extract: &DbExtract,
+    capture: &DbCapture,
+    worker: &str,
+) -> Outcome {
+    warn!(
+        "Worker {worker} is no longer configured, re-dispatching {} / {}",
+        extract.extractor, capture.url
+    );
+    let update = extracts::state.eq(ExtractState::Pending.as_str());
+    persist(state, extract.id, update).await;
+    Outcome::Proceed(std::time::Duration::ZERO)
+}
+
+/// Mark an extract as failed, and count it against its capture
+async fn give_up(state: &State, extract: &DbExtract, capture: &DbCapture, reason: &str) -> Outcome {
+    error!(
+        "Extract {} / {} failed: {reason}",
+        extract.extractor, capture.url
+    );
+    let update = (
+        extracts::state.eq(ExtractState::Failed.as_str()),
+        extracts::failure.eq(Some(reason)),
+    );
+    persist(state, extract.id, update).await;
+    let _ = state
+        .capture_map()
+        .await
+        .incr_failed(&capture.uuid, &extract.extractor, reason)
+        .await;
+    webhook::capture_finished(state, capture.id).await;
+    Outcome::Finished
+}
+
+/// Record a change to an extract, along with the time it happened
+async fn persist<U>(state: &State, id: i32, update: U)
+where
+    U: diesel::query_builder::AsChangeset<Target = extracts::table> + Send,
+    U::Changeset: diesel::query_builder::QueryFragment<diesel::pg::Pg> + Send,
+{
     let mut conn = match state.db_pool().await.get().await {
         Ok(c) => c,
         Err(e) => {
@@ -33,192 +276,282 @@ pub async fn extract(
             return;
         }
     };
-    let count = diesel::insert_into(core::schema::extracts::table)
-        .values(new_extract)
+    let result = diesel::update(extracts::table.filter(extracts::id.eq(id)))
+        .set((update, extracts::time_updated.eq(chrono::Utc::now())))
         .execute(&mut conn)
         .await;
-    if count != Ok(1) {
-        error!("Unexpected issue inserting extract: count == {:?}", count);
+    if result != Ok(1) {
+        error!("Unexpected issue updating extract {id}: {result:?}");
     }
 }
 
-async fn try_extract(
-    state: &mut actix_web::web::Data<crate::core::state::State>,
-    extractor: String,
-    url: url::Url,
-    db_capid: i32,
-    capture_uuid: uuid::Uuid,
-) -> Result<InsExtract, InsExtract> {
-    let worker = state
-        .worker_dispatch()
-        .select_worker(&extractor, &url)
-        .await;
-    debug!("Extract task for {extractor} / {url} assigned worker {worker}");
-    let descriptor = state.worker_dispatch().describe_worker(&worker).await;
-    let http = state.http_client();
-    let extract_uuid = uuid::Uuid::new_v4();
-    let failure = InsExtract::new(extract_uuid.clone(), db_capid, extractor.clone(), false);
-    let mut initresp: corwrk::InitiateExtractResponse = corwrk::InitiateExtractResponse::InvalidUrl;
-
-    // Try up to 3 times to initiate
-    for i in 0..3 {
-        match initiate(&http, &extractor, &url, &descriptor).await {
-            Ok(r) => {
-                initresp = r;
-                break;
-            }
-            Err(e) => {
-                error!("POST /extract/create encountered an error: {:?}", e);
-                match i {
-                    0 => tokio::time::sleep(tokio::time::Duration::from_secs(5)).await,
-                    1 => tokio::time::sleep(tokio::time::Duration::from_secs(30)).await,
-                    _ => {
-                        error!("Initiating extract on {extractor} / {url} failed 3 times");
-                        return Err(failure);
-                    }
-                }
-                continue;
-            }
-        }
+/// Load an extract along with the capture it belongs to
+async fn load(state: &State, id: i32) -> Result<Option<(DbExtract, DbCapture)>, String> {
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .map_err(|e| format!("database unavailable: {e}"))?;
+    extracts::table
+        .inner_join(captures::table)
+        .filter(extracts::id.eq(id))
+        .get_result(&mut conn)
+        .await
+        .optional()
+        .map_err(|e| e.to_string())
+}
+
+fn poll_interval(state: &State) -> std::time::Duration {
+    std::time::Duration::from_secs(state.job_queue().config().poll_interval())
+}
+
+/// Ask a worker to start an extract
+async fn dispatch(
+    state: &State,
+    extract: &DbExtract,
+    capture: &DbCapture,
+) -> Result<Outcome, Failure> {
+    let (extractor, url) = (&extract.extractor, &capture.url);
+    if extract.dispatches >= MAX_DISPATCHES {
+        return Err(Failure::Permanent(format!(
+            "dispatched {MAX_DISPATCHES} times without success"
+        )));
     }
-    let ticket = match initresp {
+    let worker = state.worker_dispatch().select_worker(extractor, url).await;
+    debug!("Extract task for {extractor} / {url} assigned worker {worker}");
+    let descriptor = state
+        .worker_dispatch()
+        .describe_worker(&worker)
+        .await
+        .ok_or_else(|| Failure::Permanent(format!("worker {worker} is not configured")))?;
+    let response = initiate(&state.http_client(), extractor, url, &descriptor)
+        .await
+        .map_err(|e| {
+            error!("POST /extract/create encountered an error: {:?}", e);
+            Failure::Transient("worker unreachable".to_string())
+        })?;
+    match response {
         corwrk::InitiateExtractResponse::InvalidUrl => {
             error!("Extracting {extractor} / {url} returned InvalidUrl");
-            return Err(failure);
+            Err(Failure::Permanent("worker rejected the URL".to_string()))
         }
         corwrk::InitiateExtractResponse::InvalidExtractor => {
             error!("Extracting {extractor} / {url} returned InvalidExtractor");
-            return Err(failure);
+            Err(Failure::Permanent(
+                "worker does not support the extractor".to_string(),
+            ))
         }
-        corwrk::InitiateExtractResponse::Initiated { ticket } => ticket,
-    };
+        corwrk::InitiateExtractResponse::Initiated { ticket } => {
+            let update = (
+                extracts::state.eq(ExtractState::Dispatched.as_str()),
+                extracts::worker.eq(Some(&worker)),
+                extracts::ticket.eq(Some(ticket)),
+                extracts::dispatches.eq(extract.dispatches + 1),
+            );
+            persist(state, extract.id, update).await;
+            state
+                .capture_map()
+                .await
+                .extract_dispatched(&capture.uuid, extractor, &worker)
+                .await;
+            Ok(Outcome::Proceed(poll_interval(state)))
+        }
+    }
+}
 
-    // Wait for a final result from the worker
-    let mut abnormal_responses: u32 = 0;
-    loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
-        match progcheck(&http, &descriptor, &ticket).await {
-            Ok(corwrk::QueryExtractProgressResponse::InProgress) => {
-                continue;
-            }
-            Ok(corwrk::QueryExtractProgressResponse::NoSuchExtract) => {
-                debug!("w [{worker}] / e [{extractor}] / u [{url}]: NoSuchExtract");
-                abnormal_responses += 1;
-                if abnormal_responses > 6 {
-                    debug!(
-                        "w [{worker}] / e [{extractor}] / u [{url}]: Too many abnormal responses, bailing"
-                    );
-                    return Err(failure);
-                } else {
-                    tokio::time::sleep(tokio::time::Duration::from_secs(
-                        2_u64.pow(abnormal_responses),
-                    ))
-                    .await;
-                    continue;
-                }
-            }
-            Ok(corwrk::QueryExtractProgressResponse::UnsupportedUrl) => {
-                error!("w [{worker}] / e [{extractor}] / u [{url}]: UnsupportedUrl");
-                return Err(failure);
-            }
-            Ok(corwrk::QueryExtractProgressResponse::Failed) => {
-                error!("w [{worker}] / e [{extractor}] / u [{url}]: Failed");
-                return Err(failure);
-            }
-            Ok(corwrk::QueryExtractProgressResponse::Completed) => {
-                debug!("w [{worker}] / e [{extractor}] / u [{url}]: Completed");
-                break;
-            }
-            Err(e) => {
-                debug!("w [{worker}] / e [{extractor}] / u [{url}]: Err: {e}");
-                abnormal_responses += 1;
-                if abnormal_responses > 6 {
-                    debug!(
-                        "w [{worker}] / e [{extractor}] / u [{url}]: Too many abnormal responses, bailing"
-                    );
-                    return Err(failure);
-                } else {
-                    tokio::time::sleep(tokio::time::Duration::from_secs(
-                        2_u64.pow(abnormal_responses),
-                    ))
-                    .await;
-                    continue;
-                }
-            }
+/// Check once on an extract a worker is producing
+///
+/// Once the job has run out of attempts to get a sensible answer, the worker
+/// is assumed to have lost the ticket and the extract is dispatched again.
+async fn poll(
+    state: &State,
+    extract: &DbExtract,
+    capture: &DbCapture,
+    descriptor: &crate::core::state::WorkerDescriptor,
+    ticket: &uuid::Uuid,
+    exhausted: bool,
+) -> Result<Outcome, Failure> {
+    let (extractor, url) = (&extract.extractor, &capture.url);
+    let abnormal = match progcheck(&state.http_client(), descriptor, ticket).await {
+        Ok(corwrk::QueryExtractProgressResponse::InProgress) => {
+            return Ok(Outcome::Proceed(poll_interval(state)));
         }
+        Ok(corwrk::QueryExtractProgressResponse::Completed) => {
+            debug!("t [{ticket}] / e [{extractor}] / u [{url}]: Completed");
+            let update = extracts::state.eq(ExtractState::Downloading.as_str());
+            persist(state, extract.id, update).await;
+            return Ok(Outcome::Proceed(std::time::Duration::ZERO));
+        }
+        Ok(corwrk::QueryExtractProgressResponse::UnsupportedUrl) => {
+            error!("t [{ticket}] / e [{extractor}] / u [{url}]: UnsupportedUrl");
+            return Err(Failure::Permanent(
+                "extractor does not support the URL (exit 10)".to_string(),
+            ));
+        }
+        Ok(corwrk::QueryExtractProgressResponse::ExtractionFailed) => {
+            error!("t [{ticket}] / e [{extractor}] / u [{url}]: ExtractionFailed");
+            return Err(Failure::Permanent(
+                "extractor failed to extract the URL (exit 11)".to_string(),
+            ));
+        }
+        Ok(corwrk::QueryExtractProgressResponse::Failed) => {
+            error!("t [{ticket}] / e [{extractor}] / u [{url}]: Failed");
+            return Err(Failure::Permanent(
+                "extractor exited abnormally".to_string(),
+            ));
+        }
+        Ok(corwrk::QueryExtractProgressResponse::NoSuchExtract) => "NoSuchExtract".to_string(),
+        Err(e) => format!("Err: {e}"),
+    };
+    debug!("t [{ticket}] / e [{extractor}] / u [{url}]: {abnormal}");
+    if exhausted {
+        debug!(
+            "t [{ticket}] / e [{extractor}] / u [{url}]: Too many abnormal responses, re-dispatching"
+        );
+        let update = extracts::state.eq(ExtractState::Pending.as_str());
+        persist(state, extract.id, update).await;
+        return Ok(Outcome::Proceed(std::time::Duration::ZERO));
     }
+    Err(Failure::Transient(
+        "worker did not report progress".to_string(),
+    ))
+}
+
+/// Download a completed extract, check it against the worker's hash and install it
+async fn fetch(
+    state: &State,
+    extract: &DbExtract,
+    capture: &DbCapture,
+    descriptor: &crate::core::state::WorkerDescriptor,
+    ticket: &uuid::Uuid,
+) -> Result<Outcome, Failure> {
+    let http = state.http_client();
+    let (extractor, capture_uuid) = (&extract.extractor, &capture.uuid);
 
     // Download the completed extract
-    let mut abnormal_downloads: u32 = 0;
-    let (tfn, hash) = loop {
-        let (tf, tfn) = match state.storage_manager().temp_file().await {
-            Ok(a) => a,
-            Err(e) => {
-                error!("Failed to acquire temp file: {e}");
-                return Err(failure);
-            }
-        };
-        match download(&http, &descriptor, &ticket, tf).await {
-            Ok(h) => break (tfn, h),
-            Err(e) => {
-                debug!("w [{worker}] / e [{extractor}] / u [{url}]: download failed: {e}");
-                abnormal_downloads += 1;
-                if abnormal_downloads > 6 {
-                    debug!(
-                        "w [{worker}] / e [{extractor}] / u [{url}]: Too many abnormal downloads, bailing"
-                    );
-                    return Err(failure);
-                } else {
-                    tokio::time::sleep(tokio::time::Duration::from_secs(
-                        2_u64.pow(abnormal_downloads),
-                    ))
-                    .await;
-                    continue;
-                }
-            }
+    let (tf, tfn) = state.storage_manager().temp_file().await.map_err(|e| {
+        error!("Failed to acquire temp file: {e}");
+        Failure::Transient("unable to create temporary file".to_string())
+    })?;
+    let hash = match download(&http, descriptor, ticket, tf).await {
+        Ok(h) => h,
+        Err(e) => {
+            debug!("t [{ticket}] / e [{extractor}]: download failed: {e}");
+            discard_temp(state, &tfn).await;
+            return Err(Failure::Transient("download failed".to_string()));
         }
     };
 
     // Compare observed against server's known hash
-    for i in 0..3 {
-        match validate(&http, &descriptor, &ticket, &hash).await {
-            Ok(true) => {
-                break;
-            }
-            Ok(false) => {
-                error!("POST /extract/confirm returned a conflicting hash");
-                return Err(failure);
-            }
-            Err(e) => {
-                error!("POST /extract/confirm encountered an error: {:?}", e);
-                match i {
-                    0 => tokio::time::sleep(tokio::time::Duration::from_secs(5)).await,
-                    1 => tokio::time::sleep(tokio::time::Duration::from_secs(30)).await,
-                    _ => {
-                        error!("Validating extract {ticket} failed 3 times");
-                        return Err(failure);
-                    }
-                }
-                continue;
-            }
+    match validate(&http, descriptor, ticket, &hash).await {
+        Ok(true) => {}
+        Ok(false) => {
+            error!("POST /extract/confirm returned a conflicting hash");
+            discard_temp(state, &tfn).await;
+            return Err(Failure::Permanent(
+                "downloaded output does not match the worker's hash".to_string(),
+            ));
+        }
+        Err(e) => {
+            error!("POST /extract/confirm encountered an error: {:?}", e);
+            discard_temp(state, &tfn).await;
+            return Err(Failure::Transient(
+                "unable to confirm hash with worker".to_string(),
+            ));
         }
     }
 
     // Install received archive to permanent location
     let install_result = state
         .storage_manager()
-        .install_temp(&tfn, &capture_uuid, &extractor)
+        .install_temp(&tfn, capture_uuid, extractor)
+        .await;
+    if let Err(e) = install_result {
+        error!("Installing tarball {tfn} for {capture_uuid}/{extractor} failed: {e}");
+        return Err(Failure::Permanent("unable to unpack output".to_string()));
+    }
+    let update = extracts::state.eq(ExtractState::Installed.as_str());
+    persist(state, extract.id, update).await;
+    let _ = state
+        .capture_map()
+        .await
+        .incr_completed(capture_uuid, extractor)
+        .await;
+    webhook::capture_finished(state, capture.id).await;
+    Ok(Outcome::Finished)
+}
+
+async fn discard_temp(state: &State, temp_uuid: &uuid::Uuid) {
+    if let Err(e) = state.storage_manager().remove_temp(temp_uuid).await {
+        error!("Unable to remove temporary file {temp_uuid}: {e}");
+    }
+}
+
+/// Restore the progress of every capture left unfinished by a previous run
+///
+/// The extracts themselves are carried on by the job queue; this only
+/// reinstates what the [`CaptureMap`] knew about them.
+///
+/// [`CaptureMap`]: crate::core::state::CaptureMap
+pub async fn recover(state: &State) {
+    if let Err(e) = state.storage_manager().clear_temp().await {
+        error!("Unable to clear stale temporary files: {e}");
+    }
+    let mut conn = match state.db_pool().await.get().await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("db_pool.get() failed, unable to recover extracts: {e}");
+            return;
+        }
+    };
+    let capture_ids: Result<Vec<i32>, _> = extracts::table
+        .filter(extracts::state.eq_any(ExtractState::UNFINISHED))
+        .select(extracts::capture)
+        .distinct()
+        .load(&mut conn)
+        .await;
+    let capture_ids = match capture_ids {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Unable to find unfinished extracts: {e}");
+            return;
+        }
+    };
+    let rows: Result<Vec<(DbExtract, DbCapture)>, _> = extracts::table
+        .inner_join(captures::table)
+        .filter(extracts::capture.eq_any(capture_ids))
+        .load(&mut conn)
         .await;
-    match install_result {
-        Ok(()) => {}
+    let rows = match rows {
+        Ok(r) => r,
         Err(e) => {
-            error!("Installing tarball {tfn} for {capture_uuid}/{extractor} failed: {e}");
-            return Err(failure);
+            error!("Unable to load unfinished extracts: {e}");
+            return;
+        }
+    };
+    let mut progress: HashMap<uuid::Uuid, (clicor::QueryCaptureResponse, CaptureAccess)> =
+        HashMap::new();
+    let mut resumed = 0;
+    for (extract, capture) in rows {
+        let entry = progress.entry(capture.uuid).or_insert((
+            clicor::QueryCaptureResponse::new_from_quantity(0),
+            CaptureAccess::from(&capture),
+        ));
+        match extract.state.parse() {
+            Ok(ExtractState::Installed) => entry.0.add_completed(),
+            Ok(ExtractState::Failed) => entry.0.add_failed(),
+            _ => {
+                entry.0.add_in_progress();
+                resumed += 1;
+            }
         }
     }
-
-    let success = InsExtract::new(extract_uuid.clone(), db_capid, extractor.clone(), true);
-    Ok(success)
+    let cm = state.capture_map().await;
+    for (capture_uuid, (progress, access)) in progress {
+        cm.restore_status(&capture_uuid, progress, access).await;
+    }
+    info!("Resuming {resumed} unfinished extracts");
 }
 
 async fn initiate(
@@ -317,14 +650,108 @@ async fn validate(
 #[derive(Debug, Snafu)]
 enum WebClientError {
     #[snafu(display("reqwest returned an error"))]
-    ReqwestError { source: reqwest::Error },
+    Reqwest { source: reqwest::Error },
 
     #[snafu(display("response could not be deserialized as json"))]
-    JsonError { source: reqwest::Error },
+    Json { source: reqwest::Error },
 
     #[snafu(display("error reading chunk from stream"))]
-    StreamError { source: reqwest::Error },
+    Stream { source: reqwest::Error },
 
     #[snafu(display("error writing to filesystem"))]
-    FilesystemError { source: std::io::Error },
+    Filesystem { source: std::io::Error },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(state: ExtractState, assigned: bool) -> DbExtract {
+        let now = chrono::Utc::now();
+        DbExtract {
+            id: 1,
+            uuid: uuid::Uuid::new_v4(),
+            capture: 1,
+            extractor: "singlefile".to_string(),
+            state: state.as_str().to_string(),
+            worker: assigned.then(|| "w1".to_string()),
+            ticket: assigned.then(uuid::Uuid::nil),
+            dispatches: 0,
+            failure: None,
+            time_created: now,
+            time_updated: now,
+        }
+    }
+
+    #[test]
+    fn pending_extract_is_dispatched() {
+        assert_eq!(step(&extract(ExtractState::Pending, false)), Step::Dispatch);
+    }
+
+    #[test]
+    fn dispatched_extract_is_polled_on_its_worker() {
+        assert_eq!(
+            step(&extract(ExtractState::Dispatched, true)),
+            Step::Poll {
+                worker: "w1",
+                ticket: uuid::Uuid::nil()
+            }
+        );
+    }
+
+    #[test]
+    fn downloading_extract_is_fetched_from_its_worker() {
+        assert_eq!(
+            step(&extract(ExtractState::Downloading, true)),
+            Step::Fetch {
+                worker: "w1",
+                ticket: uuid::Uuid::nil()
+            }
+        );
+    }
+
+    #[test]
+    fn finished_extracts_need_nothing() {
+        assert_eq!(step(&extract(ExtractState::Installed, true)), Step::Finish);
+        assert_eq!(step(&extract(ExtractState::Failed, false)), Step::Finish);
+    }
+
+    #[test]
+    fn unassigned_or_unknown_extracts_give_up() {
+        for state in [ExtractState::Dispatched, ExtractState::Downloading] {
+            assert!(matches!(step(&extract(state, false)), Step::GiveUp(_)));
+        }
+        let mut unknown = extract(ExtractState::Pending, false);
+        unknown.state = "paused".to_string();
+        assert_eq!(
+            step(&unknown),
+            Step::GiveUp("unknown state paused".to_string())
+        );
+    }
+
+    #[test]
+    fn successful_step_settles_with_its_outcome() {
+        let delay = std::time::Duration::from_secs(3);
+        for exhausted in [false, true] {
+            assert!(matches!(
+                verdict(Ok(Outcome::Proceed(delay)), exhausted),
+                Verdict::Settle(Outcome::Proceed(d)) if d == delay
+            ));
+        }
+    }
+
+    #[test]
+    fn transient_failure_is_retried_until_attempts_run_out() {
+        let failure = || Err(Failure::Transient("worker unreachable".to_string()));
+        assert!(matches!(verdict(failure(), false), Verdict::Retry(_)));
+        assert!(
+            matches!(verdict(failure(), true), Verdict::GiveUp(r) if r == "worker unreachable")
+        );
+    }
+
+    #[test]
+    fn permanent_failure_gives_up_at_once() {
+        let failure = Err(Failure::Permanent("hash mismatch".to_string()));
+        assert!(matches!(verdict(failure, false), Verdict::GiveUp(r) if r == "hash mismatch"));
+    }
 }
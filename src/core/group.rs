@@ -0,0 +1,402 @@
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use snafu::prelude::*;
+
+use crate::core::models::{DbCapture, DbGroup, InsGroup, InsGroupMember};
+use crate::core::schema::{group_members, groups, users};
+use crate::core::state::State;
+use crate::msg::clicor::{GroupDescription, GroupRole, MemberDescription};
+
+/// Maximum length in characters of a group's name
+pub const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Debug, Snafu)]
+pub enum GroupError {
+    #[snafu(display("Group names must be 1 to {MAX_NAME_LENGTH} characters"))]
+    InvalidGroupNameError,
+
+    #[snafu(display("Group name is already taken"))]
+    GroupNameTakenError,
+
+    #[snafu(display("No such group"))]
+    NoSuchGroupError,
+
+    #[snafu(display("Insufficient role in group"))]
+    GroupForbiddenError,
+
+    #[snafu(display("No such user"))]
+    GroupNoSuchUserError,
+
+    #[snafu(display("User is not a member of the group"))]
+    NoSuchMemberError,
+
+    #[snafu(display("Group would be left without an admin"))]
+    LastAdminError,
+
+    #[snafu(display("Unable to get a database connection"))]
+    GroupPoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Group query failed"))]
+    GroupQueryError { source: diesel::result::Error },
+}
+
+impl From<diesel::result::Error> for GroupError {
+    fn from(source: diesel::result::Error) -> Self {
+        GroupError::GroupQueryError { source }
+    }
+}
+
+/// Get a user's role in a group, if they are a member
+pub async fn role(
+    conn: &mut AsyncPgConnection,
+    group_id: i32,
+    user_id: i32,
+) -> Result<Option<GroupRole>, diesel::result::Error> {
+    let role: Option<String> = group_members::table
+        .filter(group_members::group_id.eq(group_id))
+        .filter(group_members::member.eq(user_id))
+        .select(group_members::role)
+        .get_result(conn)
+        .await
+        .optional()?;
+    Ok(role.and_then(|r| r.parse().ok()))
+}
+
+/// Determine whether a user may file captures under a group
+///
+/// Admins may file under any group which exists; others must contribute to it.
+pub async fn may_contribute(
+    conn: &mut AsyncPgConnection,
+    group_id: i32,
+    user_id: i32,
+    is_admin: bool,
+) -> Result<bool, diesel::result::Error> {
+    if is_admin {
+        let exists: i64 = groups::table
+            .filter(groups::id.eq(group_id))
+            .count()
+            .get_result(conn)
+            .await?;
+        return Ok(exists > 0);
+    }
+    Ok(role(conn, group_id, user_id).await? >= Some(GroupRole::Contributor))
+}
+
+/// List the IDs of every group a user is a member of
+pub async fn memberships(state: &State, user_id: i32) -> Result<Vec<i32>, GroupError> {
+    let mut conn = state.db_pool().await.get().await.context(GroupPoolSnafu)?;
+    Ok(group_members::table
+        .filter(group_members::member.eq(user_id))
+        .select(group_members::group_id)
+        .load(&mut conn)
+        .await?)
+}
+
+/// Determine whether a capture is public, or owned by or shared through a group with the viewer
+pub async fn can_view(
+    state: &State,
+    capture: &DbCapture,
+    viewer: Option<i32>,
+) -> Result<bool, GroupError> {
+    if capture.public {
+        return Ok(true);
+    }
+    let Some(viewer) = viewer else {
+        return Ok(false);
+    };
+    if capture.owner == viewer {
+        return Ok(true);
+    }
+    let Some(group_id) = capture.group_id else {
+        return Ok(false);
+    };
+    let mut conn = state.db_pool().await.get().await.context(GroupPoolSnafu)?;
+    Ok(role(&mut conn, group_id, viewer).await?.is_some())
+}
+
+/// Determine whether a user may change or delete a capture
+///
+/// That is its owner, an admin, or an admin of the capture's group.
+pub async fn can_modify(
+    state: &State,
+    capture: &DbCapture,
+    user_id: i32,
+) -> Result<bool, GroupError> {
+    if capture.owner == user_id || state.is_admin(user_id).await {
+        return Ok(true);
+    }
+    let Some(group_id) = capture.group_id else {
+        return Ok(false);
+    };
+    let mut conn = state.db_pool().await.get().await.context(GroupPoolSnafu)?;
+    Ok(role(&mut conn, group_id, user_id).await? == Some(GroupRole::Admin))
+}
+
+/// Create a group with its creator as its first admin
+pub async fn create(
+    state: &State,
+    user_id: i32,
+    name: &str,
+) -> Result<GroupDescription, GroupError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(GroupError::InvalidGroupNameError);
+    }
+    let mut conn = state.db_pool().await.get().await.context(GroupPoolSnafu)?;
+    let group = conn
+        .transaction::<_, GroupError, _>(|conn| {
+            async move {
+                let now = chrono::Utc::now();
+                let new_group = InsGroup {
+                    name: name.to_owned(),
+                    time_created: now,
+                };
+                let group: DbGroup = match diesel::insert_into(groups::table)
+                    .values(new_group)
+                    .get_result(conn)
+                    .await
+                {
+                    Ok(g) => g,
+                    Err(diesel::result::Error::DatabaseError(
+                        DatabaseErrorKind::UniqueViolation,
+                        _,
+                    )) => {
+                        return Err(GroupError::GroupNameTakenError);
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                diesel::insert_into(group_members::table)
+                    .values(InsGroupMember {
+                        group_id: group.id,
+                        member: user_id,
+                        role: GroupRole::Admin.as_str().to_owned(),
+                        time_added: now,
+                    })
+                    .execute(conn)
+                    .await?;
+                Ok(group)
+            }
+            .scope_boxed()
+        })
+        .await?;
+    Ok(GroupDescription::new(
+        group.id,
+        group.name,
+        GroupRole::Admin,
+        group.time_created,
+    ))
+}
+
+/// Describe the groups a user is a member of, by name
+pub async fn list(state: &State, user_id: i32) -> Result<Vec<GroupDescription>, GroupError> {
+    let mut conn = state.db_pool().await.get().await.context(GroupPoolSnafu)?;
+    let found: Vec<(DbGroup, String)> = groups::table
+        .inner_join(group_members::table)
+        .filter(group_members::member.eq(user_id))
+        .order(groups::name.asc())
+        .select((groups::all_columns, group_members::role))
+        .load(&mut conn)
+        .await?;
+    Ok(found
+        .into_iter()
+        .filter_map(|(g, role)| {
+            Some(GroupDescription::new(
+                g.id,
+                g.name,
+                role.parse().ok()?,
+                g.time_created,
+            ))
+        })
+        .collect())
+}
+
+/// Delete a group on behalf of one of its admins, leaving its captures with their owners
+pub async fn delete(state: &State, user_id: i32, group_id: i32) -> Result<(), GroupError> {
+    let is_admin = state.is_admin(user_id).await;
+    let mut conn = state.db_pool().await.get().await.context(GroupPoolSnafu)?;
+    require_role(&mut conn, group_id, user_id, is_admin, GroupRole::Admin).await?;
+    diesel::delete(groups::table.filter(groups::id.eq(group_id)))
+        .execute(&mut conn)
+        .await?;
+    Ok(())
+}
+
+/// Describe the members of a group, for any of its members
+pub async fn members(
+    state: &State,
+    user_id: i32,
+    group_id: i32,
+) -> Result<Vec<MemberDescription>, GroupError> {
+    let is_admin = state.is_admin(user_id).await;
+    let mut conn = state.db_pool().await.get().await.context(GroupPoolSnafu)?;
+    require_role(&mut conn, group_id, user_id, is_admin, GroupRole::Viewer).await?;
+    let found: Vec<(String, String, chrono::DateTime<chrono::Utc>)> = group_members::table
+        .inner_join(users::table)
+        .filter(group_members::group_id.eq(group_id))
+        .order(users::username.asc())
+        .select((
+            users::username,
+            group_members::role,
+            group_members::time_added,
+        ))
+        .load(&mut conn)
+        .await?;
+    Ok(found
+        .into_iter()
+        .filter_map(|(username, role, time_added)| {
+            Some(MemberDescription::new(
+                username,
+                role.parse().ok()?,
+                time_added,
+            ))
+        })
+        .collect())
+}
+
+/// Add a user to a group or change their role, on behalf of one of its admins
+pub async fn set_member(
+    state: &State,
+    user_id: i32,
+    group_id: i32,
+    username: &str,
+    new_role: GroupRole,
+) -> Result<(), GroupError> {
+    let is_admin = state.is_admin(user_id).await;
+    let mut conn = state.db_pool().await.get().await.context(GroupPoolSnafu)?;
+    conn.transaction::<_, GroupError, _>(|conn| {
+        async move {
+            lock(conn, group_id).await?;
+            require_role(conn, group_id, user_id, is_admin, GroupRole::Admin).await?;
+            let member: i32 = users::table
+                .filter(users::username.eq(username))
+                .select(users::id)
+                .get_result(conn)
+                .await
+                .optional()?
+                .ok_or(GroupError::GroupNoSuchUserError)?;
+            if new_role != GroupRole::Admin {
+                ensure_other_admin(conn, group_id, member).await?;
+            }
+            diesel::insert_into(group_members::table)
+                .values(InsGroupMember {
+                    group_id,
+                    member,
+                    role: new_role.as_str().to_owned(),
+                    time_added: chrono::Utc::now(),
+                })
+                .on_conflict((group_members::group_id, group_members::member))
+                .do_update()
+                .set(group_members::role.eq(new_role.as_str()))
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Take a user out of a group, on behalf of one of its admins or the user themselves
+pub async fn remove_member(
+    state: &State,
+    user_id: i32,
+    group_id: i32,
+    username: &str,
+) -> Result<(), GroupError> {
+    let is_admin = state.is_admin(user_id).await;
+    let mut conn = state.db_pool().await.get().await.context(GroupPoolSnafu)?;
+    conn.transaction::<_, GroupError, _>(|conn| {
+        async move {
+            lock(conn, group_id).await?;
+            let member: i32 = users::table
+                .filter(users::username.eq(username))
+                .select(users::id)
+                .get_result(conn)
+                .await
+                .optional()?
+                .ok_or(GroupError::GroupNoSuchUserError)?;
+            if member == user_id {
+                require_role(conn, group_id, user_id, is_admin, GroupRole::Viewer).await?;
+            } else {
+                require_role(conn, group_id, user_id, is_admin, GroupRole::Admin).await?;
+            }
+            ensure_other_admin(conn, group_id, member).await?;
+            let removed = diesel::delete(
+                group_members::table
+                    .filter(group_members::group_id.eq(group_id))
+                    .filter(group_members::member.eq(member)),
+            )
+            .execute(conn)
+            .await?;
+            if removed == 0 {
+                return Err(GroupError::NoSuchMemberError);
+            }
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Refuse users below `required` in a group, hiding the group from non-members
+///
+/// Admins of the whole deployment may do anything to any group.
+async fn require_role(
+    conn: &mut AsyncPgConnection,
+    group_id: i32,
+    user_id: i32,
+    is_admin: bool,
+    required: GroupRole,
+) -> Result<(), GroupError> {
+    if is_admin {
+        let exists: i64 = groups::table
+            .filter(groups::id.eq(group_id))
+            .count()
+            .get_result(conn)
+            .await?;
+        return match exists {
+            0 => Err(GroupError::NoSuchGroupError),
+            _ => Ok(()),
+        };
+    }
+    match role(conn, group_id, user_id).await? {
+        None => Err(GroupError::NoSuchGroupError),
+        Some(r) if r < required => Err(GroupError::GroupForbiddenError),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Serialize changes to a group's membership
+async fn lock(conn: &mut AsyncPgConnection, group_id: i32) -> Result<(), GroupError> {
+    groups::table
+        .filter(groups::id.eq(group_id))
+        .select(groups::id)
+        .for_update()
+        .get_result::<i32>(conn)
+        .await
+        .optional()?
+        .ok_or(GroupError::NoSuchGroupError)?;
+    Ok(())
+}
+
+/// Refuse to demote or remove `member` if they are the group's only admin
+async fn ensure_other_admin(
+    conn: &mut AsyncPgConnection,
+    group_id: i32,
+    member: i32,
+) -> Result<(), GroupError> {
+    let admins: Vec<i32> = group_members::table
+        .filter(group_members::group_id.eq(group_id))
+        .filter(group_members::role.eq(GroupRole::Admin.as_str()))
+        .select(group_members::member)
+        .load(conn)
+        .await?;
+    if admins == [member] {
+        return Err(GroupError::LastAdminError);
+    }
+    Ok(())
+}
@@ -1,7 +1,21 @@
 pub mod act;
+pub mod admin;
+pub mod audit;
+pub mod auth;
 pub mod config;
 pub mod extract;
+pub mod group;
 pub mod models;
+pub mod oidc;
+pub mod public;
+pub mod queue;
+pub mod schedule;
 pub mod schema;
+pub mod share;
 pub mod state;
 pub mod task;
+pub mod throttle;
+pub mod timeline;
+pub mod totp;
+pub mod trash;
+pub mod webhook;
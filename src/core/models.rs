@@ -1,6 +1,9 @@
 use super::schema::*;
 use diesel::{Insertable, Queryable};
 
+use super::extract::ExtractState;
+use super::queue::JobKind;
+
 /// Wrapper around `String` for loading `url::Url`s from databases
 pub struct IntermediaryUrl(String);
 
@@ -27,19 +30,29 @@ where
 pub struct DbUser {
     pub id: i32,
     pub username: String,
-    pub passhash: String,
+    pub passhash: Option<String>,
+    pub is_admin: bool,
+    pub disabled: bool,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub totp_last_step: Option<i64>,
 }
 
 #[derive(Debug, Insertable)]
 #[diesel(table_name=users)]
 pub struct InsUser {
     pub username: String,
-    pub passhash: String,
+    pub passhash: Option<String>,
+    pub is_admin: bool,
 }
 
 impl InsUser {
-    pub fn new(username: String, passhash: String) -> Self {
-        Self { username, passhash }
+    pub fn new(username: String, passhash: Option<String>, is_admin: bool) -> Self {
+        Self {
+            username,
+            passhash,
+            is_admin,
+        }
     }
 }
 
@@ -52,6 +65,15 @@ pub struct DbCapture {
     pub time_initiated: chrono::DateTime<chrono::Utc>,
     pub owner: i32,
     pub public: bool,
+    pub callback_url: Option<String>,
+    pub callback_secret: Option<String>,
+    pub time_finished: Option<chrono::DateTime<chrono::Utc>>,
+    pub tracked_url: Option<i32>,
+    pub batch: Option<i32>,
+    pub time_deleted: Option<chrono::DateTime<chrono::Utc>>,
+    pub title: Option<String>,
+    pub notes: Option<String>,
+    pub group_id: Option<i32>,
 }
 
 #[derive(Debug, Insertable)]
@@ -63,6 +85,88 @@ pub struct InsCapture {
     pub time_initiated: chrono::DateTime<chrono::Utc>,
     pub owner: i32,
     pub public: bool,
+    pub callback_url: Option<String>,
+    pub callback_secret: Option<String>,
+    pub tracked_url: Option<i32>,
+    pub batch: Option<i32>,
+    pub group_id: Option<i32>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=capture_tags)]
+pub struct InsCaptureTag {
+    pub capture: i32,
+    pub tag: String,
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbCaptureShare {
+    pub id: i32,
+    pub capture: i32,
+    pub token_hash: String,
+    pub extractor: Option<String>,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_expires: Option<chrono::DateTime<chrono::Utc>>,
+    pub time_last_used: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=capture_shares)]
+pub struct InsCaptureShare {
+    pub capture: i32,
+    pub token_hash: String,
+    pub extractor: Option<String>,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_expires: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbGroup {
+    pub id: i32,
+    pub name: String,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=groups)]
+pub struct InsGroup {
+    pub name: String,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbGroupMember {
+    pub group_id: i32,
+    pub member: i32,
+    pub role: String,
+    pub time_added: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=group_members)]
+pub struct InsGroupMember {
+    pub group_id: i32,
+    pub member: i32,
+    pub role: String,
+    pub time_added: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbBatch {
+    pub id: i32,
+    pub uuid: uuid::Uuid,
+    pub owner: i32,
+    pub public: bool,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=batches)]
+pub struct InsBatch {
+    pub uuid: uuid::Uuid,
+    pub owner: i32,
+    pub public: bool,
+    pub time_created: chrono::DateTime<chrono::Utc>,
 }
 
 #[derive(Debug, Queryable)]
@@ -71,7 +175,13 @@ pub struct DbExtract {
     pub uuid: uuid::Uuid,
     pub capture: i32,
     pub extractor: String,
-    pub success: bool,
+    pub state: String,
+    pub worker: Option<String>,
+    pub ticket: Option<uuid::Uuid>,
+    pub dispatches: i32,
+    pub failure: Option<String>,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_updated: chrono::DateTime<chrono::Utc>,
 }
 
 #[derive(Debug, Insertable)]
@@ -80,16 +190,293 @@ pub struct InsExtract {
     pub uuid: uuid::Uuid,
     pub capture: i32,
     pub extractor: String,
-    pub success: bool,
+    pub state: String,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_updated: chrono::DateTime<chrono::Utc>,
 }
 
 impl InsExtract {
-    pub fn new(uuid: uuid::Uuid, capture: i32, extractor: String, success: bool) -> Self {
+    /// Describe a new extract which has yet to be dispatched
+    pub fn pending(capture: i32, extractor: String) -> Self {
+        let now = chrono::Utc::now();
         Self {
-            uuid,
+            uuid: uuid::Uuid::new_v4(),
             capture,
             extractor,
-            success,
+            state: ExtractState::Pending.as_str().to_string(),
+            time_created: now,
+            time_updated: now,
+        }
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=extract_attempts)]
+pub struct InsExtractAttempt {
+    pub extract: i32,
+    pub worker: Option<String>,
+    pub ticket: Option<uuid::Uuid>,
+    pub dispatches: i32,
+    pub failure: Option<String>,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_finished: chrono::DateTime<chrono::Utc>,
+}
+
+impl InsExtractAttempt {
+    /// Archive the current attempt of an extract before it is tried again
+    pub fn from_extract(extract: &DbExtract) -> Self {
+        Self {
+            extract: extract.id,
+            worker: extract.worker.clone(),
+            ticket: extract.ticket,
+            dispatches: extract.dispatches,
+            failure: extract.failure.clone(),
+            time_created: extract.time_created,
+            time_finished: extract.time_updated,
+        }
+    }
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbJob {
+    pub id: i32,
+    pub kind: String,
+    pub subject: i32,
+    pub priority: i32,
+    pub attempts: i32,
+    pub time_visible: chrono::DateTime<chrono::Utc>,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=jobs)]
+pub struct InsJob {
+    pub kind: String,
+    pub subject: i32,
+    pub priority: i32,
+    pub attempts: i32,
+    pub time_visible: chrono::DateTime<chrono::Utc>,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+}
+
+impl InsJob {
+    /// Describe a job which may be picked up immediately
+    pub fn new(kind: JobKind, subject: i32, priority: i32) -> Self {
+        let now = chrono::Utc::now();
+        Self {
+            kind: kind.as_str().to_string(),
+            subject,
+            priority,
+            attempts: 0,
+            time_visible: now,
+            time_created: now,
         }
     }
+
+    /// Hold the job back until a later time
+    pub fn visible_at(self, time_visible: chrono::DateTime<chrono::Utc>) -> Self {
+        Self {
+            time_visible,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbWebhook {
+    pub id: i32,
+    pub owner: i32,
+    pub url: String,
+    pub secret: String,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=webhooks)]
+pub struct InsWebhook {
+    pub owner: i32,
+    pub url: String,
+    pub secret: String,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbWebhookDelivery {
+    pub id: i32,
+    pub capture: i32,
+    pub webhook: Option<i32>,
+    pub url: String,
+    pub payload: String,
+    pub state: String,
+    pub attempts: i32,
+    pub response_status: Option<i32>,
+    pub error: Option<String>,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_updated: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=webhook_deliveries)]
+pub struct InsWebhookDelivery {
+    pub capture: i32,
+    pub webhook: Option<i32>,
+    pub url: String,
+    pub payload: String,
+    pub state: String,
+    pub attempts: i32,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_updated: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbSession {
+    pub id: i32,
+    pub token_hash: String,
+    pub owner: i32,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_last_used: chrono::DateTime<chrono::Utc>,
+    pub time_expires: chrono::DateTime<chrono::Utc>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=sessions)]
+pub struct InsSession {
+    pub token_hash: String,
+    pub owner: i32,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_last_used: chrono::DateTime<chrono::Utc>,
+    pub time_expires: chrono::DateTime<chrono::Utc>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbApiKey {
+    pub id: i32,
+    pub key_hash: String,
+    pub owner: i32,
+    pub name: String,
+    pub scopes: Vec<Option<String>>,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_expires: Option<chrono::DateTime<chrono::Utc>>,
+    pub time_last_used: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=api_keys)]
+pub struct InsApiKey {
+    pub key_hash: String,
+    pub owner: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_expires: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbInvite {
+    pub id: i32,
+    pub code_hash: String,
+    pub creator: i32,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_expires: Option<chrono::DateTime<chrono::Utc>>,
+    pub redeemer: Option<i32>,
+    pub time_redeemed: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=invites)]
+pub struct InsInvite {
+    pub code_hash: String,
+    pub creator: i32,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_expires: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=password_resets)]
+pub struct InsPasswordReset {
+    pub token_hash: String,
+    pub owner: i32,
+    pub creator: i32,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_expires: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=login_attempts)]
+pub struct InsLoginAttempt {
+    pub username: String,
+    pub ip: Option<String>,
+    pub time_attempted: chrono::DateTime<chrono::Utc>,
+    pub success: bool,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=recovery_codes)]
+pub struct InsRecoveryCode {
+    pub code_hash: String,
+    pub owner: i32,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=oidc_identities)]
+pub struct InsOidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub owner: i32,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbAuditEvent {
+    pub id: i32,
+    pub time_occurred: chrono::DateTime<chrono::Utc>,
+    pub action: String,
+    pub success: bool,
+    pub actor: Option<i32>,
+    pub ip: Option<String>,
+    pub target: Option<String>,
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=audit_events)]
+pub struct InsAuditEvent {
+    pub time_occurred: chrono::DateTime<chrono::Utc>,
+    pub action: String,
+    pub success: bool,
+    pub actor: Option<i32>,
+    pub ip: Option<String>,
+    pub target: Option<String>,
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbSchedule {
+    pub id: i32,
+    pub owner: i32,
+    #[diesel(deserialize_as=IntermediaryUrl)]
+    pub url: url::Url,
+    pub public: bool,
+    pub cron: Option<String>,
+    pub interval_secs: Option<i32>,
+    pub enabled: bool,
+    pub last_capture: Option<i32>,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_next: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=schedules)]
+pub struct InsSchedule {
+    pub owner: i32,
+    #[diesel(serialize_as=String)]
+    pub url: url::Url,
+    pub public: bool,
+    pub cron: Option<String>,
+    pub interval_secs: Option<i32>,
+    pub enabled: bool,
+    pub time_created: chrono::DateTime<chrono::Utc>,
+    pub time_next: chrono::DateTime<chrono::Utc>,
 }
@@ -0,0 +1,756 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use jsonwebtoken::jwk::JwkSet;
+use log::*;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use snafu::prelude::*;
+use tokio::sync::{OnceCell, RwLock};
+
+use crate::core::audit::{self, AuditEvent};
+use crate::core::auth;
+use crate::core::config::OidcConfig;
+use crate::core::models::{InsOidcIdentity, InsUser};
+use crate::core::schema::{oidc_identities, users};
+use crate::core::state::State;
+use crate::msg::clicor::AuditAction;
+
+/// How long a user may take to authenticate with the provider
+const PENDING_LOGIN_LIFETIME: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Snafu)]
+pub enum OidcError {
+    #[snafu(display("OIDC login is not configured"))]
+    OidcNotConfiguredError,
+
+    #[snafu(display("Unable to fetch provider metadata"))]
+    DiscoveryError { source: reqwest::Error },
+
+    #[snafu(display("Provider reports issuer {reported}, expected {expected}"))]
+    IssuerMismatchError { reported: String, expected: String },
+
+    #[snafu(display("Unable to fetch provider keys"))]
+    KeysError { source: reqwest::Error },
+
+    #[snafu(display("Login state is unknown or expired"))]
+    UnknownStateError,
+
+    #[snafu(display("Login was started from a different browser"))]
+    BrowserMismatchError,
+
+    #[snafu(display("Unable to exchange authorization code"))]
+    TokenExchangeError { source: reqwest::Error },
+
+    #[snafu(display("Provider refused authorization code: {status}"))]
+    TokenRejectedError { status: reqwest::StatusCode },
+
+    #[snafu(display("ID token is invalid"))]
+    IdTokenError { source: jsonwebtoken::errors::Error },
+
+    #[snafu(display("ID token is signed with an unknown key"))]
+    UnknownKeyError,
+
+    #[snafu(display("ID token is signed with unexpected algorithm {alg:?}"))]
+    UnexpectedAlgorithmError { alg: jsonwebtoken::Algorithm },
+
+    #[snafu(display("ID token nonce does not match"))]
+    NonceMismatchError,
+
+    #[snafu(display("ID token lacks the {claim} claim"))]
+    MissingUsernameError { claim: String },
+
+    #[snafu(display("No account is associated with this identity"))]
+    NotProvisionedError,
+
+    #[snafu(display("Username {username} is already taken"))]
+    UnavailableUsernameError { username: String },
+
+    #[snafu(display("Identity is already associated with another account"))]
+    IdentityInUseError,
+
+    #[snafu(display("Account is disabled"))]
+    OidcAccountDisabledError,
+
+    #[snafu(display("Unable to get a database connection"))]
+    OidcPoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("OIDC query failed"))]
+    OidcQueryError { source: diesel::result::Error },
+}
+
+impl From<diesel::result::Error> for OidcError {
+    fn from(source: diesel::result::Error) -> Self {
+        OidcError::OidcQueryError { source }
+    }
+}
+
+/// The subset of provider metadata webarc uses
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: url::Url,
+    token_endpoint: url::Url,
+    jwks_uri: url::Url,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    #[serde(flatten)]
+    other: HashMap<String, serde_json::Value>,
+}
+
+/// A login which has been sent to the provider and not yet returned
+#[derive(Debug)]
+struct PendingLogin {
+    verifier: String,
+    nonce: String,
+    binding: String,
+    link_user: Option<i32>,
+    created: Instant,
+}
+
+/// An identity asserted by the provider
+#[derive(Debug)]
+pub struct Identity {
+    pub subject: String,
+    pub username: Option<String>,
+    /// Existing user who started the flow to link this identity to their account
+    pub link_user: Option<i32>,
+}
+
+/// Relying party for the configured OpenID Connect provider
+#[derive(Debug)]
+pub struct OidcClient {
+    config: OidcConfig,
+    http_client: reqwest::Client,
+    metadata: OnceCell<ProviderMetadata>,
+    keys: RwLock<JwkSet>,
+    pending: RwLock<HashMap<String, PendingLogin>>,
+}
+
+/// Random URL-safe string carrying 256 bits
+fn random_string() -> String {
+    URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig, http_client: reqwest::Client) -> Self {
+        Self {
+            config,
+            http_client,
+            metadata: OnceCell::new(),
+            keys: RwLock::new(JwkSet { keys: Vec::new() }),
+            pending: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    /// Fetch provider metadata on first use
+    async fn metadata(&self) -> Result<&ProviderMetadata, OidcError> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer().trim_end_matches('/')
+                );
+                let metadata: ProviderMetadata = self
+                    .http_client
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .context(DiscoverySnafu)?
+                    .json()
+                    .await
+                    .context(DiscoverySnafu)?;
+                ensure!(
+                    metadata.issuer == self.config.issuer(),
+                    IssuerMismatchSnafu {
+                        reported: metadata.issuer,
+                        expected: self.config.issuer(),
+                    }
+                );
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// Start a login, returning the provider URL to send the user to
+    ///
+    /// `binding` is a secret held by the browser, which must be presented again
+    /// on return so that a callback URL can't be replayed in someone else's browser.
+    pub async fn authorization_url(
+        &self,
+        link_user: Option<i32>,
+        binding: &str,
+    ) -> Result<url::Url, OidcError> {
+        let metadata = self.metadata().await?;
+        let state = random_string();
+        let nonce = random_string();
+        let verifier = random_string();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let mut url = metadata.authorization_endpoint.clone();
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", self.config.client_id())
+            .append_pair("redirect_uri", self.config.redirect_url().as_str())
+            .append_pair("scope", &self.config.scopes().join(" "))
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256");
+        let mut pending = self.pending.write().await;
+        pending.retain(|_, p| p.created.elapsed() < PENDING_LOGIN_LIFETIME);
+        pending.insert(
+            state,
+            PendingLogin {
+                verifier,
+                nonce,
+                binding: binding.to_string(),
+                link_user,
+                created: Instant::now(),
+            },
+        );
+        Ok(url)
+    }
+
+    /// Finish a login by redeeming the authorization code the provider returned
+    pub async fn complete(
+        &self,
+        code: &str,
+        state: &str,
+        binding: &str,
+    ) -> Result<Identity, OidcError> {
+        let pending = self
+            .pending
+            .write()
+            .await
+            .remove(state)
+            .filter(|p| p.created.elapsed() < PENDING_LOGIN_LIFETIME)
+            .ok_or(OidcError::UnknownStateError)?;
+        ensure!(
+            auth::secrets_match(&pending.binding, binding),
+            BrowserMismatchSnafu
+        );
+        let metadata = self.metadata().await?;
+        let mut params = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_url().as_str()),
+            ("client_id", self.config.client_id()),
+            ("code_verifier", &pending.verifier),
+        ];
+        if let Some(secret) = self.config.client_secret() {
+            params.push(("client_secret", secret));
+        }
+        let response = self
+            .http_client
+            .post(metadata.token_endpoint.clone())
+            .form(&params)
+            .send()
+            .await
+            .context(TokenExchangeSnafu)?;
+        ensure!(
+            response.status().is_success(),
+            TokenRejectedSnafu {
+                status: response.status()
+            }
+        );
+        let tokens: TokenResponse = response.json().await.context(TokenExchangeSnafu)?;
+        let claims = self.verify_id_token(&tokens.id_token).await?;
+        ensure!(
+            claims.nonce.as_deref() == Some(pending.nonce.as_str()),
+            NonceMismatchSnafu
+        );
+        let username = claims
+            .other
+            .get(self.config.username_claim())
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+        Ok(Identity {
+            subject: claims.sub,
+            username,
+            link_user: pending.link_user,
+        })
+    }
+
+    /// Check an ID token's signature, issuer, audience and expiry
+    ///
+    /// The algorithm is taken from the signing key where the provider states
+    /// one, rather than trusting the token's own header.
+    async fn verify_id_token(&self, id_token: &str) -> Result<IdTokenClaims, OidcError> {
+        let header = jsonwebtoken::decode_header(id_token).context(IdTokenSnafu)?;
+        let (key, key_alg) = match self.find_key(header.kid.as_deref()).await {
+            Some(k) => k,
+            None => {
+                // The provider may have rotated its keys since they were last fetched
+                self.refresh_keys().await?;
+                self.find_key(header.kid.as_deref())
+                    .await
+                    .ok_or(OidcError::UnknownKeyError)?
+            }
+        };
+        let alg = key_alg.unwrap_or(header.alg);
+        ensure!(
+            alg == header.alg && alg.family() != jsonwebtoken::AlgorithmFamily::Hmac,
+            UnexpectedAlgorithmSnafu { alg: header.alg }
+        );
+        let mut validation = jsonwebtoken::Validation::new(alg);
+        validation.set_issuer(&[self.config.issuer()]);
+        validation.set_audience(&[self.config.client_id()]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let data = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .context(IdTokenSnafu)?;
+        Ok(data.claims)
+    }
+
+    /// Look up a cached signing key, by ID if the token names one, along with its algorithm
+    async fn find_key(
+        &self,
+        kid: Option<&str>,
+    ) -> Option<(jsonwebtoken::DecodingKey, Option<jsonwebtoken::Algorithm>)> {
+        let keys = self.keys.read().await;
+        let jwk = match kid {
+            Some(kid) => keys.find(kid)?,
+            None if keys.keys.len() == 1 => &keys.keys[0],
+            None => return None,
+        };
+        let alg = match jwk.common.key_algorithm {
+            Some(a) => Some(a.try_into().ok()?),
+            None => None,
+        };
+        Some((jsonwebtoken::DecodingKey::from_jwk(jwk).ok()?, alg))
+    }
+
+    async fn refresh_keys(&self) -> Result<(), OidcError> {
+        let metadata = self.metadata().await?;
+        let keys: JwkSet = self
+            .http_client
+            .get(metadata.jwks_uri.clone())
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .context(KeysSnafu)?
+            .json()
+            .await
+            .context(KeysSnafu)?;
+        debug!("Fetched {} OIDC signing keys", keys.keys.len());
+        *self.keys.write().await = keys;
+        Ok(())
+    }
+}
+
+/// Resolve a provider identity to a user, linking or provisioning as needed
+///
+/// Logins and provisioned accounts are recorded in the audit log.
+pub async fn login(state: &State, identity: Identity, ip: Option<&str>) -> Result<i32, OidcError> {
+    let target = identity
+        .username
+        .clone()
+        .unwrap_or_else(|| identity.subject.clone());
+    let linking = identity.link_user.is_some();
+    let result = resolve(state, identity).await;
+    if let Ok((user_id, true)) = &result {
+        let event = AuditEvent::new(AuditAction::UserCreate)
+            .actor(Some(*user_id))
+            .ip(ip)
+            .target(&target)
+            .detail("oidc");
+        audit::record(state, event).await;
+    }
+    if !linking {
+        let event = AuditEvent::new(AuditAction::Login).ip(ip).target(&target);
+        let event = match &result {
+            Ok((user_id, _)) => event.actor(Some(*user_id)).detail("oidc"),
+            Err(e) => event.failed().detail(format!("oidc: {e}")),
+        };
+        audit::record(state, event).await;
+    }
+    result.map(|(user_id, _)| user_id)
+}
+
+/// How an identity is to be tied to an account
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Resolution {
+    /// The identity already belongs to this user
+    Known(i32),
+    /// The identity is to be linked to this existing user
+    Link(i32),
+    /// The identity is to be given a newly provisioned account
+    Provision,
+}
+
+/// Decide how an identity maps onto an account, given any user it already belongs
+/// to and any signed-in user who started the flow to link it
+fn resolution(
+    owner: Option<i32>,
+    link_user: Option<i32>,
+    auto_provision: bool,
+) -> Result<Resolution, OidcError> {
+    match (owner, link_user) {
+        (Some(owner), Some(link_user)) if owner != link_user => Err(OidcError::IdentityInUseError),
+        (Some(owner), _) => Ok(Resolution::Known(owner)),
+        (None, Some(link_user)) => Ok(Resolution::Link(link_user)),
+        (None, None) if !auto_provision => Err(OidcError::NotProvisionedError),
+        (None, None) => Ok(Resolution::Provision),
+    }
+}
+
+/// Find or create the user for an identity, and whether they were just provisioned
+///
+/// Disabled accounts are refused before anything is linked to them.
+async fn resolve(state: &State, identity: Identity) -> Result<(i32, bool), OidcError> {
+    let oidc = state.oidc().context(OidcNotConfiguredSnafu)?;
+    let issuer = oidc.config().issuer().to_string();
+    let auto_provision = oidc.config().auto_provision();
+    let username_claim = oidc.config().username_claim().to_string();
+    let mut conn = state.db_pool().await.get().await.context(OidcPoolSnafu)?;
+    let (user_id, provisioned) = conn
+        .transaction::<_, OidcError, _>(|conn| {
+            async move {
+                let owner: Option<i32> = oidc_identities::table
+                    .filter(oidc_identities::issuer.eq(&issuer))
+                    .filter(oidc_identities::subject.eq(&identity.subject))
+                    .select(oidc_identities::owner)
+                    .get_result(conn)
+                    .await
+                    .optional()?;
+                let resolution = resolution(owner, identity.link_user, auto_provision)?;
+                let (user_id, provisioned) = match resolution {
+                    Resolution::Known(user_id) | Resolution::Link(user_id) => {
+                        let disabled: bool = users::table
+                            .filter(users::id.eq(user_id))
+                            .select(users::disabled)
+                            .get_result(conn)
+                            .await?;
+                        ensure!(!disabled, OidcAccountDisabledSnafu);
+                        (user_id, false)
+                    }
+                    Resolution::Provision => {
+                        let username = identity.username.filter(|u| !u.is_empty()).context(
+                            MissingUsernameSnafu {
+                                claim: username_claim,
+                            },
+                        )?;
+                        let user_count: i64 = users::table.count().get_result(conn).await?;
+                        let new_user = InsUser::new(username.clone(), None, user_count == 0);
+                        match diesel::insert_into(users::table)
+                            .values(new_user)
+                            .returning(users::id)
+                            .get_result(conn)
+                            .await
+                        {
+                            Ok(i) => (i, true),
+                            Err(diesel::result::Error::DatabaseError(
+                                DatabaseErrorKind::UniqueViolation,
+                                _,
+                            )) => return Err(OidcError::UnavailableUsernameError { username }),
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                };
+                if let Resolution::Known(_) = resolution {
+                    return Ok((user_id, provisioned));
+                }
+                let new_identity = InsOidcIdentity {
+                    issuer,
+                    subject: identity.subject,
+                    owner: user_id,
+                    time_created: chrono::Utc::now(),
+                };
+                diesel::insert_into(oidc_identities::table)
+                    .values(new_identity)
+                    .execute(conn)
+                    .await?;
+                Ok((user_id, provisioned))
+            }
+            .scope_boxed()
+        })
+        .await?;
+    Ok((user_id, provisioned))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use actix_web::{App, HttpResponse, HttpServer, web};
+    use base64::engine::general_purpose::STANDARD;
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    /// Ed25519 key the mock provider signs ID tokens with, as PKCS#8 DER
+    const SIGNING_KEY: &str = "MC4CAQAwBQYDK2VwBCIEIDBgWQVeevfzmvHXktu3qa96pid2pCAolKigF9LtTjF0";
+
+    /// Public half of `SIGNING_KEY`, as published in the provider's key set
+    const PUBLIC_KEY: &str = "59vgO8xCZAPsL7SGu8gkyPQxH-WJfEg916cFqjvph-c";
+
+    const CLIENT_ID: &str = "webarc-test";
+
+    /// An identity provider serving discovery, keys and a token endpoint on localhost
+    struct MockProvider {
+        issuer: String,
+        id_token: web::Data<Mutex<String>>,
+        client: OidcClient,
+    }
+
+    impl MockProvider {
+        async fn start() -> Self {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let issuer = format!("http://{}", listener.local_addr().unwrap());
+            let metadata = json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{issuer}/authorize"),
+                "token_endpoint": format!("{issuer}/token"),
+                "jwks_uri": format!("{issuer}/jwks"),
+            });
+            let jwks = json!({"keys": [{
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": PUBLIC_KEY,
+                "kid": "test",
+                "alg": "EdDSA",
+                "use": "sig",
+            }]});
+            let id_token = web::Data::new(Mutex::new(String::new()));
+            let served = id_token.clone();
+            let server = HttpServer::new(move || {
+                let metadata = metadata.clone();
+                let jwks = jwks.clone();
+                App::new()
+                    .app_data(served.clone())
+                    .route(
+                        "/.well-known/openid-configuration",
+                        web::get().to(move || {
+                            let metadata = metadata.clone();
+                            async move { HttpResponse::Ok().json(metadata) }
+                        }),
+                    )
+                    .route(
+                        "/jwks",
+                        web::get().to(move || {
+                            let jwks = jwks.clone();
+                            async move { HttpResponse::Ok().json(jwks) }
+                        }),
+                    )
+                    .route(
+                        "/token",
+                        web::post().to(|id_token: web::Data<Mutex<String>>| async move {
+                            let id_token = id_token.lock().unwrap().clone();
+                            HttpResponse::Ok().json(json!({ "id_token": id_token }))
+                        }),
+                    )
+            })
+            .workers(1)
+            .listen(listener)
+            .unwrap()
+            .run();
+            actix_web::rt::spawn(server);
+            let config: OidcConfig = ron::from_str(&format!(
+                r#"(
+                    issuer: "{issuer}",
+                    client_id: "{CLIENT_ID}",
+                    redirect_url: "http://webarc.test/auth/oidc/callback",
+                )"#
+            ))
+            .unwrap();
+            Self {
+                issuer,
+                id_token,
+                client: OidcClient::new(config, reqwest::Client::new()),
+            }
+        }
+
+        /// Start a login from a browser holding `binding`, returning its state and nonce
+        async fn begin(&self, binding: &str) -> (String, String) {
+            let url = self.client.authorization_url(None, binding).await.unwrap();
+            let param = |name: &str| {
+                url.query_pairs()
+                    .find(|(k, _)| k == name)
+                    .map(|(_, v)| v.into_owned())
+                    .unwrap()
+            };
+            (param("state"), param("nonce"))
+        }
+
+        /// Claims of a valid ID token for a login
+        fn claims(&self, nonce: &str) -> Value {
+            json!({
+                "iss": self.issuer,
+                "aud": CLIENT_ID,
+                "sub": "subject-1",
+                "exp": chrono::Utc::now().timestamp() + 300,
+                "nonce": nonce,
+                "preferred_username": "frank",
+            })
+        }
+
+        /// Have the token endpoint hand out an ID token signed with the provider's key
+        fn issue(&self, claims: &Value) {
+            let key = EncodingKey::from_ed_der(&STANDARD.decode(SIGNING_KEY).unwrap());
+            let mut header = Header::new(Algorithm::EdDSA);
+            header.kid = Some("test".to_string());
+            self.issue_with(&header, &key, claims);
+        }
+
+        fn issue_with(&self, header: &Header, key: &EncodingKey, claims: &Value) {
+            *self.id_token.lock().unwrap() = jsonwebtoken::encode(header, claims, key).unwrap();
+        }
+    }
+
+    #[actix_web::test]
+    async fn completes_login_with_valid_id_token() {
+        let provider = MockProvider::start().await;
+        let (state, nonce) = provider.begin("browser").await;
+        provider.issue(&provider.claims(&nonce));
+        let identity = provider
+            .client
+            .complete("code", &state, "browser")
+            .await
+            .unwrap();
+        assert_eq!(identity.subject, "subject-1");
+        assert_eq!(identity.username.as_deref(), Some("frank"));
+        assert_eq!(identity.link_user, None);
+    }
+
+    #[actix_web::test]
+    async fn refuses_state_used_twice() {
+        let provider = MockProvider::start().await;
+        let (state, nonce) = provider.begin("browser").await;
+        provider.issue(&provider.claims(&nonce));
+        provider
+            .client
+            .complete("code", &state, "browser")
+            .await
+            .unwrap();
+        let replay = provider.client.complete("code", &state, "browser").await;
+        assert!(matches!(replay, Err(OidcError::UnknownStateError)));
+    }
+
+    #[actix_web::test]
+    async fn refuses_callback_in_another_browser() {
+        let provider = MockProvider::start().await;
+        let (state, nonce) = provider.begin("browser").await;
+        provider.issue(&provider.claims(&nonce));
+        let result = provider.client.complete("code", &state, "attacker").await;
+        assert!(matches!(result, Err(OidcError::BrowserMismatchError)));
+    }
+
+    #[actix_web::test]
+    async fn refuses_mismatched_nonce() {
+        let provider = MockProvider::start().await;
+        let (state, _) = provider.begin("browser").await;
+        provider.issue(&provider.claims("some other nonce"));
+        let result = provider.client.complete("code", &state, "browser").await;
+        assert!(matches!(result, Err(OidcError::NonceMismatchError)));
+    }
+
+    #[actix_web::test]
+    async fn refuses_token_for_another_client() {
+        let provider = MockProvider::start().await;
+        let (state, nonce) = provider.begin("browser").await;
+        let mut claims = provider.claims(&nonce);
+        claims["aud"] = json!("another-client");
+        provider.issue(&claims);
+        let result = provider.client.complete("code", &state, "browser").await;
+        assert!(matches!(result, Err(OidcError::IdTokenError { .. })));
+    }
+
+    #[actix_web::test]
+    async fn refuses_expired_token() {
+        let provider = MockProvider::start().await;
+        let (state, nonce) = provider.begin("browser").await;
+        let mut claims = provider.claims(&nonce);
+        claims["exp"] = json!(chrono::Utc::now().timestamp() - 3600);
+        provider.issue(&claims);
+        let result = provider.client.complete("code", &state, "browser").await;
+        assert!(matches!(result, Err(OidcError::IdTokenError { .. })));
+    }
+
+    #[actix_web::test]
+    async fn refuses_token_signed_with_another_key() {
+        let provider = MockProvider::start().await;
+        let (state, nonce) = provider.begin("browser").await;
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some("elsewhere".to_string());
+        let key = EncodingKey::from_ed_der(&STANDARD.decode(SIGNING_KEY).unwrap());
+        provider.issue_with(&header, &key, &provider.claims(&nonce));
+        let result = provider.client.complete("code", &state, "browser").await;
+        assert!(matches!(result, Err(OidcError::UnknownKeyError)));
+    }
+
+    #[actix_web::test]
+    async fn refuses_algorithm_chosen_by_token() {
+        let provider = MockProvider::start().await;
+        let (state, nonce) = provider.begin("browser").await;
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("test".to_string());
+        let key = EncodingKey::from_secret(PUBLIC_KEY.as_bytes());
+        provider.issue_with(&header, &key, &provider.claims(&nonce));
+        let result = provider.client.complete("code", &state, "browser").await;
+        assert!(matches!(
+            result,
+            Err(OidcError::UnexpectedAlgorithmError {
+                alg: Algorithm::HS256
+            })
+        ));
+    }
+
+    #[test]
+    fn resolves_known_identity_to_its_owner() {
+        assert_eq!(
+            resolution(Some(3), None, false).unwrap(),
+            Resolution::Known(3)
+        );
+        assert_eq!(
+            resolution(Some(3), Some(3), false).unwrap(),
+            Resolution::Known(3)
+        );
+    }
+
+    #[test]
+    fn refuses_linking_identity_owned_by_someone_else() {
+        assert!(matches!(
+            resolution(Some(3), Some(4), true),
+            Err(OidcError::IdentityInUseError)
+        ));
+    }
+
+    #[test]
+    fn links_new_identity_to_signed_in_user() {
+        assert_eq!(
+            resolution(None, Some(4), false).unwrap(),
+            Resolution::Link(4)
+        );
+    }
+
+    #[test]
+    fn provisions_unknown_identity_only_when_enabled() {
+        assert!(matches!(
+            resolution(None, None, false),
+            Err(OidcError::NotProvisionedError)
+        ));
+        assert_eq!(resolution(None, None, true).unwrap(), Resolution::Provision);
+    }
+}
@@ -0,0 +1,129 @@
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use snafu::prelude::*;
+
+use super::group::{self, GroupError};
+use super::models::{DbCapture, DbExtract};
+use super::schema::{captures, extracts, users};
+use super::state::State;
+use crate::msg::clicor::ExtractDescription;
+
+/// Number of captures on each page of the public listing
+pub const PAGE_SIZE: i64 = 25;
+
+#[derive(Debug, Snafu)]
+pub enum PublicError {
+    #[snafu(display("Unable to get a database connection"))]
+    PublicPoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Public capture query failed"))]
+    PublicQueryError { source: diesel::result::Error },
+
+    #[snafu(display("Unable to check group membership"))]
+    PublicGroupError { source: GroupError },
+}
+
+impl From<diesel::result::Error> for PublicError {
+    fn from(source: diesel::result::Error) -> Self {
+        PublicError::PublicQueryError { source }
+    }
+}
+
+/// A capture as presented to visitors, who may not be signed in
+#[derive(Debug, serde::Serialize)]
+pub struct PublicCapture {
+    capture_id: uuid::Uuid,
+    url: url::Url,
+    title: Option<String>,
+    notes: Option<String>,
+    owner: String,
+    public: bool,
+    time_initiated: chrono::DateTime<chrono::Utc>,
+    time_finished: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl PublicCapture {
+    fn new(capture: DbCapture, owner: String) -> Self {
+        Self {
+            capture_id: capture.uuid,
+            url: capture.url,
+            title: capture.title,
+            notes: capture.notes,
+            owner,
+            public: capture.public,
+            time_initiated: capture.time_initiated,
+            time_finished: capture.time_finished,
+        }
+    }
+}
+
+/// One page of the public listing, most recent first
+#[derive(Debug, serde::Serialize)]
+pub struct PublicPage {
+    page: u32,
+    captures: Vec<PublicCapture>,
+    more: bool,
+}
+
+/// List the public captures on a page of the listing, counting from 1
+pub async fn browse(state: &State, page: u32) -> Result<PublicPage, PublicError> {
+    let page = page.max(1);
+    let mut conn = state.db_pool().await.get().await.context(PublicPoolSnafu)?;
+    let mut found: Vec<(DbCapture, String)> = captures::table
+        .inner_join(users::table)
+        .filter(captures::public.eq(true))
+        .filter(captures::time_deleted.is_null())
+        .order(captures::time_initiated.desc())
+        .offset((page as i64 - 1) * PAGE_SIZE)
+        .limit(PAGE_SIZE + 1)
+        .select((captures::all_columns, users::username))
+        .load(&mut conn)
+        .await?;
+    let more = found.len() as i64 > PAGE_SIZE;
+    found.truncate(PAGE_SIZE as usize);
+    Ok(PublicPage {
+        page,
+        captures: found
+            .into_iter()
+            .map(|(c, owner)| PublicCapture::new(c, owner))
+            .collect(),
+        more,
+    })
+}
+
+/// Describe a capture and its extracts, provided the viewer may see it
+pub async fn landing(
+    state: &State,
+    capture_uuid: &uuid::Uuid,
+    viewer: Option<i32>,
+) -> Result<Option<(PublicCapture, Vec<ExtractDescription>)>, PublicError> {
+    let mut conn = state.db_pool().await.get().await.context(PublicPoolSnafu)?;
+    let found: Option<(DbCapture, String)> = captures::table
+        .inner_join(users::table)
+        .filter(captures::uuid.eq(capture_uuid))
+        .filter(captures::time_deleted.is_null())
+        .select((captures::all_columns, users::username))
+        .get_result(&mut conn)
+        .await
+        .optional()?;
+    let Some((capture, owner)) = found else {
+        return Ok(None);
+    };
+    let allowed = group::can_view(state, &capture, viewer)
+        .await
+        .context(PublicGroupSnafu)?;
+    if !allowed {
+        return Ok(None);
+    }
+    let descriptions = extracts::table
+        .filter(extracts::capture.eq(capture.id))
+        .order(extracts::extractor.asc())
+        .load::<DbExtract>(&mut conn)
+        .await?
+        .into_iter()
+        .map(|e| super::extract::describe(e, capture_uuid))
+        .collect();
+    Ok(Some((PublicCapture::new(capture, owner), descriptions)))
+}
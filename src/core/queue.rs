@@ -0,0 +1,359 @@
+use actix_web::web;
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use log::*;
+use snafu::prelude::*;
+use tokio::sync::Notify;
+
+use super::config::JobQueueConfig;
+use super::extract;
+use super::models::{DbJob, InsJob};
+use super::schedule;
+use super::schema::jobs;
+use super::state::State;
+use super::trash;
+use super::webhook;
+
+/// Priority of work a user is waiting on
+pub const PRIORITY_INTERACTIVE: i32 = 10;
+
+/// Priority of work nobody is actively waiting on
+pub const PRIORITY_BACKGROUND: i32 = 0;
+
+/// How long an idle runner waits before looking for newly visible jobs
+const IDLE_WAIT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Longest delay between retries of a failing job
+const MAX_BACKOFF: u64 = 10 * 60;
+
+#[derive(Debug, Snafu)]
+pub enum QueueError {
+    #[snafu(display("Unable to get a database connection"))]
+    QueuePoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Job queue query failed"))]
+    QueueQueryError { source: diesel::result::Error },
+}
+
+/// Kind of work a job performs, as persisted in `jobs.kind`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobKind {
+    /// Advance the extract whose id is the job's subject
+    Extract,
+    /// Attempt the webhook delivery whose id is the job's subject
+    Webhook,
+    /// Run the schedule whose id is the job's subject
+    Schedule,
+    /// Remove the trashed capture whose id is the job's subject
+    Purge,
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::Extract => "extract",
+            JobKind::Webhook => "webhook",
+            JobKind::Schedule => "schedule",
+            JobKind::Purge => "purge",
+        }
+    }
+}
+
+impl std::str::FromStr for JobKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "extract" => Ok(JobKind::Extract),
+            "webhook" => Ok(JobKind::Webhook),
+            "schedule" => Ok(JobKind::Schedule),
+            "purge" => Ok(JobKind::Purge),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What should become of a job after it has been worked on
+#[derive(Debug)]
+pub enum Outcome {
+    /// Nothing is left to do, so the job is removed
+    Finished,
+    /// Progress was made; run the job again after a delay, with its attempts reset
+    Proceed(std::time::Duration),
+    /// The attempt failed; run the job again after a backoff
+    Retry,
+}
+
+/// Configuration and wakeup signal shared by the queue's runners
+#[derive(Debug)]
+pub struct JobQueue {
+    config: JobQueueConfig,
+    wake: Notify,
+}
+
+impl JobQueue {
+    pub fn new(config: JobQueueConfig) -> Self {
+        Self {
+            config,
+            wake: Notify::new(),
+        }
+    }
+
+    pub fn config(&self) -> &JobQueueConfig {
+        &self.config
+    }
+
+    /// Prompt idle runners to look for work, after jobs have been committed
+    pub fn wake(&self) {
+        self.wake.notify_waiters();
+    }
+}
+
+/// Add a job to the queue
+///
+/// Takes a connection rather than the state so that jobs can be enqueued in
+/// the same transaction as the rows they operate on.
+pub async fn enqueue(
+    conn: &mut AsyncPgConnection,
+    kind: JobKind,
+    subjects: &[i32],
+    priority: i32,
+) -> Result<(), diesel::result::Error> {
+    let new_jobs: Vec<InsJob> = subjects
+        .iter()
+        .map(|s| InsJob::new(kind, *s, priority))
+        .collect();
+    diesel::insert_into(jobs::table)
+        .values(new_jobs)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Add a job to the queue which is not to be picked up before a given time
+pub async fn enqueue_at(
+    conn: &mut AsyncPgConnection,
+    kind: JobKind,
+    subject: i32,
+    priority: i32,
+    time_visible: chrono::DateTime<chrono::Utc>,
+) -> Result<(), diesel::result::Error> {
+    diesel::insert_into(jobs::table)
+        .values(InsJob::new(kind, subject, priority).visible_at(time_visible))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Remove the job for a subject, if any
+pub async fn dequeue(
+    conn: &mut AsyncPgConnection,
+    kind: JobKind,
+    subject: i32,
+) -> Result<(), diesel::result::Error> {
+    diesel::delete(
+        jobs::table
+            .filter(jobs::kind.eq(kind.as_str()))
+            .filter(jobs::subject.eq(subject)),
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Start the configured number of runners working through the queue
+///
+/// Jobs claimed by a core which has since stopped are picked up again once
+/// their claim expires, at most a visibility timeout later.
+pub async fn run(state: web::Data<State>) {
+    let concurrency = state.job_queue().config().concurrency();
+    info!("Starting {concurrency} job runners");
+    for _ in 0..concurrency {
+        tokio::spawn(runner(state.clone()));
+    }
+}
+
+async fn runner(state: web::Data<State>) {
+    loop {
+        match claim(&state).await {
+            Ok(Some(job)) => {
+                let outcome = work(&state, &job).await;
+                if let Err(e) = settle(&state, &job, outcome).await {
+                    error!("Unable to settle job {}: {e}", job.id);
+                }
+            }
+            Ok(None) => {
+                tokio::select! {
+                    _ = state.job_queue().wake.notified() => {}
+                    _ = tokio::time::sleep(IDLE_WAIT) => {}
+                }
+            }
+            Err(e) => {
+                error!("Unable to claim a job: {e}");
+                tokio::time::sleep(IDLE_WAIT).await;
+            }
+        }
+    }
+}
+
+/// Take the most urgent visible job, hiding it from other runners for the visibility timeout
+async fn claim(state: &State) -> Result<Option<DbJob>, QueueError> {
+    let timeout = visibility_timeout(state);
+    let mut conn = state.db_pool().await.get().await.context(QueuePoolSnafu)?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            let now = chrono::Utc::now();
+            let job: Option<DbJob> = jobs::table
+                .filter(jobs::time_visible.le(now))
+                .order((jobs::priority.desc(), jobs::time_visible, jobs::id))
+                .limit(1)
+                .for_update()
+                .skip_locked()
+                .get_result(conn)
+                .await
+                .optional()?;
+            let Some(job) = job else {
+                return Ok(None);
+            };
+            diesel::update(jobs::table.filter(jobs::id.eq(job.id)))
+                .set((
+                    jobs::attempts.eq(jobs::attempts + 1),
+                    jobs::time_visible.eq(now + timeout),
+                ))
+                .get_result(conn)
+                .await
+                .map(Some)
+        }
+        .scope_boxed()
+    })
+    .await
+    .context(QueueQuerySnafu)
+}
+
+/// Work on a job, extending its claim for as long as that takes
+///
+/// An attempt which outlasts the job timeout is abandoned and retried, so a
+/// hung worker can't hold a runner and keep renewing its claim forever.
+async fn work(state: &State, job: &DbJob) -> Outcome {
+    let job_timeout = std::time::Duration::from_secs(state.job_queue().config().job_timeout());
+    let heartbeat = async {
+        let mut interval = tokio::time::interval(visibility_timeout(state).to_std().unwrap() / 2);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = extend_claim(state, job.id).await {
+                error!("Unable to extend claim on job {}: {e}", job.id);
+            }
+        }
+    };
+    let handler = async {
+        match job.kind.parse() {
+            Ok(JobKind::Extract) => extract::advance(state, job).await,
+            Ok(JobKind::Webhook) => webhook::deliver(state, job).await,
+            Ok(JobKind::Schedule) => schedule::run(state, job).await,
+            Ok(JobKind::Purge) => trash::run(state, job).await,
+            Err(()) => {
+                error!("Job {} has unknown kind {}, discarding", job.id, job.kind);
+                Outcome::Finished
+            }
+        }
+    };
+    let attempt = async {
+        tokio::select! {
+            outcome = handler => outcome,
+            _ = heartbeat => Outcome::Retry,
+        }
+    };
+    match tokio::time::timeout(job_timeout, attempt).await {
+        Ok(outcome) => outcome,
+        Err(_) => {
+            warn!(
+                "Job {} ({} {}) timed out after {}s",
+                job.id,
+                job.kind,
+                job.subject,
+                job_timeout.as_secs()
+            );
+            Outcome::Retry
+        }
+    }
+}
+
+async fn extend_claim(state: &State, id: i32) -> Result<(), QueueError> {
+    let visible = chrono::Utc::now() + visibility_timeout(state);
+    let mut conn = state.db_pool().await.get().await.context(QueuePoolSnafu)?;
+    diesel::update(jobs::table.filter(jobs::id.eq(id)))
+        .set(jobs::time_visible.eq(visible))
+        .execute(&mut conn)
+        .await
+        .context(QueueQuerySnafu)?;
+    Ok(())
+}
+
+/// Remove or reschedule a job according to how working on it went
+async fn settle(state: &State, job: &DbJob, outcome: Outcome) -> Result<(), QueueError> {
+    let mut conn = state.db_pool().await.get().await.context(QueuePoolSnafu)?;
+    let target = jobs::table.filter(jobs::id.eq(job.id));
+    let now = chrono::Utc::now();
+    match outcome {
+        Outcome::Finished => {
+            diesel::delete(target)
+                .execute(&mut conn)
+                .await
+                .context(QueueQuerySnafu)?;
+        }
+        Outcome::Proceed(delay) => {
+            let delay = chrono::TimeDelta::from_std(delay).unwrap_or(chrono::TimeDelta::zero());
+            diesel::update(target)
+                .set((jobs::attempts.eq(0), jobs::time_visible.eq(now + delay)))
+                .execute(&mut conn)
+                .await
+                .context(QueueQuerySnafu)?;
+        }
+        Outcome::Retry => {
+            let backoff = backoff(job.attempts);
+            debug!(
+                "Job {} failed attempt {}, retrying in {backoff}s",
+                job.id, job.attempts
+            );
+            diesel::update(target)
+                .set(jobs::time_visible.eq(now + chrono::TimeDelta::seconds(backoff as i64)))
+                .execute(&mut conn)
+                .await
+                .context(QueueQuerySnafu)?;
+        }
+    }
+    Ok(())
+}
+
+/// Seconds to wait before retrying a job which has failed `attempts` times in a row
+fn backoff(attempts: i32) -> u64 {
+    2_u64.saturating_pow(attempts as u32).min(MAX_BACKOFF)
+}
+
+fn visibility_timeout(state: &State) -> chrono::TimeDelta {
+    chrono::TimeDelta::seconds(state.job_queue().config().visibility_timeout() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_with_each_failed_attempt() {
+        assert_eq!(backoff(0), 1);
+        assert_eq!(backoff(1), 2);
+        assert_eq!(backoff(5), 32);
+        assert_eq!(backoff(9), 512);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert_eq!(backoff(10), MAX_BACKOFF);
+        assert_eq!(backoff(64), MAX_BACKOFF);
+        assert_eq!(backoff(i32::MAX), MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,535 @@
+use chrono::SubsecRound;
+use croner::parser::{CronParser, Seconds, Year};
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use log::*;
+use snafu::prelude::*;
+
+use crate::core::act::{self, CreateCaptureError};
+use crate::core::audit::{self, AuditEvent};
+use crate::core::models::{DbJob, DbSchedule, InsSchedule};
+use crate::core::queue::{self, JobKind, Outcome};
+use crate::core::schema::{captures, schedules, users};
+use crate::core::state::State;
+use crate::msg::clicor::{self, AuditAction};
+
+/// Shortest interval allowed between captures of an interval schedule, in seconds
+pub const MIN_INTERVAL: u32 = 60;
+
+#[derive(Debug, Snafu)]
+pub enum ScheduleError {
+    #[snafu(display("Invalid schedule: {reason}"))]
+    InvalidScheduleError { reason: String },
+
+    #[snafu(display("No appropriate extractors for URL"))]
+    ScheduleNoExtractorsError,
+
+    #[snafu(display("No such schedule"))]
+    NoSuchScheduleError,
+
+    #[snafu(display("Unable to get a database connection"))]
+    SchedulePoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Schedule query failed"))]
+    ScheduleQueryError { source: diesel::result::Error },
+}
+
+impl From<diesel::result::Error> for ScheduleError {
+    fn from(source: diesel::result::Error) -> Self {
+        ScheduleError::ScheduleQueryError { source }
+    }
+}
+
+/// How often a schedule fires
+enum Recurrence {
+    Cron(Box<croner::Cron>),
+    Interval(chrono::TimeDelta),
+}
+
+impl Recurrence {
+    /// Interpret a schedule's cron expression or interval, exactly one of which must be given
+    fn parse(cron: Option<&str>, interval: Option<u32>) -> Result<Self, String> {
+        match (cron, interval) {
+            (Some(cron), None) => CronParser::builder()
+                .seconds(Seconds::Disallowed)
+                .year(Year::Disallowed)
+                .build()
+                .parse(cron)
+                .map(|c| Recurrence::Cron(Box::new(c)))
+                .map_err(|e| e.to_string()),
+            (None, Some(interval)) if interval >= MIN_INTERVAL => Ok(Recurrence::Interval(
+                chrono::TimeDelta::seconds(interval as i64),
+            )),
+            (None, Some(_)) => Err(format!("interval must be at least {MIN_INTERVAL}s")),
+            _ => Err("exactly one of cron and interval is required".to_string()),
+        }
+    }
+
+    fn of(schedule: &DbSchedule) -> Result<Self, String> {
+        Self::parse(
+            schedule.cron.as_deref(),
+            schedule.interval_secs.map(|i| i as u32),
+        )
+    }
+
+    /// The first time the schedule fires after `after`
+    fn next_after(
+        &self,
+        after: chrono::DateTime<chrono::Utc>,
+    ) -> Result<chrono::DateTime<chrono::Utc>, String> {
+        match self {
+            Recurrence::Cron(cron) => cron
+                .find_next_occurrence(&after.trunc_subsecs(0), false)
+                .map_err(|e| e.to_string()),
+            Recurrence::Interval(interval) => Ok(after + *interval),
+        }
+    }
+}
+
+/// Register a schedule for a user, returning its description
+///
+/// The first capture is made as soon as the schedule next fires.
+pub async fn create(
+    state: &State,
+    owner: i32,
+    req: &clicor::ScheduleRequest,
+) -> Result<clicor::ScheduleDescription, ScheduleError> {
+    let time_next = validate(state, req).await?;
+    let new_schedule = InsSchedule {
+        owner,
+        url: req.url().clone(),
+        public: req.public(),
+        cron: req.cron().map(str::to_string),
+        interval_secs: req.interval().map(|i| i as i32),
+        enabled: req.enabled(),
+        time_created: chrono::Utc::now(),
+        time_next,
+    };
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(SchedulePoolSnafu)?;
+    let schedule = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let schedule: DbSchedule = diesel::insert_into(schedules::table)
+                    .values(new_schedule)
+                    .get_result(conn)
+                    .await?;
+                if schedule.enabled {
+                    enqueue(conn, &schedule).await?;
+                }
+                Ok(schedule)
+            }
+            .scope_boxed()
+        })
+        .await?;
+    describe(&mut conn, schedule).await
+}
+
+/// Replace the URL, visibility, recurrence and enablement of one of a user's schedules
+pub async fn update(
+    state: &State,
+    owner: i32,
+    id: i32,
+    req: &clicor::ScheduleRequest,
+) -> Result<clicor::ScheduleDescription, ScheduleError> {
+    let time_next = validate(state, req).await?;
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(SchedulePoolSnafu)?;
+    let schedule = conn
+        .transaction::<_, ScheduleError, _>(|conn| {
+            async move {
+                let target = schedules::table
+                    .filter(schedules::id.eq(id))
+                    .filter(schedules::owner.eq(owner));
+                let schedule: DbSchedule = diesel::update(target)
+                    .set((
+                        schedules::url.eq(req.url().as_str()),
+                        schedules::public.eq(req.public()),
+                        schedules::cron.eq(req.cron()),
+                        schedules::interval_secs.eq(req.interval().map(|i| i as i32)),
+                        schedules::enabled.eq(req.enabled()),
+                        schedules::time_next.eq(time_next),
+                    ))
+                    .get_result(conn)
+                    .await
+                    .optional()?
+                    .ok_or(ScheduleError::NoSuchScheduleError)?;
+                queue::dequeue(conn, JobKind::Schedule, schedule.id).await?;
+                if schedule.enabled {
+                    enqueue(conn, &schedule).await?;
+                }
+                Ok(schedule)
+            }
+            .scope_boxed()
+        })
+        .await?;
+    describe(&mut conn, schedule).await
+}
+
+/// Describe all of a user's schedules
+pub async fn list(
+    state: &State,
+    owner: i32,
+) -> Result<Vec<clicor::ScheduleDescription>, ScheduleError> {
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(SchedulePoolSnafu)?;
+    let rows: Vec<(DbSchedule, Option<uuid::Uuid>)> = schedules::table
+        .left_join(captures::table)
+        .filter(schedules::owner.eq(owner))
+        .order(schedules::time_created.desc())
+        .select((schedules::all_columns, captures::uuid.nullable()))
+        .load(&mut conn)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(s, last_capture)| description(s, last_capture))
+        .collect())
+}
+
+/// Remove one of a user's schedules, returning whether it existed
+///
+/// Captures it has already made are kept.
+pub async fn delete(state: &State, owner: i32, id: i32) -> Result<bool, ScheduleError> {
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(SchedulePoolSnafu)?;
+    conn.transaction::<_, ScheduleError, _>(|conn| {
+        async move {
+            let target = schedules::table
+                .filter(schedules::id.eq(id))
+                .filter(schedules::owner.eq(owner));
+            let count = diesel::delete(target).execute(conn).await?;
+            if count > 0 {
+                queue::dequeue(conn, JobKind::Schedule, id).await?;
+            }
+            Ok(count > 0)
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Check a schedule request, returning when it first fires
+async fn validate(
+    state: &State,
+    req: &clicor::ScheduleRequest,
+) -> Result<chrono::DateTime<chrono::Utc>, ScheduleError> {
+    let time_next = Recurrence::parse(req.cron(), req.interval())
+        .and_then(|r| r.next_after(chrono::Utc::now()))
+        .map_err(|reason| ScheduleError::InvalidScheduleError { reason })?;
+    let extractors = state
+        .extractor_map()
+        .await
+        .extractors_for_url(req.url())
+        .await;
+    if extractors.is_empty() {
+        return Err(ScheduleError::ScheduleNoExtractorsError);
+    }
+    Ok(time_next)
+}
+
+async fn enqueue(
+    conn: &mut AsyncPgConnection,
+    schedule: &DbSchedule,
+) -> Result<(), diesel::result::Error> {
+    queue::enqueue_at(
+        conn,
+        JobKind::Schedule,
+        schedule.id,
+        queue::PRIORITY_BACKGROUND,
+        schedule.time_next,
+    )
+    .await
+}
+
+async fn describe(
+    conn: &mut AsyncPgConnection,
+    schedule: DbSchedule,
+) -> Result<clicor::ScheduleDescription, ScheduleError> {
+    let last_capture = match schedule.last_capture {
+        Some(id) => captures::table
+            .filter(captures::id.eq(id))
+            .select(captures::uuid)
+            .get_result(conn)
+            .await
+            .optional()?,
+        None => None,
+    };
+    Ok(description(schedule, last_capture))
+}
+
+fn description(
+    schedule: DbSchedule,
+    last_capture: Option<uuid::Uuid>,
+) -> clicor::ScheduleDescription {
+    clicor::ScheduleDescription::new(
+        schedule.id,
+        schedule.url,
+        schedule.public,
+        schedule.cron,
+        schedule.interval_secs.map(|i| i as u32),
+        schedule.enabled,
+        last_capture,
+        schedule.time_created,
+        schedule.time_next,
+    )
+}
+
+/// Capture a schedule's URL if it is due, then wait for it to fire again
+///
+/// A run is skipped while the capture made by the previous run is still in
+/// progress, or while the schedule's owner is disabled.
+pub async fn run(state: &State, job: &DbJob) -> Outcome {
+    let schedule = match load(state, job.subject).await {
+        Ok(Some(s)) if s.enabled => s,
+        Ok(_) => return Outcome::Finished,
+        Err(e) => {
+            error!("Unable to load schedule {}: {e}", job.subject);
+            return Outcome::Retry;
+        }
+    };
+    let now = chrono::Utc::now();
+    if schedule.time_next > now {
+        return Outcome::Proceed((schedule.time_next - now).to_std().unwrap_or_default());
+    }
+    let recurrence = match Recurrence::of(&schedule) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Schedule {} is invalid, discarding: {e}", schedule.id);
+            return Outcome::Finished;
+        }
+    };
+    let capture = match is_blocked(state, &schedule).await {
+        Ok(Some(reason)) => {
+            info!("Skipping run of schedule {}: {reason}", schedule.id);
+            None
+        }
+        Ok(None) => match capture(state, &schedule).await {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Unable to capture for schedule {}: {e}", schedule.id);
+                return Outcome::Retry;
+            }
+        },
+        Err(e) => {
+            error!("Unable to check schedule {}: {e}", schedule.id);
+            return Outcome::Retry;
+        }
+    };
+    // Runs missed while the core was down are not made up
+    let next = match recurrence.next_after(schedule.time_next) {
+        Ok(n) if n <= now => recurrence.next_after(now),
+        next => next,
+    };
+    let next = match next {
+        Ok(n) => n,
+        Err(e) => {
+            error!("Schedule {} will not fire again: {e}", schedule.id);
+            return Outcome::Finished;
+        }
+    };
+    match reschedule(state, schedule.id, capture, next).await {
+        Ok(()) => Outcome::Proceed((next - now).to_std().unwrap_or_default()),
+        Err(e) => {
+            error!("Unable to reschedule schedule {}: {e}", schedule.id);
+            Outcome::Retry
+        }
+    }
+}
+
+async fn load(state: &State, id: i32) -> Result<Option<DbSchedule>, ScheduleError> {
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(SchedulePoolSnafu)?;
+    Ok(schedules::table
+        .filter(schedules::id.eq(id))
+        .get_result(&mut conn)
+        .await
+        .optional()?)
+}
+
+/// Why a schedule may not capture right now, if anything
+async fn is_blocked(
+    state: &State,
+    schedule: &DbSchedule,
+) -> Result<Option<&'static str>, ScheduleError> {
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(SchedulePoolSnafu)?;
+    let disabled: bool = users::table
+        .filter(users::id.eq(schedule.owner))
+        .select(users::disabled)
+        .get_result(&mut conn)
+        .await?;
+    if disabled {
+        return Ok(Some("owner is disabled"));
+    }
+    let Some(last_capture) = schedule.last_capture else {
+        return Ok(None);
+    };
+    let unfinished: i64 = captures::table
+        .filter(captures::id.eq(last_capture))
+        .filter(captures::time_finished.is_null())
+        .count()
+        .get_result(&mut conn)
+        .await?;
+    Ok((unfinished > 0).then_some("previous capture is still in progress"))
+}
+
+/// Create a capture of the schedule's URL
+///
+/// Returns `None` if the URL no longer has any appropriate extractors.
+async fn capture(
+    state: &State,
+    schedule: &DbSchedule,
+) -> Result<Option<uuid::Uuid>, CreateCaptureError> {
+    let result = act::create_capture(
+        schedule.url.clone(),
+        schedule.owner,
+        schedule.public,
+        None,
+        None,
+        queue::PRIORITY_BACKGROUND,
+        state,
+    )
+    .await;
+    let capture_uuid = match result {
+        Ok(u) => u,
+        Err(CreateCaptureError::NoAppropriateExtractorsError) => {
+            warn!(
+                "Schedule {} has no appropriate extractors for {}",
+                schedule.id, schedule.url
+            );
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+    let event = AuditEvent::new(AuditAction::CaptureCreate)
+        .actor(Some(schedule.owner))
+        .target(capture_uuid)
+        .detail(&schedule.url);
+    audit::record(state, event).await;
+    Ok(Some(capture_uuid))
+}
+
+/// Record a run of a schedule and when it next fires
+async fn reschedule(
+    state: &State,
+    id: i32,
+    capture: Option<uuid::Uuid>,
+    next: chrono::DateTime<chrono::Utc>,
+) -> Result<(), ScheduleError> {
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(SchedulePoolSnafu)?;
+    let target = schedules::table.filter(schedules::id.eq(id));
+    match capture {
+        Some(capture) => {
+            let capture_id = captures::table
+                .filter(captures::uuid.eq(capture))
+                .select(captures::id.nullable())
+                .single_value();
+            diesel::update(target)
+                .set((
+                    schedules::last_capture.eq(capture_id),
+                    schedules::time_next.eq(next),
+                ))
+                .execute(&mut conn)
+                .await?
+        }
+        None => {
+            diesel::update(target)
+                .set(schedules::time_next.eq(next))
+                .execute(&mut conn)
+                .await?
+        }
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(hour: u32, minute: u32, second: u32) -> chrono::DateTime<chrono::Utc> {
+        chrono::Utc
+            .with_ymd_and_hms(2024, 3, 15, hour, minute, second)
+            .unwrap()
+    }
+
+    fn next(
+        cron: Option<&str>,
+        interval: Option<u32>,
+        after: chrono::DateTime<chrono::Utc>,
+    ) -> chrono::DateTime<chrono::Utc> {
+        Recurrence::parse(cron, interval)
+            .unwrap()
+            .next_after(after)
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_requires_exactly_one_of_cron_and_interval() {
+        assert!(Recurrence::parse(None, None).is_err());
+        assert!(Recurrence::parse(Some("0 * * * *"), Some(3600)).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_short_intervals() {
+        assert!(Recurrence::parse(None, Some(MIN_INTERVAL - 1)).is_err());
+        assert!(Recurrence::parse(None, Some(MIN_INTERVAL)).is_ok());
+    }
+
+    #[test]
+    fn parse_rejects_seconds_years_and_garbage() {
+        assert!(Recurrence::parse(Some("0 0 * * * *"), None).is_err());
+        assert!(Recurrence::parse(Some("0 0 1 1 * 2030"), None).is_err());
+        assert!(Recurrence::parse(Some("every tuesday"), None).is_err());
+    }
+
+    #[test]
+    fn interval_fires_one_interval_later() {
+        assert_eq!(next(None, Some(90), at(10, 0, 0)), at(10, 1, 30));
+    }
+
+    #[test]
+    fn cron_fires_strictly_after() {
+        assert_eq!(next(Some("30 * * * *"), None, at(10, 0, 0)), at(10, 30, 0));
+        assert_eq!(next(Some("30 * * * *"), None, at(10, 30, 0)), at(11, 30, 0));
+    }
+
+    #[test]
+    fn cron_ignores_fractional_seconds() {
+        let after = at(10, 30, 0) + chrono::TimeDelta::milliseconds(250);
+        assert_eq!(next(Some("30 * * * *"), None, after), at(11, 30, 0));
+    }
+}
@@ -1,5 +1,60 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (id) {
+        id -> Int4,
+        key_hash -> Text,
+        owner -> Int4,
+        name -> Text,
+        scopes -> Array<Nullable<Text>>,
+        time_created -> Timestamptz,
+        time_expires -> Nullable<Timestamptz>,
+        time_last_used -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    audit_events (id) {
+        id -> Int4,
+        time_occurred -> Timestamptz,
+        action -> Text,
+        success -> Bool,
+        actor -> Nullable<Int4>,
+        ip -> Nullable<Text>,
+        target -> Nullable<Text>,
+        detail -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    batches (id) {
+        id -> Int4,
+        uuid -> Uuid,
+        owner -> Int4,
+        public -> Bool,
+        time_created -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    capture_shares (id) {
+        id -> Int4,
+        capture -> Int4,
+        token_hash -> Text,
+        extractor -> Nullable<Text>,
+        time_created -> Timestamptz,
+        time_expires -> Nullable<Timestamptz>,
+        time_last_used -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    capture_tags (capture, tag) {
+        capture -> Int4,
+        tag -> Text,
+    }
+}
+
 diesel::table! {
     captures (id) {
         id -> Int4,
@@ -8,6 +63,28 @@ diesel::table! {
         time_initiated -> Timestamptz,
         owner -> Int4,
         public -> Bool,
+        callback_url -> Nullable<Text>,
+        callback_secret -> Nullable<Text>,
+        time_finished -> Nullable<Timestamptz>,
+        tracked_url -> Nullable<Int4>,
+        batch -> Nullable<Int4>,
+        time_deleted -> Nullable<Timestamptz>,
+        title -> Nullable<Text>,
+        notes -> Nullable<Text>,
+        group_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    extract_attempts (id) {
+        id -> Int4,
+        extract -> Int4,
+        worker -> Nullable<Text>,
+        ticket -> Nullable<Uuid>,
+        dispatches -> Int4,
+        failure -> Nullable<Text>,
+        time_created -> Timestamptz,
+        time_finished -> Timestamptz,
     }
 }
 
@@ -17,19 +94,212 @@ diesel::table! {
         uuid -> Uuid,
         capture -> Int4,
         extractor -> Text,
+        state -> Text,
+        worker -> Nullable<Text>,
+        ticket -> Nullable<Uuid>,
+        dispatches -> Int4,
+        failure -> Nullable<Text>,
+        time_created -> Timestamptz,
+        time_updated -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    group_members (group_id, member) {
+        group_id -> Int4,
+        member -> Int4,
+        role -> Text,
+        time_added -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    groups (id) {
+        id -> Int4,
+        name -> Text,
+        time_created -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    invites (id) {
+        id -> Int4,
+        code_hash -> Text,
+        creator -> Int4,
+        time_created -> Timestamptz,
+        time_expires -> Nullable<Timestamptz>,
+        redeemer -> Nullable<Int4>,
+        time_redeemed -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    jobs (id) {
+        id -> Int4,
+        kind -> Text,
+        subject -> Int4,
+        priority -> Int4,
+        attempts -> Int4,
+        time_visible -> Timestamptz,
+        time_created -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    login_attempts (id) {
+        id -> Int4,
+        username -> Text,
+        ip -> Nullable<Text>,
+        time_attempted -> Timestamptz,
         success -> Bool,
     }
 }
 
+diesel::table! {
+    oidc_identities (id) {
+        id -> Int4,
+        issuer -> Text,
+        subject -> Text,
+        owner -> Int4,
+        time_created -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    password_resets (id) {
+        id -> Int4,
+        token_hash -> Text,
+        owner -> Int4,
+        creator -> Int4,
+        time_created -> Timestamptz,
+        time_expires -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    recovery_codes (id) {
+        id -> Int4,
+        code_hash -> Text,
+        owner -> Int4,
+    }
+}
+
+diesel::table! {
+    schedules (id) {
+        id -> Int4,
+        owner -> Int4,
+        url -> Text,
+        public -> Bool,
+        cron -> Nullable<Text>,
+        interval_secs -> Nullable<Int4>,
+        enabled -> Bool,
+        last_capture -> Nullable<Int4>,
+        time_created -> Timestamptz,
+        time_next -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Int4,
+        token_hash -> Text,
+        owner -> Int4,
+        time_created -> Timestamptz,
+        time_last_used -> Timestamptz,
+        time_expires -> Timestamptz,
+        user_agent -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    tracked_urls (id) {
+        id -> Int4,
+        url -> Text,
+        time_created -> Timestamptz,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Int4,
         username -> Text,
-        passhash -> Text,
+        passhash -> Nullable<Text>,
+        is_admin -> Bool,
+        disabled -> Bool,
+        totp_secret -> Nullable<Text>,
+        totp_enabled -> Bool,
+        totp_last_step -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Int4,
+        capture -> Int4,
+        webhook -> Nullable<Int4>,
+        url -> Text,
+        payload -> Text,
+        state -> Text,
+        attempts -> Int4,
+        response_status -> Nullable<Int4>,
+        error -> Nullable<Text>,
+        time_created -> Timestamptz,
+        time_updated -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    webhooks (id) {
+        id -> Int4,
+        owner -> Int4,
+        url -> Text,
+        secret -> Text,
+        time_created -> Timestamptz,
     }
 }
 
+diesel::joinable!(api_keys -> users (owner));
+diesel::joinable!(batches -> users (owner));
+diesel::joinable!(capture_shares -> captures (capture));
+diesel::joinable!(capture_tags -> captures (capture));
+diesel::joinable!(captures -> batches (batch));
+diesel::joinable!(captures -> groups (group_id));
+diesel::joinable!(captures -> tracked_urls (tracked_url));
 diesel::joinable!(captures -> users (owner));
+diesel::joinable!(extract_attempts -> extracts (extract));
 diesel::joinable!(extracts -> captures (capture));
+diesel::joinable!(group_members -> groups (group_id));
+diesel::joinable!(group_members -> users (member));
+diesel::joinable!(oidc_identities -> users (owner));
+diesel::joinable!(recovery_codes -> users (owner));
+diesel::joinable!(schedules -> captures (last_capture));
+diesel::joinable!(schedules -> users (owner));
+diesel::joinable!(sessions -> users (owner));
+diesel::joinable!(webhook_deliveries -> captures (capture));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook));
+diesel::joinable!(webhooks -> users (owner));
 
-diesel::allow_tables_to_appear_in_same_query!(captures, extracts, users,);
+diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_events,
+    batches,
+    capture_shares,
+    capture_tags,
+    captures,
+    extract_attempts,
+    extracts,
+    group_members,
+    groups,
+    invites,
+    jobs,
+    login_attempts,
+    oidc_identities,
+    password_resets,
+    recovery_codes,
+    schedules,
+    sessions,
+    tracked_urls,
+    users,
+    webhook_deliveries,
+    webhooks,
+);
@@ -0,0 +1,282 @@
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use log::*;
+use snafu::prelude::*;
+
+use crate::core::auth;
+use crate::core::group::{self, GroupError};
+use crate::core::models::{DbCapture, DbCaptureShare, InsCaptureShare};
+use crate::core::schema::{capture_shares, captures, extracts};
+use crate::core::state::State;
+use crate::msg::clicor;
+
+#[derive(Debug, Snafu)]
+pub enum ShareError {
+    #[snafu(display("No such capture"))]
+    ShareNoSuchCaptureError,
+
+    #[snafu(display("Only the capture's owner, an admin or an admin of its group may share it"))]
+    ShareForbiddenError,
+
+    #[snafu(display("Capture has no extract by that extractor"))]
+    ShareNoSuchExtractorError,
+
+    #[snafu(display("Expiry is in the past"))]
+    AlreadyExpiredError,
+
+    #[snafu(display("Unable to check group membership"))]
+    ShareGroupError { source: GroupError },
+
+    #[snafu(display("Unable to get a database connection"))]
+    SharePoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Share query failed"))]
+    ShareQueryError { source: diesel::result::Error },
+}
+
+impl From<diesel::result::Error> for ShareError {
+    fn from(source: diesel::result::Error) -> Self {
+        ShareError::ShareQueryError { source }
+    }
+}
+
+/// A newly created share, whose token is not stored anywhere
+#[derive(Debug)]
+pub struct NewShare {
+    pub id: i32,
+    pub token: String,
+    /// Where the shared resources can be viewed with the token
+    pub location: String,
+}
+
+/// A live share resolved from its token
+#[derive(Debug)]
+pub struct Share {
+    pub id: i32,
+    pub capture_uuid: uuid::Uuid,
+    pub extractor: Option<String>,
+}
+
+impl Share {
+    /// Whether the share covers a path within its capture
+    ///
+    /// Paths that could climb out of the capture, or out of the shared
+    /// extractor's files, are never covered.
+    pub fn allows(&self, tail: &std::path::Path) -> bool {
+        if !tail
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+        {
+            return false;
+        }
+        match &self.extractor {
+            Some(extractor) => tail.starts_with(extractor),
+            None => true,
+        }
+    }
+}
+
+/// Create a share token for a capture on behalf of someone who may modify it
+pub async fn create(
+    state: &State,
+    capture_uuid: &uuid::Uuid,
+    user_id: i32,
+    request: &clicor::CreateShareRequest,
+) -> Result<NewShare, ShareError> {
+    if request.expires().is_some_and(|e| *e <= chrono::Utc::now()) {
+        return Err(ShareError::AlreadyExpiredError);
+    }
+    let capture = load_shareable(state, capture_uuid, user_id).await?;
+    let mut conn = state.db_pool().await.get().await.context(SharePoolSnafu)?;
+    if let Some(extractor) = request.extractor() {
+        let extracted: i64 = extracts::table
+            .filter(extracts::capture.eq(capture.id))
+            .filter(extracts::extractor.eq(extractor))
+            .count()
+            .get_result(&mut conn)
+            .await?;
+        if extracted == 0 {
+            return Err(ShareError::ShareNoSuchExtractorError);
+        }
+    }
+
+    let token = auth::generate_secret();
+    let new_share = InsCaptureShare {
+        capture: capture.id,
+        token_hash: auth::hash_secret(&token),
+        extractor: request.extractor().map(str::to_owned),
+        time_created: chrono::Utc::now(),
+        time_expires: request.expires().cloned(),
+    };
+    let id = diesel::insert_into(capture_shares::table)
+        .values(new_share)
+        .returning(capture_shares::id)
+        .get_result(&mut conn)
+        .await?;
+    let location = match request.extractor() {
+        Some(extractor) => format!("/share/{token}/{extractor}/"),
+        None => format!("/share/{token}/"),
+    };
+    Ok(NewShare {
+        id,
+        token,
+        location,
+    })
+}
+
+/// Describe the shares of a capture, newest first
+pub async fn list(
+    state: &State,
+    capture_uuid: &uuid::Uuid,
+    user_id: i32,
+) -> Result<Vec<clicor::ShareDescription>, ShareError> {
+    let capture = load_shareable(state, capture_uuid, user_id).await?;
+    let mut conn = state.db_pool().await.get().await.context(SharePoolSnafu)?;
+    let shares: Vec<DbCaptureShare> = capture_shares::table
+        .filter(capture_shares::capture.eq(capture.id))
+        .order(capture_shares::time_created.desc())
+        .load(&mut conn)
+        .await?;
+    Ok(shares
+        .into_iter()
+        .map(|s| {
+            clicor::ShareDescription::new(
+                s.id,
+                s.extractor,
+                s.time_created,
+                s.time_expires,
+                s.time_last_used,
+            )
+        })
+        .collect())
+}
+
+/// Revoke a share of a capture by its ID, returning whether one existed
+pub async fn revoke(
+    state: &State,
+    capture_uuid: &uuid::Uuid,
+    user_id: i32,
+    share_id: i32,
+) -> Result<bool, ShareError> {
+    let capture = load_shareable(state, capture_uuid, user_id).await?;
+    let mut conn = state.db_pool().await.get().await.context(SharePoolSnafu)?;
+    let share = capture_shares::table
+        .filter(capture_shares::id.eq(share_id))
+        .filter(capture_shares::capture.eq(capture.id));
+    let count = diesel::delete(share).execute(&mut conn).await?;
+    Ok(count > 0)
+}
+
+/// Resolve a share token, provided it is unexpired and its capture not deleted
+pub async fn resolve(state: &State, token: &str) -> Option<Share> {
+    let now = chrono::Utc::now();
+    let mut conn = match state.db_pool().await.get().await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("db_pool.get() failed: {e}");
+            return None;
+        }
+    };
+    let share: Result<Option<(DbCaptureShare, uuid::Uuid)>, _> = capture_shares::table
+        .inner_join(captures::table)
+        .filter(capture_shares::token_hash.eq(auth::hash_secret(token)))
+        .filter(
+            capture_shares::time_expires
+                .is_null()
+                .or(capture_shares::time_expires.gt(now)),
+        )
+        .filter(captures::time_deleted.is_null())
+        .select((capture_shares::all_columns, captures::uuid))
+        .get_result(&mut conn)
+        .await
+        .optional();
+    let (share, capture_uuid) = match share {
+        Ok(Some(s)) => s,
+        Ok(None) => return None,
+        Err(e) => {
+            error!("Share lookup failed: {e}");
+            return None;
+        }
+    };
+    let touched = diesel::update(capture_shares::table.filter(capture_shares::id.eq(share.id)))
+        .set(capture_shares::time_last_used.eq(now))
+        .execute(&mut conn)
+        .await;
+    if let Err(e) = touched {
+        error!("Updating share last use failed: {e}");
+    }
+    Some(Share {
+        id: share.id,
+        capture_uuid,
+        extractor: share.extractor,
+    })
+}
+
+/// Load a capture which the user is allowed to share
+async fn load_shareable(
+    state: &State,
+    capture_uuid: &uuid::Uuid,
+    user_id: i32,
+) -> Result<DbCapture, ShareError> {
+    let mut conn = state.db_pool().await.get().await.context(SharePoolSnafu)?;
+    let capture: DbCapture = captures::table
+        .filter(captures::uuid.eq(capture_uuid))
+        .filter(captures::time_deleted.is_null())
+        .get_result(&mut conn)
+        .await
+        .optional()?
+        .ok_or(ShareError::ShareNoSuchCaptureError)?;
+    if !group::can_modify(state, &capture, user_id)
+        .await
+        .context(ShareGroupSnafu)?
+    {
+        return Err(ShareError::ShareForbiddenError);
+    }
+    Ok(capture)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn share(extractor: Option<&str>) -> Share {
+        Share {
+            id: 1,
+            capture_uuid: uuid::Uuid::new_v4(),
+            extractor: extractor.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn whole_capture_share_allows_any_path_within_it() {
+        let share = share(None);
+        assert!(share.allows(Path::new("")));
+        assert!(share.allows(Path::new("singlefile/index.html")));
+        assert!(share.allows(Path::new("wget/a/b.css")));
+    }
+
+    #[test]
+    fn extractor_share_allows_only_its_extractor() {
+        let share = share(Some("singlefile"));
+        assert!(share.allows(Path::new("singlefile")));
+        assert!(share.allows(Path::new("singlefile/index.html")));
+        assert!(!share.allows(Path::new("")));
+        assert!(!share.allows(Path::new("wget/index.html")));
+        assert!(!share.allows(Path::new("singlefile-old/index.html")));
+    }
+
+    #[test]
+    fn paths_climbing_out_are_refused() {
+        for extractor in [None, Some("singlefile")] {
+            let share = share(extractor);
+            assert!(!share.allows(Path::new("singlefile/../wget/index.html")));
+            assert!(!share.allows(Path::new("singlefile/../../other")));
+            assert!(!share.allows(Path::new("/etc/passwd")));
+            assert!(!share.allows(Path::new("./singlefile/index.html")));
+        }
+    }
+}
@@ -4,25 +4,44 @@ use std::path::PathBuf;
 
 use actix_web::web::Bytes;
 use async_stream::stream;
-use diesel_async::AsyncPgConnection;
+use diesel::prelude::*;
+use diesel_async::pooled_connection::PoolError;
 use diesel_async::pooled_connection::{AsyncDieselConnectionManager, mobc::Pool};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use log::*;
 use snafu::prelude::*;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{Mutex, RwLock, broadcast};
 use tokio_stream::Stream;
 
 use crate::msg;
+use crate::msg::clicor::Scope;
 
-use super::config::CoreConfig;
+use super::auth;
+use super::config::{CoreConfig, LoginThrottle, PasswordPolicy, Registration};
+use super::models::{DbApiKey, DbCapture, DbSession, InsApiKey, InsSession};
+use super::oidc::OidcClient;
+use super::queue::JobQueue;
+use super::schema::{api_keys, sessions, users};
+use super::webhook::DeliveryClient;
 
 type PgPool = Pool<AsyncPgConnection>;
 
 pub struct State {
     db_pool: PgPool,
-    token_map: RwLock<HashMap<u128, i32>>,
+    session_lifetime: chrono::TimeDelta,
+    trash_period: chrono::TimeDelta,
+    registration: Registration,
+    password_policy: PasswordPolicy,
+    login_throttle: LoginThrottle,
+    trust_proxy_headers: bool,
+    secure_cookies: bool,
     http_client: reqwest::Client,
+    delivery_client: DeliveryClient,
     extractor_map: ExtractorMap,
     capture_map: CaptureMap,
+    login_challenges: LoginChallenges,
+    oidc: Option<OidcClient>,
+    job_queue: JobQueue,
     worker_dispatch: WorkerDispatch,
     storage_manager: StorageManager,
 }
@@ -64,7 +83,7 @@ impl ExtractorConfig {
         Self { url_regex }
     }
 
-    fn url_matches(&self, url: &String) -> bool {
+    fn url_matches(&self, url: &str) -> bool {
         self.url_regex.is_match(url)
     }
 }
@@ -86,43 +105,128 @@ impl CaptureMap {
         &self,
         capture: &uuid::Uuid,
         extract_quantity: usize,
-        user_id: i32,
-        public: bool,
+        access: CaptureAccess,
     ) {
-        let user_restriction = match public {
-            true => None,
-            false => Some(user_id),
-        };
-        self.map.write().await.insert(
-            capture.clone(),
-            CaptureStatus::new(extract_quantity, user_restriction),
+        self.map
+            .write()
+            .await
+            .insert(*capture, CaptureStatus::new(extract_quantity, access));
+    }
+
+    /// Reinstate the status of a capture from its persisted progress
+    ///
+    /// Used when extracts were interrupted by a restart or have been retried.
+    /// Subscribers to an existing status keep receiving its events.
+    pub async fn restore_status(
+        &self,
+        capture: &uuid::Uuid,
+        progress: msg::clicor::QueryCaptureResponse,
+        access: CaptureAccess,
+    ) {
+        let mut map = self.map.write().await;
+        let events = map.remove(capture).and_then(|s| s.events);
+        map.insert(
+            *capture,
+            CaptureStatus {
+                progress,
+                access,
+                events,
+            },
         );
     }
 
+    /// Update who may follow an ongoing capture after its owner, visibility or group changed
+    pub async fn set_access(&self, capture: &uuid::Uuid, access: CaptureAccess) {
+        if let Some(s) = self.map.write().await.get_mut(capture) {
+            s.access = access;
+        }
+    }
+
+    /// Forget a capture, disconnecting any subscribers to its events
+    pub async fn remove_status(&self, capture: &uuid::Uuid) {
+        self.map.write().await.remove(capture);
+    }
+
     /// Get the status of an ongoing capture
     pub async fn get_status(&self, capture: &uuid::Uuid) -> Option<CaptureStatus> {
-        self.map.read().await.get(capture).map(|a| a.clone())
+        self.map.read().await.get(capture).cloned()
+    }
+
+    /// Subscribe to the events of a capture, if it is still in progress
+    pub async fn subscribe(
+        &self,
+        capture: &uuid::Uuid,
+    ) -> Option<broadcast::Receiver<msg::clicor::CaptureEvent>> {
+        let mut map = self.map.write().await;
+        let status = map.get_mut(capture)?;
+        if status.progress.in_progress() == 0 {
+            return None;
+        }
+        let sender = status
+            .events
+            .get_or_insert_with(|| broadcast::channel(CAPTURE_EVENT_CAPACITY).0);
+        Some(sender.subscribe())
+    }
+
+    /// Announce that an extract of an ongoing capture was sent to a worker
+    pub async fn extract_dispatched(&self, capture: &uuid::Uuid, extractor: &str, worker: &str) {
+        if let Some(s) = self.map.read().await.get(capture) {
+            s.emit(msg::clicor::CaptureEvent::ExtractDispatched {
+                extractor: extractor.to_string(),
+                worker: worker.to_string(),
+            });
+        }
     }
 
     /// Increment the completed extract count for an ongoing capture
-    pub async fn incr_completed(&self, capture: &uuid::Uuid) -> bool {
+    pub async fn incr_completed(&self, capture: &uuid::Uuid, extractor: &str) -> bool {
         let mut map = self.map.write().await;
         if let Some(s) = map.get_mut(capture) {
             s.progress.incr_completed();
-            return true;
+            s.emit(msg::clicor::CaptureEvent::ExtractCompleted {
+                extractor: extractor.to_string(),
+            });
+            s.finish_if_done();
+            true
         } else {
-            return false;
+            false
         }
     }
 
-    /// Increment the completed extract count for an ongoing capture
-    pub async fn incr_failed(&self, capture: &uuid::Uuid) -> bool {
+    /// Increment the failed extract count for an ongoing capture
+    pub async fn incr_failed(&self, capture: &uuid::Uuid, extractor: &str, reason: &str) -> bool {
         let mut map = self.map.write().await;
         if let Some(s) = map.get_mut(capture) {
             s.progress.incr_failed();
-            return true;
+            s.emit(msg::clicor::CaptureEvent::ExtractFailed {
+                extractor: extractor.to_string(),
+                reason: reason.to_string(),
+            });
+            s.finish_if_done();
+            true
         } else {
-            return false;
+            false
+        }
+    }
+}
+
+/// Events buffered per capture for subscribers which fall behind
+const CAPTURE_EVENT_CAPACITY: usize = 64;
+
+/// Who may view a capture
+#[derive(Clone, Copy, Debug)]
+pub struct CaptureAccess {
+    pub owner: i32,
+    pub public: bool,
+    pub group: Option<i32>,
+}
+
+impl From<&DbCapture> for CaptureAccess {
+    fn from(capture: &DbCapture) -> Self {
+        Self {
+            owner: capture.owner,
+            public: capture.public,
+            group: capture.group_id,
         }
     }
 }
@@ -130,25 +234,105 @@ impl CaptureMap {
 #[derive(Clone, Debug)]
 pub struct CaptureStatus {
     progress: msg::clicor::QueryCaptureResponse,
-    user_restriction: Option<i32>,
+    access: CaptureAccess,
+    events: Option<broadcast::Sender<msg::clicor::CaptureEvent>>,
 }
 
 impl CaptureStatus {
-    pub fn new(extract_quantity: usize, user_restriction: Option<i32>) -> Self {
+    pub fn new(extract_quantity: usize, access: CaptureAccess) -> Self {
         Self {
             progress: msg::clicor::QueryCaptureResponse::new_from_quantity(extract_quantity),
-            user_restriction,
+            access,
+            events: None,
         }
     }
 
+    /// Describe a capture which is no longer tracked in memory
+    pub fn from_progress(
+        progress: msg::clicor::QueryCaptureResponse,
+        access: CaptureAccess,
+    ) -> Self {
+        Self {
+            progress,
+            access,
+            events: None,
+        }
+    }
+
+    /// Attach the per-extractor breakdown to the progress
+    pub fn set_extracts(&mut self, extracts: Vec<msg::clicor::ExtractDescription>) {
+        self.progress.set_extracts(extracts);
+    }
+
     /// Return a clone of the progress
     pub fn get_progress(&self) -> msg::clicor::QueryCaptureResponse {
         self.progress.clone()
     }
 
-    /// Determine if a specific user is allowed to check this capture's progress
-    pub fn allows_user(&self, user: i32) -> bool {
-        self.user_restriction == None || self.user_restriction == Some(user)
+    /// Send an event to any subscribers
+    fn emit(&self, event: msg::clicor::CaptureEvent) {
+        if let Some(sender) = &self.events {
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Announce the end of the capture once no extracts remain, closing the event stream
+    fn finish_if_done(&mut self) {
+        if self.progress.in_progress() == 0 {
+            self.emit(msg::clicor::CaptureEvent::CaptureFinished {
+                completed: self.progress.completed(),
+                failed: self.progress.failed(),
+            });
+            self.events = None;
+        }
+    }
+
+    /// Determine if a user, member of `groups`, is allowed to check this capture's progress
+    pub fn allows_user(&self, user: i32, groups: &[i32]) -> bool {
+        self.access.public
+            || self.access.owner == user
+            || self.access.group.is_some_and(|g| groups.contains(&g))
+    }
+}
+
+/// How long a form login may wait for its second factor
+const LOGIN_CHALLENGE_LIFETIME: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// Form logins which passed the password check but still await a second factor
+#[derive(Debug)]
+pub struct LoginChallenges {
+    map: RwLock<HashMap<u128, (i32, std::time::Instant)>>,
+}
+
+impl LoginChallenges {
+    fn new() -> Self {
+        Self {
+            map: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Issue a challenge for a user, discarding any which have expired
+    pub async fn create(&self, user_id: i32) -> u128 {
+        let challenge = rand::random();
+        let mut map = self.map.write().await;
+        map.retain(|_, (_, created)| created.elapsed() < LOGIN_CHALLENGE_LIFETIME);
+        map.insert(challenge, (user_id, std::time::Instant::now()));
+        challenge
+    }
+
+    /// Get the user a challenge was issued for, if it has not expired
+    pub async fn user(&self, challenge: u128) -> Option<i32> {
+        match self.map.read().await.get(&challenge) {
+            Some((user_id, created)) if created.elapsed() < LOGIN_CHALLENGE_LIFETIME => {
+                Some(*user_id)
+            }
+            _ => None,
+        }
+    }
+
+    /// Remove a challenge once it has been completed
+    pub async fn remove(&self, challenge: u128) {
+        self.map.write().await.remove(&challenge);
     }
 }
 
@@ -186,13 +370,13 @@ impl WorkerDispatch {
 
     pub async fn select_worker(&self, extractor: &str, target_url: &url::Url) -> String {
         let mut selector = self.selector.lock().await;
-        let worker_name = selector.select_worker(&extractor, &target_url);
+        let worker_name = selector.select_worker(extractor, target_url);
         worker_name.to_string()
     }
 
-    /// Retrieve descriptor for a specified worker name
-    pub async fn describe_worker(&self, name: &str) -> WorkerDescriptor {
-        self.worker_map.read().await.get(name).unwrap().clone()
+    /// Retrieve descriptor for a specified worker name, if it is still configured
+    pub async fn describe_worker(&self, name: &str) -> Option<WorkerDescriptor> {
+        self.worker_map.read().await.get(name).cloned()
     }
 }
 
@@ -263,6 +447,29 @@ impl StorageManager {
             .map(|a| (a, temp_uuid))
     }
 
+    /// Remove temporary files left behind by interrupted downloads
+    pub async fn clear_temp(&self) -> Result<(), StorageError> {
+        let temp_path = self.root.join(".tmp");
+        let mut entries = tokio::fs::read_dir(&temp_path)
+            .await
+            .context(FilesystemSnafu)?;
+        while let Some(entry) = entries.next_entry().await.context(FilesystemSnafu)? {
+            debug!("Removing stale temporary file {:?}", entry.path());
+            tokio::fs::remove_file(entry.path())
+                .await
+                .context(FilesystemSnafu)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a temporary file which will not be installed
+    pub async fn remove_temp(&self, temp_uuid: &uuid::Uuid) -> Result<(), StorageError> {
+        let temp_path = self.root.join(".tmp").join(temp_uuid.to_string());
+        tokio::fs::remove_file(temp_path)
+            .await
+            .context(FilesystemSnafu)
+    }
+
     /// Install a received tarball to its final location
     pub async fn install_temp(
         &self,
@@ -296,6 +503,15 @@ impl StorageManager {
         Ok(())
     }
 
+    /// Remove the storage subdirectory of a capture and everything in it
+    pub async fn remove_capture(&self, capture_uuid: &uuid::Uuid) -> Result<(), StorageError> {
+        let dir_path = self.root.join(capture_uuid.to_string());
+        match tokio::fs::remove_dir_all(&dir_path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).context(FilesystemSnafu),
+            _ => Ok(()),
+        }
+    }
+
     /// Determine the content type for a specified file
     pub async fn asset_mime(&self, capture_uuid: &uuid::Uuid, tail: PathBuf) -> Option<String> {
         let joined_path = self.root.join(capture_uuid.to_string()).join(tail);
@@ -305,6 +521,25 @@ impl StorageManager {
         cookie.file(joined_path).ok()
     }
 
+    /// List the entries of a specified directory as names and whether each is itself a directory
+    ///
+    /// Returns `None` if the path is not a directory.
+    pub async fn asset_listing(
+        &self,
+        capture_uuid: &uuid::Uuid,
+        tail: PathBuf,
+    ) -> Option<Vec<(String, bool)>> {
+        let joined_path = self.root.join(capture_uuid.to_string()).join(tail);
+        let mut entries = tokio::fs::read_dir(joined_path).await.ok()?;
+        let mut listing = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let is_dir = entry.file_type().await.is_ok_and(|t| t.is_dir());
+            listing.push((entry.file_name().to_string_lossy().into_owned(), is_dir));
+        }
+        listing.sort();
+        Some(listing)
+    }
+
     /// Determine the size of a specified file in bytes
     pub async fn asset_size(&self, capture_uuid: &uuid::Uuid, tail: PathBuf) -> Option<usize> {
         let joined_path = self.root.join(capture_uuid.to_string()).join(tail);
@@ -354,15 +589,45 @@ pub enum StorageError {
     UnpackError { source: std::io::Error },
 }
 
+#[derive(Debug, Snafu)]
+pub enum CredentialError {
+    #[snafu(display("Unable to get a database connection"))]
+    CredentialPoolError { source: mobc::Error<PoolError> },
+
+    #[snafu(display("Credential query failed"))]
+    CredentialQueryError { source: diesel::result::Error },
+}
+
+/// IDs of users who have not been disabled
+fn enabled_users() -> users::BoxedQuery<'static, diesel::pg::Pg, diesel::sql_types::Integer> {
+    users::table
+        .select(users::id)
+        .filter(users::disabled.eq(false))
+        .into_boxed()
+}
+
+/// How long the core waits to connect to a worker or identity provider
+const HTTP_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long the core waits for a worker or identity provider to send anything
+const HTTP_READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Hash a session token for storage
+fn hash_token(token: u128) -> String {
+    auth::hash_secret(&token.to_string())
+}
+
 impl State {
     /// Initiate state from config
     pub async fn from_config(config: CoreConfig) -> Self {
         let cm = AsyncDieselConnectionManager::<AsyncPgConnection>::new(config.database_url());
         let db_pool = Pool::new(cm);
-        let token_map = RwLock::new(HashMap::new());
+        let session_lifetime = chrono::TimeDelta::seconds(config.session_lifetime() as i64);
         let user_agent = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
         let http_client = reqwest::ClientBuilder::new()
             .user_agent(user_agent)
+            .connect_timeout(HTTP_CONNECT_TIMEOUT)
+            .read_timeout(HTTP_READ_TIMEOUT)
             .build()
             .unwrap();
         let mut extractor_map = HashMap::new();
@@ -378,15 +643,28 @@ impl State {
         }
         let extractor_map = ExtractorMap::from_map(extractor_map);
         let capture_map = CaptureMap::new();
+        let oidc = config
+            .oidc()
+            .map(|c| OidcClient::new(c.clone(), http_client.clone()));
         let worker_dispatch = WorkerDispatch::from_config(&config);
         let storage_manager =
             StorageManager::from_config(&config).expect("Error setting up storage manager");
         Self {
             db_pool,
-            token_map,
+            session_lifetime,
+            trash_period: chrono::TimeDelta::seconds(config.trash_period() as i64),
+            registration: config.registration(),
+            password_policy: config.password_policy().clone(),
+            login_throttle: config.login_throttle().clone(),
+            trust_proxy_headers: config.trust_proxy_headers(),
+            secure_cookies: config.secure_cookies(),
             http_client,
+            delivery_client: DeliveryClient::from_config(&config),
             extractor_map,
             capture_map,
+            login_challenges: LoginChallenges::new(),
+            oidc,
+            job_queue: JobQueue::new(config.job_queue().clone()),
             worker_dispatch,
             storage_manager,
         }
@@ -398,15 +676,306 @@ impl State {
     }
 
     /// Create a new token->user association
-    pub async fn register_token(&self, token: u128, user_id: i32) {
-        let mut token_map = self.token_map.write().await;
-        token_map.insert(token, user_id);
+    pub async fn register_token(
+        &self,
+        token: u128,
+        user_id: i32,
+        user_agent: Option<String>,
+    ) -> Result<(), CredentialError> {
+        let now = chrono::Utc::now();
+        let new_session = InsSession {
+            token_hash: hash_token(token),
+            owner: user_id,
+            time_created: now,
+            time_last_used: now,
+            time_expires: now + self.session_lifetime,
+            user_agent,
+        };
+        let mut conn = self.db_pool.get().await.context(CredentialPoolSnafu)?;
+        diesel::insert_into(sessions::table)
+            .values(new_session)
+            .execute(&mut conn)
+            .await
+            .context(CredentialQuerySnafu)?;
+        Ok(())
     }
 
-    /// Derive a user from an associated token
+    /// Derive a user from an associated token, sliding its expiry forward
     pub async fn user_from_token(&self, token: u128) -> Option<i32> {
-        let token_map = self.token_map.read().await;
-        token_map.get(&token).map(|i| *i)
+        let now = chrono::Utc::now();
+        let mut conn = match self.db_pool.get().await {
+            Ok(c) => c,
+            Err(e) => {
+                error!("db_pool.get() failed: {e}");
+                return None;
+            }
+        };
+        let session = sessions::table
+            .filter(sessions::token_hash.eq(hash_token(token)))
+            .filter(sessions::time_expires.gt(now))
+            .filter(sessions::owner.eq_any(enabled_users()));
+        let user = diesel::update(session)
+            .set((
+                sessions::time_last_used.eq(now),
+                sessions::time_expires.eq(now + self.session_lifetime),
+            ))
+            .returning(sessions::owner)
+            .get_result(&mut conn)
+            .await
+            .optional();
+        match user {
+            Ok(u) => u,
+            Err(e) => {
+                error!("Session lookup failed: {e}");
+                None
+            }
+        }
+    }
+
+    /// Revoke the session associated with a token, returning whether one existed
+    pub async fn revoke_token(&self, token: u128) -> Result<bool, CredentialError> {
+        let mut conn = self.db_pool.get().await.context(CredentialPoolSnafu)?;
+        let count =
+            diesel::delete(sessions::table.filter(sessions::token_hash.eq(hash_token(token))))
+                .execute(&mut conn)
+                .await
+                .context(CredentialQuerySnafu)?;
+        Ok(count > 0)
+    }
+
+    /// Revoke a user's session by its ID, returning whether one existed
+    pub async fn revoke_session(
+        &self,
+        user_id: i32,
+        session_id: i32,
+    ) -> Result<bool, CredentialError> {
+        let mut conn = self.db_pool.get().await.context(CredentialPoolSnafu)?;
+        let session = sessions::table
+            .filter(sessions::id.eq(session_id))
+            .filter(sessions::owner.eq(user_id));
+        let count = diesel::delete(session)
+            .execute(&mut conn)
+            .await
+            .context(CredentialQuerySnafu)?;
+        Ok(count > 0)
+    }
+
+    /// Describe a user's unexpired sessions, flagging the one belonging to `current_token`
+    pub async fn list_sessions(
+        &self,
+        user_id: i32,
+        current_token: u128,
+    ) -> Result<Vec<msg::clicor::SessionDescription>, CredentialError> {
+        let mut conn = self.db_pool.get().await.context(CredentialPoolSnafu)?;
+        let sessions: Vec<DbSession> = sessions::table
+            .filter(sessions::owner.eq(user_id))
+            .filter(sessions::time_expires.gt(chrono::Utc::now()))
+            .order(sessions::time_last_used.desc())
+            .load(&mut conn)
+            .await
+            .context(CredentialQuerySnafu)?;
+        let current_hash = hash_token(current_token);
+        let sessions = sessions
+            .into_iter()
+            .map(|s| {
+                let current = s.token_hash == current_hash;
+                msg::clicor::SessionDescription::new(
+                    s.id,
+                    s.time_created,
+                    s.time_last_used,
+                    s.time_expires,
+                    s.user_agent,
+                    current,
+                )
+            })
+            .collect();
+        Ok(sessions)
+    }
+
+    /// Create a new API key, returning its ID and the key itself
+    pub async fn create_api_key(
+        &self,
+        user_id: i32,
+        name: &str,
+        scopes: &[Scope],
+        expires: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<(i32, String), CredentialError> {
+        let key = auth::generate_api_key();
+        let new_key = InsApiKey {
+            key_hash: auth::hash_secret(&key),
+            owner: user_id,
+            name: name.to_string(),
+            scopes: scopes.iter().map(|s| s.as_str().to_string()).collect(),
+            time_created: chrono::Utc::now(),
+            time_expires: expires,
+        };
+        let mut conn = self.db_pool.get().await.context(CredentialPoolSnafu)?;
+        let id = diesel::insert_into(api_keys::table)
+            .values(new_key)
+            .returning(api_keys::id)
+            .get_result(&mut conn)
+            .await
+            .context(CredentialQuerySnafu)?;
+        Ok((id, key))
+    }
+
+    /// Describe all of a user's API keys
+    pub async fn list_api_keys(
+        &self,
+        user_id: i32,
+    ) -> Result<Vec<msg::clicor::ApiKeyDescription>, CredentialError> {
+        let mut conn = self.db_pool.get().await.context(CredentialPoolSnafu)?;
+        let keys: Vec<DbApiKey> = api_keys::table
+            .filter(api_keys::owner.eq(user_id))
+            .order(api_keys::time_created.desc())
+            .load(&mut conn)
+            .await
+            .context(CredentialQuerySnafu)?;
+        let keys = keys
+            .into_iter()
+            .map(|k| {
+                let scopes = k
+                    .scopes
+                    .iter()
+                    .flatten()
+                    .filter_map(|s| s.parse().ok())
+                    .collect();
+                msg::clicor::ApiKeyDescription::new(
+                    k.id,
+                    k.name,
+                    scopes,
+                    k.time_created,
+                    k.time_expires,
+                    k.time_last_used,
+                )
+            })
+            .collect();
+        Ok(keys)
+    }
+
+    /// Revoke a user's API key by its ID, returning whether one existed
+    pub async fn revoke_api_key(&self, user_id: i32, key_id: i32) -> Result<bool, CredentialError> {
+        let mut conn = self.db_pool.get().await.context(CredentialPoolSnafu)?;
+        let key = api_keys::table
+            .filter(api_keys::id.eq(key_id))
+            .filter(api_keys::owner.eq(user_id));
+        let count = diesel::delete(key)
+            .execute(&mut conn)
+            .await
+            .context(CredentialQuerySnafu)?;
+        Ok(count > 0)
+    }
+
+    /// Derive a user from an API key, provided the key is unexpired and grants `scope`
+    pub async fn user_from_api_key(&self, key: &str, scope: Scope) -> Option<i32> {
+        let now = chrono::Utc::now();
+        let mut conn = match self.db_pool.get().await {
+            Ok(c) => c,
+            Err(e) => {
+                error!("db_pool.get() failed: {e}");
+                return None;
+            }
+        };
+        let key: Result<Option<DbApiKey>, _> = api_keys::table
+            .filter(api_keys::key_hash.eq(auth::hash_secret(key)))
+            .filter(
+                api_keys::time_expires
+                    .is_null()
+                    .or(api_keys::time_expires.gt(now)),
+            )
+            .filter(api_keys::owner.eq_any(enabled_users()))
+            .get_result(&mut conn)
+            .await
+            .optional();
+        let key = match key {
+            Ok(Some(k)) => k,
+            Ok(None) => return None,
+            Err(e) => {
+                error!("API key lookup failed: {e}");
+                return None;
+            }
+        };
+        if !key.scopes.iter().flatten().any(|s| s == scope.as_str()) {
+            debug!("API key {} lacks scope {}", key.id, scope.as_str());
+            return None;
+        }
+        let touched = diesel::update(api_keys::table.filter(api_keys::id.eq(key.id)))
+            .set(api_keys::time_last_used.eq(now))
+            .execute(&mut conn)
+            .await;
+        if let Err(e) = touched {
+            error!("Updating API key last use failed: {e}");
+        }
+        Some(key.owner)
+    }
+
+    /// Derive a user from either kind of token, provided it grants `scope`
+    ///
+    /// Session tokens carry every scope.
+    pub async fn authenticate(&self, token: &auth::Token, scope: Scope) -> Option<i32> {
+        match token {
+            auth::Token::Session(t) => self.user_from_token(*t).await,
+            auth::Token::ApiKey(k) => self.user_from_api_key(k, scope).await,
+        }
+    }
+
+    /// Determine whether a user holds administrative rights
+    pub async fn is_admin(&self, user_id: i32) -> bool {
+        let mut conn = match self.db_pool.get().await {
+            Ok(c) => c,
+            Err(e) => {
+                error!("db_pool.get() failed: {e}");
+                return false;
+            }
+        };
+        let is_admin = users::table
+            .filter(users::id.eq(user_id))
+            .filter(users::disabled.eq(false))
+            .select(users::is_admin)
+            .get_result(&mut conn)
+            .await
+            .optional();
+        match is_admin {
+            Ok(a) => a.unwrap_or(false),
+            Err(e) => {
+                error!("Admin lookup failed: {e}");
+                false
+            }
+        }
+    }
+
+    /// Delete every session whose expiry has passed, returning how many were removed
+    pub async fn sweep_sessions(&self) -> Result<usize, CredentialError> {
+        let mut conn = self.db_pool.get().await.context(CredentialPoolSnafu)?;
+        diesel::delete(sessions::table.filter(sessions::time_expires.le(chrono::Utc::now())))
+            .execute(&mut conn)
+            .await
+            .context(CredentialQuerySnafu)
+    }
+
+    pub fn registration(&self) -> Registration {
+        self.registration
+    }
+
+    /// How long deleted captures stay in the trash; zero if they are removed immediately
+    pub fn trash_period(&self) -> chrono::TimeDelta {
+        self.trash_period
+    }
+
+    pub fn password_policy(&self) -> &PasswordPolicy {
+        &self.password_policy
+    }
+
+    pub fn login_throttle(&self) -> &LoginThrottle {
+        &self.login_throttle
+    }
+
+    pub fn trust_proxy_headers(&self) -> bool {
+        self.trust_proxy_headers
+    }
+
+    pub fn secure_cookies(&self) -> bool {
+        self.secure_cookies
     }
 
     pub async fn extractor_map(&self) -> &ExtractorMap {
@@ -417,6 +986,19 @@ impl State {
         &self.capture_map
     }
 
+    pub fn login_challenges(&self) -> &LoginChallenges {
+        &self.login_challenges
+    }
+
+    /// OpenID Connect relying party, if a provider is configured
+    pub fn oidc(&self) -> Option<&OidcClient> {
+        self.oidc.as_ref()
+    }
+
+    pub fn job_queue(&self) -> &JobQueue {
+        &self.job_queue
+    }
+
     pub fn worker_dispatch(&self) -> &WorkerDispatch {
         &self.worker_dispatch
     }
@@ -426,6 +1008,11 @@ impl State {
         self.http_client.clone()
     }
 
+    /// HTTP client for webhook and callback deliveries
+    pub fn delivery_client(&self) -> &DeliveryClient {
+        &self.delivery_client
+    }
+
     pub fn storage_manager(&self) -> &StorageManager {
         &self.storage_manager
     }
@@ -0,0 +1,21 @@
+use actix_web::web;
+use log::*;
+
+use crate::core::state::State;
+use crate::core::throttle;
+
+/// Periodically remove expired sessions and stale login attempts from the database
+pub async fn sweep_sessions(state: web::Data<State>) {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        match state.sweep_sessions().await {
+            Ok(n) => debug!("Swept {n} expired sessions"),
+            Err(e) => error!("Sweeping expired sessions failed: {e}"),
+        }
+        match throttle::prune(&state).await {
+            Ok(n) => debug!("Pruned {n} stale login attempts"),
+            Err(e) => error!("Pruning login attempts failed: {e}"),
+        }
+    }
+}
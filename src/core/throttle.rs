@@ -0,0 +1,292 @@
+use diesel::dsl::{count_star, max};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use snafu::prelude::*;
+
+use crate::core::config::LoginThrottle;
+use crate::core::models::InsLoginAttempt;
+use crate::core::schema::login_attempts;
+use crate::core::state::State;
+
+type Timestamp = chrono::DateTime<chrono::Utc>;
+
+#[derive(Debug, Snafu)]
+pub enum ThrottleError {
+    #[snafu(display("Unable to get a database connection"))]
+    ThrottlePoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Login attempt query failed"))]
+    ThrottleQueryError { source: diesel::result::Error },
+}
+
+/// A login attempt which has been recorded and may be evaluated
+///
+/// The attempt counts as a failure unless it is resolved with
+/// [`Attempt::succeed`] or [`Attempt::abandon`].
+#[derive(Debug)]
+#[must_use]
+pub struct Attempt {
+    id: i32,
+}
+
+/// Whether a login attempt may be evaluated
+#[derive(Debug)]
+pub enum Admission {
+    Admitted(Attempt),
+    /// Seconds to wait before the next attempt will be evaluated
+    Throttled(u64),
+}
+
+/// Record a login attempt and decide whether it may be evaluated
+///
+/// The attempt is stored as a failure before earlier ones are counted, so
+/// concurrent guesses see each other instead of all passing the same check.
+/// A throttled attempt is removed again, so that it doesn't extend the penalty.
+pub async fn begin_attempt(
+    state: &State,
+    username: &str,
+    ip: Option<&str>,
+) -> Result<Admission, ThrottleError> {
+    let throttle = state.login_throttle();
+    let now = chrono::Utc::now();
+    let attempt = InsLoginAttempt {
+        username: username.to_string(),
+        ip: ip.map(|i| i.to_string()),
+        time_attempted: now,
+        success: false,
+    };
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(ThrottlePoolSnafu)?;
+    let id: i32 = diesel::insert_into(login_attempts::table)
+        .values(attempt)
+        .returning(login_attempts::id)
+        .get_result(&mut conn)
+        .await
+        .context(ThrottleQuerySnafu)?;
+    let (count, latest) = username_failures(&mut conn, throttle, username, id, now)
+        .await
+        .context(ThrottleQuerySnafu)?;
+    let mut wait = penalty(throttle, count, latest, now);
+    if let Some(ip) = ip {
+        let (count, latest) = ip_failures(&mut conn, throttle, ip, id, now)
+            .await
+            .context(ThrottleQuerySnafu)?;
+        wait = wait.max(penalty(throttle, count, latest, now));
+    }
+    match wait {
+        Some(retry_after) => {
+            Attempt { id }.abandon(state).await?;
+            Ok(Admission::Throttled(retry_after))
+        }
+        None => Ok(Admission::Admitted(Attempt { id })),
+    }
+}
+
+impl Attempt {
+    /// Mark the attempt as successful
+    pub async fn succeed(self, state: &State) -> Result<(), ThrottleError> {
+        let mut conn = state
+            .db_pool()
+            .await
+            .get()
+            .await
+            .context(ThrottlePoolSnafu)?;
+        diesel::update(login_attempts::table.filter(login_attempts::id.eq(self.id)))
+            .set(login_attempts::success.eq(true))
+            .execute(&mut conn)
+            .await
+            .context(ThrottleQuerySnafu)?;
+        Ok(())
+    }
+
+    /// Forget an attempt which reached neither success nor failure
+    pub async fn abandon(self, state: &State) -> Result<(), ThrottleError> {
+        let mut conn = state
+            .db_pool()
+            .await
+            .get()
+            .await
+            .context(ThrottlePoolSnafu)?;
+        diesel::delete(login_attempts::table.filter(login_attempts::id.eq(self.id)))
+            .execute(&mut conn)
+            .await
+            .context(ThrottleQuerySnafu)?;
+        Ok(())
+    }
+}
+
+/// Delete attempts too old to count towards any penalty, returning how many were removed
+pub async fn prune(state: &State) -> Result<usize, ThrottleError> {
+    let window = chrono::TimeDelta::seconds(state.login_throttle().window() as i64);
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(ThrottlePoolSnafu)?;
+    diesel::delete(
+        login_attempts::table
+            .filter(login_attempts::time_attempted.lt(chrono::Utc::now() - window)),
+    )
+    .execute(&mut conn)
+    .await
+    .context(ThrottleQuerySnafu)
+}
+
+/// Count a username's failures within the window since its last successful login
+///
+/// The attempt being evaluated, `current`, is left out.
+async fn username_failures(
+    conn: &mut AsyncPgConnection,
+    throttle: &LoginThrottle,
+    username: &str,
+    current: i32,
+    now: Timestamp,
+) -> Result<(i64, Option<Timestamp>), diesel::result::Error> {
+    let last_success: Option<Timestamp> = login_attempts::table
+        .filter(login_attempts::username.eq(username))
+        .filter(login_attempts::success.eq(true))
+        .select(max(login_attempts::time_attempted))
+        .get_result(conn)
+        .await?;
+    let window_start = now - chrono::TimeDelta::seconds(throttle.window() as i64);
+    let since = last_success.map_or(window_start, |s| s.max(window_start));
+    login_attempts::table
+        .filter(login_attempts::username.eq(username))
+        .filter(login_attempts::id.ne(current))
+        .filter(login_attempts::success.eq(false))
+        .filter(login_attempts::time_attempted.gt(since))
+        .select((count_star(), max(login_attempts::time_attempted)))
+        .get_result(conn)
+        .await
+}
+
+/// Count an address's failures within the window, leaving out `current`
+///
+/// Successes don't reset this count, so that an attacker can't clear it by
+/// logging into an account of their own.
+async fn ip_failures(
+    conn: &mut AsyncPgConnection,
+    throttle: &LoginThrottle,
+    ip: &str,
+    current: i32,
+    now: Timestamp,
+) -> Result<(i64, Option<Timestamp>), diesel::result::Error> {
+    let window_start = now - chrono::TimeDelta::seconds(throttle.window() as i64);
+    login_attempts::table
+        .filter(login_attempts::ip.eq(ip))
+        .filter(login_attempts::id.ne(current))
+        .filter(login_attempts::success.eq(false))
+        .filter(login_attempts::time_attempted.gt(window_start))
+        .select((count_star(), max(login_attempts::time_attempted)))
+        .get_result(conn)
+        .await
+}
+
+/// Seconds remaining in the penalty earned by `count` failures, the latest at `latest`
+fn penalty(
+    throttle: &LoginThrottle,
+    count: i64,
+    latest: Option<Timestamp>,
+    now: Timestamp,
+) -> Option<u64> {
+    let latest = latest?;
+    let delay = if count >= throttle.lockout_threshold() as i64 {
+        throttle.lockout_duration()
+    } else if count >= throttle.free_attempts() as i64 {
+        let exponent = (count - throttle.free_attempts() as i64) as u32;
+        let factor = 2_u64.checked_pow(exponent).unwrap_or(u64::MAX);
+        throttle
+            .base_delay()
+            .saturating_mul(factor)
+            .min(throttle.lockout_duration())
+    } else {
+        return None;
+    };
+    let until = latest + chrono::TimeDelta::seconds(delay as i64);
+    let remaining = (until - now).num_milliseconds();
+    if remaining > 0 {
+        Some((remaining as u64).div_ceil(1000))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> Timestamp {
+        "2024-03-15T12:00:00Z".parse().unwrap()
+    }
+
+    fn seconds_ago(seconds: i64) -> Option<Timestamp> {
+        Some(now() - chrono::TimeDelta::seconds(seconds))
+    }
+
+    #[test]
+    fn free_attempts_carry_no_penalty() {
+        let throttle = LoginThrottle::default();
+        assert_eq!(penalty(&throttle, 0, None, now()), None);
+        assert_eq!(penalty(&throttle, 2, seconds_ago(0), now()), None);
+    }
+
+    #[test]
+    fn delay_doubles_with_each_failure_past_the_free_ones() {
+        let throttle = LoginThrottle::default();
+        let delays: Vec<Option<u64>> = (3..=9)
+            .map(|count| penalty(&throttle, count, seconds_ago(0), now()))
+            .collect();
+        assert_eq!(
+            delays,
+            [
+                Some(2),
+                Some(4),
+                Some(8),
+                Some(16),
+                Some(32),
+                Some(64),
+                Some(128)
+            ]
+        );
+    }
+
+    #[test]
+    fn lockout_applies_from_the_threshold() {
+        let throttle = LoginThrottle::default();
+        assert_eq!(penalty(&throttle, 10, seconds_ago(0), now()), Some(900));
+        assert_eq!(penalty(&throttle, 500, seconds_ago(0), now()), Some(900));
+    }
+
+    #[test]
+    fn backoff_is_capped_at_the_lockout_duration() {
+        let throttle: LoginThrottle = ron::from_str(
+            "(free_attempts: 1, base_delay: 10, lockout_threshold: 100, lockout_duration: 60)",
+        )
+        .unwrap();
+        assert_eq!(penalty(&throttle, 3, seconds_ago(0), now()), Some(40));
+        assert_eq!(penalty(&throttle, 4, seconds_ago(0), now()), Some(60));
+        assert_eq!(penalty(&throttle, 99, seconds_ago(0), now()), Some(60));
+    }
+
+    #[test]
+    fn penalty_counts_down_from_the_latest_failure() {
+        let throttle = LoginThrottle::default();
+        assert_eq!(penalty(&throttle, 5, seconds_ago(3), now()), Some(5));
+        assert_eq!(penalty(&throttle, 5, seconds_ago(8), now()), None);
+        assert_eq!(penalty(&throttle, 5, seconds_ago(20), now()), None);
+    }
+
+    #[test]
+    fn partial_seconds_round_up() {
+        let throttle = LoginThrottle::default();
+        let latest = now() - chrono::TimeDelta::milliseconds(1500);
+        assert_eq!(penalty(&throttle, 4, Some(latest), now()), Some(3));
+    }
+}
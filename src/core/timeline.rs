@@ -0,0 +1,299 @@
+use chrono::Datelike;
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use log::*;
+use snafu::prelude::*;
+
+use super::models::DbCapture;
+use super::schema::{captures, group_members, tracked_urls};
+use super::state::State;
+use crate::msg::clicor::TimelineEntry;
+
+#[derive(Debug, Snafu)]
+pub enum TimelineError {
+    #[snafu(display("Unable to get a database connection"))]
+    TimelinePoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Timeline query failed"))]
+    TimelineQueryError { source: diesel::result::Error },
+}
+
+/// Reduce a URL to the form shared by every capture of the same page
+///
+/// Fragments are dropped and query parameters sorted, on top of the
+/// normalization `url::Url` already performs on the scheme, host and port.
+pub fn normalize(url: &url::Url) -> url::Url {
+    let mut normalized = url.clone();
+    normalized.set_fragment(None);
+    let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    if pairs.is_empty() {
+        normalized.set_query(None);
+    } else {
+        pairs.sort();
+        normalized.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    normalized
+}
+
+/// Get the ID of the tracked URL a capture of `url` belongs to, tracking it if new
+pub async fn track(
+    conn: &mut AsyncPgConnection,
+    url: &url::Url,
+) -> Result<i32, diesel::result::Error> {
+    diesel::insert_into(tracked_urls::table)
+        .values((
+            tracked_urls::url.eq(normalize(url).as_str()),
+            tracked_urls::time_created.eq(chrono::Utc::now()),
+        ))
+        .on_conflict(tracked_urls::url)
+        .do_update()
+        .set(tracked_urls::url.eq(excluded(tracked_urls::url)))
+        .returning(tracked_urls::id)
+        .get_result(conn)
+        .await
+}
+
+/// Link captures made before URLs were tracked to their tracked URLs
+pub async fn backfill(state: &State) {
+    match link_untracked(state).await {
+        Ok(0) => {}
+        Ok(n) => info!("Linked {n} captures to tracked URLs"),
+        Err(e) => error!("Unable to link captures to tracked URLs: {e}"),
+    }
+}
+
+async fn link_untracked(state: &State) -> Result<usize, TimelineError> {
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(TimelinePoolSnafu)?;
+    let untracked: Vec<DbCapture> = captures::table
+        .filter(captures::tracked_url.is_null())
+        .load(&mut conn)
+        .await
+        .context(TimelineQuerySnafu)?;
+    for capture in untracked.iter() {
+        let tracked = track(&mut conn, &capture.url)
+            .await
+            .context(TimelineQuerySnafu)?;
+        diesel::update(captures::table.filter(captures::id.eq(capture.id)))
+            .set(captures::tracked_url.eq(tracked))
+            .execute(&mut conn)
+            .await
+            .context(TimelineQuerySnafu)?;
+    }
+    Ok(untracked.len())
+}
+
+/// List the captures of a URL visible to a user, directly or through a group, oldest first
+pub async fn timeline(
+    state: &State,
+    url: &url::Url,
+    user_id: i32,
+) -> Result<Vec<TimelineEntry>, TimelineError> {
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(TimelinePoolSnafu)?;
+    let captures: Vec<DbCapture> = captures::table
+        .inner_join(tracked_urls::table)
+        .filter(tracked_urls::url.eq(normalize(url).as_str()))
+        .filter(
+            captures::owner
+                .eq(user_id)
+                .or(captures::public.eq(true))
+                .or(captures::group_id.eq_any(
+                    group_members::table
+                        .filter(group_members::member.eq(user_id))
+                        .select(group_members::group_id.nullable()),
+                )),
+        )
+        .filter(captures::time_deleted.is_null())
+        .order(captures::time_initiated.asc())
+        .select(captures::all_columns)
+        .load(&mut conn)
+        .await
+        .context(TimelineQuerySnafu)?;
+    Ok(captures
+        .into_iter()
+        .map(|c| TimelineEntry::new(c.uuid, c.url, c.time_initiated, c.time_finished, c.public))
+        .collect())
+}
+
+/// A year of a timeline laid out as a calendar
+#[derive(Debug, serde::Serialize)]
+pub struct CalendarYear {
+    year: i32,
+    months: Vec<CalendarMonth>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct CalendarMonth {
+    name: &'static str,
+    /// Weeks starting on Monday, with `None` padding days outside the month
+    weeks: Vec<Vec<Option<CalendarDay>>>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct CalendarDay {
+    day: u32,
+    /// Captures initiated on the day, oldest first
+    captures: Vec<uuid::Uuid>,
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Lay out every year in which a timeline has captures, in UTC
+pub fn calendar(entries: &[TimelineEntry]) -> Vec<CalendarYear> {
+    let mut years: Vec<i32> = entries.iter().map(|e| e.time_initiated().year()).collect();
+    years.dedup();
+    years
+        .into_iter()
+        .map(|year| CalendarYear {
+            year,
+            months: (1..=12)
+                .map(|month| lay_out(year, month, entries))
+                .collect(),
+        })
+        .collect()
+}
+
+fn lay_out(year: i32, month: u32, entries: &[TimelineEntry]) -> CalendarMonth {
+    let first = chrono::NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+    let mut weeks = vec![];
+    let mut week: Vec<Option<CalendarDay>> = (0..first.weekday().num_days_from_monday())
+        .map(|_| None)
+        .collect();
+    for date in first.iter_days().take_while(|d| d.month() == month) {
+        let captures = entries
+            .iter()
+            .filter(|e| e.time_initiated().date_naive() == date)
+            .map(|e| *e.capture_id())
+            .collect();
+        week.push(Some(CalendarDay {
+            day: date.day(),
+            captures,
+        }));
+        if week.len() == 7 {
+            weeks.push(std::mem::take(&mut week));
+        }
+    }
+    if !week.is_empty() {
+        week.resize_with(7, || None);
+        weeks.push(week);
+    }
+    CalendarMonth {
+        name: MONTH_NAMES[month as usize - 1],
+        weeks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn url(s: &str) -> url::Url {
+        url::Url::parse(s).unwrap()
+    }
+
+    fn entry(year: i32, month: u32, day: u32) -> TimelineEntry {
+        let time = chrono::Utc
+            .with_ymd_and_hms(year, month, day, 12, 0, 0)
+            .unwrap();
+        TimelineEntry::new(
+            uuid::Uuid::new_v4(),
+            url("https://example.com/"),
+            time,
+            None,
+            false,
+        )
+    }
+
+    #[test]
+    fn normalize_drops_fragment_and_sorts_query() {
+        assert_eq!(
+            normalize(&url("HTTPS://Example.com:443/a?b=2&a=1#top")),
+            url("https://example.com/a?a=1&b=2")
+        );
+    }
+
+    #[test]
+    fn normalize_drops_empty_query() {
+        assert_eq!(
+            normalize(&url("https://example.com/a?")),
+            url("https://example.com/a")
+        );
+    }
+
+    #[test]
+    fn normalize_keeps_path_case_and_trailing_slash() {
+        let u = url("https://example.com/A/");
+        assert_eq!(normalize(&u), u);
+        assert_ne!(normalize(&u), normalize(&url("https://example.com/A")));
+    }
+
+    #[test]
+    fn calendar_has_one_year_per_year_with_captures() {
+        let entries = [entry(2023, 5, 1), entry(2023, 6, 1), entry(2025, 1, 1)];
+        let years: Vec<i32> = calendar(&entries).iter().map(|y| y.year).collect();
+        assert_eq!(years, [2023, 2025]);
+        assert!(calendar(&[]).is_empty());
+    }
+
+    #[test]
+    fn calendar_pads_weeks_to_start_on_monday() {
+        // 1 September 2024 was a Sunday, and the 30th a Monday
+        let years = calendar(&[entry(2024, 9, 1)]);
+        let september = &years[0].months[8];
+        assert_eq!(september.name, "September");
+        assert_eq!(september.weeks.len(), 6);
+        assert!(september.weeks.iter().all(|w| w.len() == 7));
+        assert!(september.weeks[0][..6].iter().all(Option::is_none));
+        assert_eq!(september.weeks[0][6].as_ref().unwrap().day, 1);
+        assert_eq!(september.weeks[5][0].as_ref().unwrap().day, 30);
+        assert!(september.weeks[5][1..].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn calendar_files_captures_under_their_day() {
+        let entries = [entry(2024, 2, 29), entry(2024, 2, 29), entry(2024, 3, 1)];
+        let years = calendar(&entries);
+        let day = |month: usize, day: u32| {
+            years[0].months[month]
+                .weeks
+                .iter()
+                .flatten()
+                .flatten()
+                .find(|d| d.day == day)
+                .unwrap()
+        };
+        assert_eq!(
+            day(1, 29).captures,
+            [*entries[0].capture_id(), *entries[1].capture_id()]
+        );
+        assert_eq!(day(2, 1).captures, [*entries[2].capture_id()]);
+        assert!(day(1, 28).captures.is_empty());
+    }
+}
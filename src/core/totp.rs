@@ -0,0 +1,256 @@
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use snafu::prelude::*;
+use totp_rs::{Builder, Secret, Totp};
+
+use crate::core::auth;
+use crate::core::models::{DbUser, InsRecoveryCode};
+use crate::core::schema::{recovery_codes, users};
+use crate::core::state::State;
+
+/// Issuer shown by authenticator apps
+const ISSUER: &str = "webarc";
+
+/// Number of recovery codes issued upon enrollment
+const RECOVERY_CODE_COUNT: usize = 10;
+
+#[derive(Debug, Snafu)]
+pub enum TotpError {
+    #[snafu(display("Two-factor authentication is already enabled"))]
+    AlreadyEnabledError,
+
+    #[snafu(display("Two-factor authentication is not enabled"))]
+    NotEnabledError,
+
+    #[snafu(display("No enrollment is pending"))]
+    NotEnrolledError,
+
+    #[snafu(display("Code is incorrect"))]
+    InvalidCodeError,
+
+    #[snafu(display("Unable to build TOTP generator"))]
+    GeneratorError { source: totp_rs::TotpError },
+
+    #[snafu(display("Unable to render QR code"))]
+    QrError { source: qrcode::types::QrError },
+
+    #[snafu(display("Unable to get a database connection"))]
+    TotpPoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("TOTP query failed"))]
+    TotpQueryError { source: diesel::result::Error },
+}
+
+impl From<diesel::result::Error> for TotpError {
+    fn from(source: diesel::result::Error) -> Self {
+        TotpError::TotpQueryError { source }
+    }
+}
+
+/// Details an authenticator app needs to start producing codes
+pub struct Enrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+    pub qr_svg: String,
+}
+
+/// Build a TOTP generator for a base32-encoded secret
+fn generator(secret: &str, username: &str) -> Option<Totp> {
+    let secret = Secret::try_from_base32(secret).ok()?;
+    Builder::new()
+        .with_secret(secret)
+        .with_account_name(username)
+        .with_issuer(Some(ISSUER))
+        .build()
+        .ok()
+}
+
+/// Normalise a recovery code as typed by a user
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Generate a fresh set of recovery codes
+fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = hex::encode(rand::random::<[u8; 8]>());
+            format!(
+                "{}-{}-{}-{}",
+                &code[0..4],
+                &code[4..8],
+                &code[8..12],
+                &code[12..16]
+            )
+        })
+        .collect()
+}
+
+/// Generate a pending secret for a user, or describe the one already pending
+///
+/// Reusing a pending secret means revisiting the enrollment page doesn't
+/// invalidate a code the user has already scanned. The secret has no effect
+/// on login until confirmed with [`confirm_enrollment`].
+pub async fn begin_enrollment(state: &State, user_id: i32) -> Result<Enrollment, TotpError> {
+    let mut conn = state.db_pool().await.get().await.context(TotpPoolSnafu)?;
+    let user: DbUser = users::table
+        .filter(users::id.eq(user_id))
+        .get_result(&mut conn)
+        .await?;
+    if user.totp_enabled {
+        return Err(TotpError::AlreadyEnabledError);
+    }
+    let pending = user
+        .totp_secret
+        .as_deref()
+        .and_then(|s| Secret::try_from_base32(s).ok());
+    let fresh = pending.is_none();
+    let totp = Builder::new()
+        .with_secret(pending.unwrap_or_else(|| rand::random::<[u8; 20]>().into()))
+        .with_account_name(user.username.as_str())
+        .with_issuer(Some(ISSUER))
+        .build()
+        .context(GeneratorSnafu)?;
+    let secret = totp.secret().to_base32();
+    let otpauth_uri = totp.to_url().context(GeneratorSnafu)?;
+    let qr_svg = qrcode::QrCode::new(otpauth_uri.as_bytes())
+        .context(QrSnafu)?
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+    if fresh {
+        diesel::update(users::table.filter(users::id.eq(user_id)))
+            .set((
+                users::totp_secret.eq(&secret),
+                users::totp_last_step.eq(None::<i64>),
+            ))
+            .execute(&mut conn)
+            .await?;
+    }
+    Ok(Enrollment {
+        secret,
+        otpauth_uri,
+        qr_svg,
+    })
+}
+
+/// Enable two-factor authentication using a code from the pending secret
+///
+/// Returns the user's recovery codes, which are not retrievable afterwards.
+pub async fn confirm_enrollment(
+    state: &State,
+    user_id: i32,
+    code: &str,
+) -> Result<Vec<String>, TotpError> {
+    let mut conn = state.db_pool().await.get().await.context(TotpPoolSnafu)?;
+    let user: DbUser = users::table
+        .filter(users::id.eq(user_id))
+        .get_result(&mut conn)
+        .await?;
+    if user.totp_enabled {
+        return Err(TotpError::AlreadyEnabledError);
+    }
+    let secret = user.totp_secret.ok_or(TotpError::NotEnrolledError)?;
+    let step = generator(&secret, &user.username)
+        .and_then(|g| g.check_current(code))
+        .ok_or(TotpError::InvalidCodeError)?;
+    let codes = generate_recovery_codes();
+    let new_codes: Vec<InsRecoveryCode> = codes
+        .iter()
+        .map(|c| InsRecoveryCode {
+            code_hash: auth::hash_secret(&normalize_recovery_code(c)),
+            owner: user_id,
+        })
+        .collect();
+    conn.transaction::<_, TotpError, _>(|conn| {
+        async move {
+            diesel::update(users::table.filter(users::id.eq(user_id)))
+                .set((
+                    users::totp_enabled.eq(true),
+                    users::totp_last_step.eq(step as i64),
+                ))
+                .execute(conn)
+                .await?;
+            diesel::delete(recovery_codes::table.filter(recovery_codes::owner.eq(user_id)))
+                .execute(conn)
+                .await?;
+            diesel::insert_into(recovery_codes::table)
+                .values(new_codes)
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await?;
+    Ok(codes)
+}
+
+/// Turn off two-factor authentication, given a valid code
+pub async fn disable(state: &State, user_id: i32, code: &str) -> Result<(), TotpError> {
+    let mut conn = state.db_pool().await.get().await.context(TotpPoolSnafu)?;
+    let user: DbUser = users::table
+        .filter(users::id.eq(user_id))
+        .get_result(&mut conn)
+        .await?;
+    if !user.totp_enabled {
+        return Err(TotpError::NotEnabledError);
+    }
+    if !check_code(state, &user, code).await? {
+        return Err(TotpError::InvalidCodeError);
+    }
+    conn.transaction::<_, TotpError, _>(|conn| {
+        async move {
+            diesel::update(users::table.filter(users::id.eq(user_id)))
+                .set((
+                    users::totp_secret.eq(None::<String>),
+                    users::totp_enabled.eq(false),
+                    users::totp_last_step.eq(None::<i64>),
+                ))
+                .execute(conn)
+                .await?;
+            diesel::delete(recovery_codes::table.filter(recovery_codes::owner.eq(user_id)))
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Check a second factor, which may be a current TOTP code or an unused recovery code
+///
+/// Each TOTP step and each recovery code is only accepted once.
+pub async fn check_code(state: &State, user: &DbUser, code: &str) -> Result<bool, TotpError> {
+    let mut conn = state.db_pool().await.get().await.context(TotpPoolSnafu)?;
+    let step = user
+        .totp_secret
+        .as_deref()
+        .and_then(|s| generator(s, &user.username))
+        .and_then(|g| g.check_current(code));
+    if let Some(step) = step {
+        let step = step as i64;
+        let unused = users::table.filter(users::id.eq(user.id)).filter(
+            users::totp_last_step
+                .is_null()
+                .or(users::totp_last_step.lt(step)),
+        );
+        let count = diesel::update(unused)
+            .set(users::totp_last_step.eq(step))
+            .execute(&mut conn)
+            .await?;
+        return Ok(count == 1);
+    }
+    let recovery = recovery_codes::table
+        .filter(recovery_codes::owner.eq(user.id))
+        .filter(recovery_codes::code_hash.eq(auth::hash_secret(&normalize_recovery_code(code))));
+    let count = diesel::delete(recovery).execute(&mut conn).await?;
+    Ok(count == 1)
+}
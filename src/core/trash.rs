@@ -0,0 +1,240 @@
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use log::*;
+use snafu::prelude::*;
+
+use crate::core::group::{self, GroupError};
+use crate::core::models::{DbCapture, DbJob};
+use crate::core::queue::{self, JobKind, Outcome};
+use crate::core::schema::{captures, extracts, jobs, webhook_deliveries};
+use crate::core::state::{State, StorageError};
+use crate::msg::clicor;
+
+#[derive(Debug, Snafu)]
+pub enum TrashError {
+    #[snafu(display("No such capture"))]
+    TrashNoSuchCaptureError,
+
+    #[snafu(display("Only the capture's owner, an admin or an admin of its group may do that"))]
+    TrashForbiddenError,
+
+    #[snafu(display("Capture is still in progress"))]
+    CaptureInProgressError,
+
+    #[snafu(display("Capture is not in the trash"))]
+    NotTrashedError,
+
+    #[snafu(display("Unable to check group membership"))]
+    TrashGroupError { source: GroupError },
+
+    #[snafu(display("Unable to get a database connection"))]
+    TrashPoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Trash query failed"))]
+    TrashQueryError { source: diesel::result::Error },
+
+    #[snafu(display("Unable to remove capture from storage"))]
+    TrashStorageError { source: StorageError },
+}
+
+impl From<diesel::result::Error> for TrashError {
+    fn from(source: diesel::result::Error) -> Self {
+        TrashError::TrashQueryError { source }
+    }
+}
+
+/// What became of a deleted capture
+#[derive(Debug)]
+pub enum Deletion {
+    /// Hidden until it is removed at the given time, unless restored first
+    Trashed(chrono::DateTime<chrono::Utc>),
+    /// Removed from the database and storage
+    Purged,
+}
+
+/// Delete a capture on behalf of someone who may modify it
+///
+/// Captures go to the trash when a trash period is configured, unless
+/// `permanent` is set. Deleting a capture which is already in the trash
+/// removes it immediately.
+pub async fn delete(
+    state: &State,
+    capture_uuid: &uuid::Uuid,
+    user_id: i32,
+    permanent: bool,
+) -> Result<Deletion, TrashError> {
+    let capture = load_modifiable(state, capture_uuid, user_id).await?;
+    if capture.time_deleted.is_none() && capture.time_finished.is_none() {
+        return Err(TrashError::CaptureInProgressError);
+    }
+    let period = state.trash_period();
+    if permanent || period.is_zero() || capture.time_deleted.is_some() {
+        purge(state, capture.id).await?;
+        return Ok(Deletion::Purged);
+    }
+
+    let time_purge = chrono::Utc::now() + period;
+    let mut conn = state.db_pool().await.get().await.context(TrashPoolSnafu)?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            diesel::update(captures::table.filter(captures::id.eq(capture.id)))
+                .set(captures::time_deleted.eq(chrono::Utc::now()))
+                .execute(conn)
+                .await?;
+            queue::enqueue_at(
+                conn,
+                JobKind::Purge,
+                capture.id,
+                queue::PRIORITY_BACKGROUND,
+                time_purge,
+            )
+            .await
+        }
+        .scope_boxed()
+    })
+    .await?;
+    state.capture_map().await.remove_status(capture_uuid).await;
+    Ok(Deletion::Trashed(time_purge))
+}
+
+/// Take a capture back out of the trash
+pub async fn restore(
+    state: &State,
+    capture_uuid: &uuid::Uuid,
+    user_id: i32,
+) -> Result<(), TrashError> {
+    let capture = load_modifiable(state, capture_uuid, user_id).await?;
+    if capture.time_deleted.is_none() {
+        return Err(TrashError::NotTrashedError);
+    }
+    let mut conn = state.db_pool().await.get().await.context(TrashPoolSnafu)?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            diesel::update(captures::table.filter(captures::id.eq(capture.id)))
+                .set(captures::time_deleted.eq(None::<chrono::DateTime<chrono::Utc>>))
+                .execute(conn)
+                .await?;
+            queue::dequeue(conn, JobKind::Purge, capture.id).await
+        }
+        .scope_boxed()
+    })
+    .await?;
+    Ok(())
+}
+
+/// Describe the captures a user has in the trash, most recently deleted first
+pub async fn list(state: &State, owner: i32) -> Result<Vec<clicor::TrashedCapture>, TrashError> {
+    let mut conn = state.db_pool().await.get().await.context(TrashPoolSnafu)?;
+    let trashed: Vec<DbCapture> = captures::table
+        .filter(captures::owner.eq(owner))
+        .filter(captures::time_deleted.is_not_null())
+        .order(captures::time_deleted.desc())
+        .load(&mut conn)
+        .await?;
+    let period = state.trash_period();
+    Ok(trashed
+        .into_iter()
+        .filter_map(|c| {
+            let time_deleted = c.time_deleted?;
+            Some(clicor::TrashedCapture::new(
+                c.uuid,
+                c.url,
+                time_deleted,
+                time_deleted + period,
+            ))
+        })
+        .collect())
+}
+
+/// Load a capture, trashed or not, which the user is allowed to delete
+async fn load_modifiable(
+    state: &State,
+    capture_uuid: &uuid::Uuid,
+    user_id: i32,
+) -> Result<DbCapture, TrashError> {
+    let mut conn = state.db_pool().await.get().await.context(TrashPoolSnafu)?;
+    let capture: DbCapture = captures::table
+        .filter(captures::uuid.eq(capture_uuid))
+        .get_result(&mut conn)
+        .await
+        .optional()?
+        .ok_or(TrashError::TrashNoSuchCaptureError)?;
+    if !group::can_modify(state, &capture, user_id)
+        .await
+        .context(TrashGroupSnafu)?
+    {
+        return Err(TrashError::TrashForbiddenError);
+    }
+    Ok(capture)
+}
+
+/// Remove a capture whose time in the trash is up
+pub async fn run(state: &State, job: &DbJob) -> Outcome {
+    match purge(state, job.subject).await {
+        Ok(()) => Outcome::Finished,
+        Err(e) => {
+            error!("Unable to purge capture {}: {e}", job.subject);
+            Outcome::Retry
+        }
+    }
+}
+
+/// Remove a capture's stored output, followed by the capture, its extracts
+/// and their history, its webhook deliveries and any jobs working on them
+///
+/// Storage goes first so that, should it fail, the capture and its purge job
+/// remain to be tried again rather than leaving the output orphaned.
+async fn purge(state: &State, capture_id: i32) -> Result<(), TrashError> {
+    let mut conn = state.db_pool().await.get().await.context(TrashPoolSnafu)?;
+    let Some(capture_uuid) = captures::table
+        .filter(captures::id.eq(capture_id))
+        .select(captures::uuid)
+        .get_result::<uuid::Uuid>(&mut conn)
+        .await
+        .optional()?
+    else {
+        return Ok(());
+    };
+    state.capture_map().await.remove_status(&capture_uuid).await;
+    state
+        .storage_manager()
+        .remove_capture(&capture_uuid)
+        .await
+        .context(TrashStorageSnafu)?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            let extract_ids: Vec<i32> = extracts::table
+                .filter(extracts::capture.eq(capture_id))
+                .select(extracts::id)
+                .load(conn)
+                .await?;
+            let delivery_ids: Vec<i32> = webhook_deliveries::table
+                .filter(webhook_deliveries::capture.eq(capture_id))
+                .select(webhook_deliveries::id)
+                .load(conn)
+                .await?;
+            let capture_jobs = jobs::table.filter(
+                jobs::kind
+                    .eq(JobKind::Extract.as_str())
+                    .and(jobs::subject.eq_any(extract_ids))
+                    .or(jobs::kind
+                        .eq(JobKind::Webhook.as_str())
+                        .and(jobs::subject.eq_any(delivery_ids)))
+                    .or(jobs::kind
+                        .eq(JobKind::Purge.as_str())
+                        .and(jobs::subject.eq(capture_id))),
+            );
+            diesel::delete(capture_jobs).execute(conn).await?;
+            diesel::delete(captures::table.filter(captures::id.eq(capture_id)))
+                .execute(conn)
+                .await
+        }
+        .scope_boxed()
+    })
+    .await?;
+    info!("Removed capture {capture_uuid}");
+    Ok(())
+}
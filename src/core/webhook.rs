@@ -0,0 +1,671 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use hmac::{Hmac, Mac};
+use log::*;
+use snafu::prelude::*;
+
+use crate::core::auth;
+use crate::core::config::CoreConfig;
+use crate::core::extract::{self, ExtractState};
+use crate::core::models::{
+    DbCapture, DbExtract, DbJob, DbWebhook, DbWebhookDelivery, InsWebhook, InsWebhookDelivery,
+};
+use crate::core::queue::{self, JobKind, Outcome};
+use crate::core::schema::{captures, extracts, webhook_deliveries, webhooks};
+use crate::core::state::State;
+use crate::msg::clicor::{self, DeliveryState};
+
+/// How long a destination has to respond to a delivery
+const DELIVERY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long a destination has to accept a connection
+const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Most deliveries shown in the delivery log
+const DELIVERY_LOG_LIMIT: i64 = 100;
+
+#[derive(Debug, Snafu)]
+pub enum WebhookError {
+    #[snafu(display("Unable to get a database connection"))]
+    WebhookPoolError {
+        source: mobc::Error<diesel_async::pooled_connection::PoolError>,
+    },
+
+    #[snafu(display("Webhook query failed"))]
+    WebhookQueryError { source: diesel::result::Error },
+}
+
+/// A URL to notify when a single capture finishes, along with the secret to sign with
+#[derive(Clone, Debug)]
+pub struct Callback {
+    url: url::Url,
+    secret: String,
+}
+
+impl Callback {
+    /// Prepare a callback to a URL, minting a fresh secret for it
+    ///
+    /// Returns `None` unless deliveries can be made to the URL.
+    pub async fn new(state: &State, url: url::Url) -> Option<Self> {
+        is_deliverable(state, &url).await.then(|| Self {
+            url,
+            secret: auth::generate_secret(),
+        })
+    }
+
+    pub fn url(&self) -> &url::Url {
+        &self.url
+    }
+
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+}
+
+/// Whether deliveries can be made to a URL
+///
+/// It must be HTTP(S) and resolve only to public addresses other than the workers'.
+pub async fn is_deliverable(state: &State, url: &url::Url) -> bool {
+    state.delivery_client().permits(url).await
+}
+
+/// HTTP client for deliveries, which refuses destinations inside the deployment
+///
+/// Users choose where deliveries go, so without this they could have the core
+/// make requests to itself, its workers or anything else on its network.
+/// Redirects aren't followed, as they could lead anywhere.
+#[derive(Clone)]
+pub struct DeliveryClient {
+    client: reqwest::Client,
+    blocked_hosts: Arc<Vec<String>>,
+}
+
+impl DeliveryClient {
+    pub fn from_config(config: &CoreConfig) -> Self {
+        let blocked_hosts: Arc<Vec<String>> = Arc::new(
+            config
+                .workers()
+                .iter()
+                .filter_map(|(_, _, url)| url.host_str())
+                .map(|h| h.trim_matches(['[', ']']).to_ascii_lowercase())
+                .collect(),
+        );
+        let user_agent = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        let client = reqwest::ClientBuilder::new()
+            .user_agent(user_agent)
+            .redirect(reqwest::redirect::Policy::none())
+            .connect_timeout(CONNECT_TIMEOUT)
+            .dns_resolver(DestinationResolver {
+                blocked_hosts: blocked_hosts.clone(),
+            })
+            .build()
+            .expect("Error setting up delivery client");
+        Self {
+            client,
+            blocked_hosts,
+        }
+    }
+
+    /// Whether a URL is an acceptable destination at the moment
+    ///
+    /// Names are checked again as they're resolved for each delivery, so one
+    /// which later changes to point inside the deployment is still refused.
+    pub async fn permits(&self, url: &url::Url) -> bool {
+        if !matches!(url.scheme(), "http" | "https") {
+            return false;
+        }
+        let (Some(host), Some(port)) = (url.host(), url.port_or_known_default()) else {
+            return false;
+        };
+        let host = match host {
+            url::Host::Domain(d) => d.to_owned(),
+            url::Host::Ipv4(ip) => ip.to_string(),
+            url::Host::Ipv6(ip) => ip.to_string(),
+        };
+        resolve_destination(&host, port, &self.blocked_hosts)
+            .await
+            .is_ok()
+    }
+}
+
+/// Resolver which only yields addresses deliveries may be made to
+struct DestinationResolver {
+    blocked_hosts: Arc<Vec<String>>,
+}
+
+impl reqwest::dns::Resolve for DestinationResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_owned();
+        let blocked_hosts = self.blocked_hosts.clone();
+        Box::pin(async move {
+            let addrs = resolve_destination(&host, 0, &blocked_hosts).await?;
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+#[derive(Debug, Snafu)]
+enum DestinationError {
+    #[snafu(display("Unable to resolve destination"))]
+    UnresolvableError { source: std::io::Error },
+
+    #[snafu(display("Destination is not a public address"))]
+    ForbiddenDestinationError,
+}
+
+/// Resolve a destination host, refusing it if any of its addresses aren't allowed
+async fn resolve_destination(
+    host: &str,
+    port: u16,
+    blocked_hosts: &[String],
+) -> Result<Vec<SocketAddr>, DestinationError> {
+    let host = host.to_ascii_lowercase();
+    ensure!(
+        host != "localhost" && !host.ends_with(".localhost") && !blocked_hosts.contains(&host),
+        ForbiddenDestinationSnafu
+    );
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .context(UnresolvableSnafu)?
+        .collect();
+    ensure!(
+        !addrs.is_empty() && addrs.iter().all(|a| is_public(a.ip())),
+        ForbiddenDestinationSnafu
+    );
+    for blocked in blocked_hosts {
+        let Ok(worker_addrs) = tokio::net::lookup_host((blocked.as_str(), 0)).await else {
+            continue;
+        };
+        for worker_addr in worker_addrs {
+            ensure!(
+                addrs.iter().all(|a| a.ip() != worker_addr.ip()),
+                ForbiddenDestinationSnafu
+            );
+        }
+    }
+    Ok(addrs)
+}
+
+/// Whether an address is publicly routable, rather than loopback, private, link-local or reserved
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240
+                // Shared address space for carrier-grade NAT
+                || (a == 100 && (64..128).contains(&b))
+                // IETF protocol assignments
+                || (a == 192 && b == 0 && ip.octets()[2] == 0))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(mapped) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(mapped));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local
+                || (first & 0xfe00) == 0xfc00
+                // Link-local
+                || (first & 0xffc0) == 0xfe80
+                // Documentation
+                || first == 0x2001 && ip.segments()[1] == 0x0db8)
+        }
+    }
+}
+
+/// Register an account-level webhook, returning its id and signing secret
+pub async fn create(
+    state: &State,
+    owner: i32,
+    url: &url::Url,
+) -> Result<(i32, String), WebhookError> {
+    let secret = auth::generate_secret();
+    let new_webhook = InsWebhook {
+        owner,
+        url: url.to_string(),
+        secret: secret.clone(),
+        time_created: chrono::Utc::now(),
+    };
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(WebhookPoolSnafu)?;
+    let id = diesel::insert_into(webhooks::table)
+        .values(new_webhook)
+        .returning(webhooks::id)
+        .get_result(&mut conn)
+        .await
+        .context(WebhookQuerySnafu)?;
+    Ok((id, secret))
+}
+
+/// Describe all of a user's webhooks
+pub async fn list(
+    state: &State,
+    owner: i32,
+) -> Result<Vec<clicor::WebhookDescription>, WebhookError> {
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(WebhookPoolSnafu)?;
+    let hooks: Vec<DbWebhook> = webhooks::table
+        .filter(webhooks::owner.eq(owner))
+        .order(webhooks::time_created.desc())
+        .load(&mut conn)
+        .await
+        .context(WebhookQuerySnafu)?;
+    Ok(hooks
+        .into_iter()
+        .map(|h| clicor::WebhookDescription::new(h.id, h.url, h.time_created))
+        .collect())
+}
+
+/// Remove one of a user's webhooks, returning whether it existed
+///
+/// Its pending deliveries go with it.
+pub async fn delete(state: &State, owner: i32, id: i32) -> Result<bool, WebhookError> {
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(WebhookPoolSnafu)?;
+    let target = webhooks::table
+        .filter(webhooks::id.eq(id))
+        .filter(webhooks::owner.eq(owner));
+    let count = diesel::delete(target)
+        .execute(&mut conn)
+        .await
+        .context(WebhookQuerySnafu)?;
+    Ok(count > 0)
+}
+
+/// Describe the most recent deliveries for a user's captures, newest first
+pub async fn deliveries(
+    state: &State,
+    owner: i32,
+) -> Result<Vec<clicor::WebhookDeliveryDescription>, WebhookError> {
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(WebhookPoolSnafu)?;
+    let rows: Vec<(DbWebhookDelivery, uuid::Uuid)> = webhook_deliveries::table
+        .inner_join(captures::table)
+        .filter(captures::owner.eq(owner))
+        .order(webhook_deliveries::id.desc())
+        .limit(DELIVERY_LOG_LIMIT)
+        .select((webhook_deliveries::all_columns, captures::uuid))
+        .load(&mut conn)
+        .await
+        .context(WebhookQuerySnafu)?;
+    Ok(rows
+        .into_iter()
+        .map(|(d, capture_uuid)| {
+            clicor::WebhookDeliveryDescription::new(
+                d.id,
+                capture_uuid,
+                d.webhook,
+                d.url,
+                d.state.parse().unwrap_or(DeliveryState::Failed),
+                d.attempts,
+                d.response_status,
+                d.error,
+                d.time_created,
+                d.time_updated,
+            )
+        })
+        .collect())
+}
+
+/// Queue notifications for a capture if its last extract has just finished
+///
+/// Marking the capture finished is a single conditional update, so when
+/// several extracts finish at once exactly one of them triggers delivery.
+pub async fn capture_finished(state: &State, capture_id: i32) {
+    match try_capture_finished(state, capture_id).await {
+        Ok(0) => (),
+        Ok(n) => {
+            debug!("Queued {n} webhook deliveries for capture {capture_id}");
+            state.job_queue().wake();
+        }
+        Err(e) => error!("Unable to queue webhook deliveries for capture {capture_id}: {e}"),
+    }
+}
+
+async fn try_capture_finished(state: &State, capture_id: i32) -> Result<usize, WebhookError> {
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(WebhookPoolSnafu)?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            let unfinished = extracts::table
+                .filter(extracts::capture.eq(capture_id))
+                .filter(extracts::state.eq_any(ExtractState::UNFINISHED));
+            let now = chrono::Utc::now();
+            let capture: Option<DbCapture> = diesel::update(
+                captures::table
+                    .filter(captures::id.eq(capture_id))
+                    .filter(captures::time_finished.is_null())
+                    .filter(diesel::dsl::not(diesel::dsl::exists(unfinished))),
+            )
+            .set(captures::time_finished.eq(now))
+            .get_result(conn)
+            .await
+            .optional()?;
+            let Some(capture) = capture else {
+                return Ok(0);
+            };
+            let results: Vec<clicor::ExtractDescription> = extracts::table
+                .filter(extracts::capture.eq(capture_id))
+                .order(extracts::extractor.asc())
+                .load::<DbExtract>(conn)
+                .await?
+                .into_iter()
+                .map(|e| extract::describe(e, &capture.uuid))
+                .collect();
+            let payload = clicor::WebhookPayload::capture_finished(
+                capture.uuid,
+                capture.url.clone(),
+                capture.time_initiated,
+                now,
+                results,
+            );
+            let payload = serde_json::to_string(&payload).expect("payload serializes");
+            let mut destinations: Vec<(Option<i32>, String)> = webhooks::table
+                .filter(webhooks::owner.eq(capture.owner))
+                .select((webhooks::id.nullable(), webhooks::url))
+                .load(conn)
+                .await?;
+            if let Some(callback_url) = capture.callback_url {
+                destinations.push((None, callback_url));
+            }
+            if destinations.is_empty() {
+                return Ok(0);
+            }
+            let new_deliveries: Vec<InsWebhookDelivery> = destinations
+                .into_iter()
+                .map(|(webhook, url)| InsWebhookDelivery {
+                    capture: capture_id,
+                    webhook,
+                    url,
+                    payload: payload.clone(),
+                    state: DeliveryState::Pending.as_str().to_string(),
+                    attempts: 0,
+                    time_created: now,
+                    time_updated: now,
+                })
+                .collect();
+            let ids: Vec<i32> = diesel::insert_into(webhook_deliveries::table)
+                .values(new_deliveries)
+                .returning(webhook_deliveries::id)
+                .get_results(conn)
+                .await?;
+            queue::enqueue(conn, JobKind::Webhook, &ids, queue::PRIORITY_BACKGROUND).await?;
+            Ok(ids.len())
+        }
+        .scope_boxed()
+    })
+    .await
+    .context(WebhookQuerySnafu)
+}
+
+/// Attempt a single delivery, as the work of a queued job
+pub async fn deliver(state: &State, job: &DbJob) -> Outcome {
+    let (delivery, secret) = match load(state, job.subject).await {
+        Ok(Some(d)) => d,
+        Ok(None) => {
+            debug!(
+                "Delivery {} no longer exists, discarding its job",
+                job.subject
+            );
+            return Outcome::Finished;
+        }
+        Err(e) => {
+            error!("Unable to load delivery {}: {e}", job.subject);
+            return Outcome::Retry;
+        }
+    };
+    if delivery.state != DeliveryState::Pending.as_str() {
+        return Outcome::Finished;
+    }
+    let Some(secret) = secret else {
+        let update = (
+            webhook_deliveries::state.eq(DeliveryState::Failed.as_str()),
+            webhook_deliveries::error.eq(Some("no signing secret")),
+        );
+        record(state, delivery.id, update).await;
+        return Outcome::Finished;
+    };
+    let attempts = delivery.attempts + 1;
+    let client = state.delivery_client();
+    let permitted = match url::Url::parse(&delivery.url) {
+        Ok(url) => client.permits(&url).await,
+        Err(_) => false,
+    };
+    if !permitted {
+        let update = (
+            webhook_deliveries::state.eq(DeliveryState::Failed.as_str()),
+            webhook_deliveries::attempts.eq(attempts),
+            webhook_deliveries::error.eq(Some("destination not allowed")),
+        );
+        record(state, delivery.id, update).await;
+        return Outcome::Finished;
+    }
+    let response = client
+        .client
+        .post(&delivery.url)
+        .timeout(DELIVERY_TIMEOUT)
+        .header("Content-Type", "application/json")
+        .header("X-Webarc-Event", "capture_finished")
+        .header("X-Webarc-Delivery", delivery.id.to_string())
+        .header(
+            "X-Webarc-Signature",
+            format!("sha256={}", sign(&secret, &delivery.payload)),
+        )
+        .body(delivery.payload.clone())
+        .send()
+        .await;
+    let (status, error) = match response {
+        Ok(r) if r.status().is_success() => {
+            let update = (
+                webhook_deliveries::state.eq(DeliveryState::Delivered.as_str()),
+                webhook_deliveries::attempts.eq(attempts),
+                webhook_deliveries::response_status.eq(Some(r.status().as_u16() as i32)),
+                webhook_deliveries::error.eq(None::<String>),
+            );
+            record(state, delivery.id, update).await;
+            return Outcome::Finished;
+        }
+        Ok(r) => (
+            Some(r.status().as_u16() as i32),
+            format!("destination responded {}", r.status()),
+        ),
+        Err(e) => {
+            debug!("Delivery {} to {} failed: {e}", delivery.id, delivery.url);
+            // Only broad categories are reported back, as the details describe
+            // the network between the core and the destination
+            let error = match e {
+                e if e.is_timeout() => "timed out",
+                e if e.is_connect() => "unable to connect",
+                e if e.is_redirect() => "redirects are not followed",
+                _ => "request failed",
+            };
+            (None, error.to_string())
+        }
+    };
+    let exhausted = job.attempts >= state.job_queue().config().max_attempts();
+    let delivery_state = match exhausted {
+        true => DeliveryState::Failed,
+        false => DeliveryState::Pending,
+    };
+    let update = (
+        webhook_deliveries::state.eq(delivery_state.as_str()),
+        webhook_deliveries::attempts.eq(attempts),
+        webhook_deliveries::response_status.eq(status),
+        webhook_deliveries::error.eq(Some(error)),
+    );
+    record(state, delivery.id, update).await;
+    match exhausted {
+        true => Outcome::Finished,
+        false => Outcome::Retry,
+    }
+}
+
+/// Load a delivery along with the secret its payload is signed with
+async fn load(
+    state: &State,
+    id: i32,
+) -> Result<Option<(DbWebhookDelivery, Option<String>)>, WebhookError> {
+    let mut conn = state
+        .db_pool()
+        .await
+        .get()
+        .await
+        .context(WebhookPoolSnafu)?;
+    let row: Option<(DbWebhookDelivery, Option<String>, Option<String>)> =
+        webhook_deliveries::table
+            .inner_join(captures::table)
+            .left_join(webhooks::table)
+            .filter(webhook_deliveries::id.eq(id))
+            .select((
+                webhook_deliveries::all_columns,
+                captures::callback_secret,
+                webhooks::secret.nullable(),
+            ))
+            .get_result(&mut conn)
+            .await
+            .optional()
+            .context(WebhookQuerySnafu)?;
+    Ok(row.map(|(delivery, callback_secret, webhook_secret)| {
+        let secret = match delivery.webhook {
+            Some(_) => webhook_secret,
+            None => callback_secret,
+        };
+        (delivery, secret)
+    }))
+}
+
+/// Record the result of a delivery attempt
+async fn record<U>(state: &State, id: i32, update: U)
+where
+    U: diesel::query_builder::AsChangeset<Target = webhook_deliveries::table> + Send,
+    U::Changeset: diesel::query_builder::QueryFragment<diesel::pg::Pg> + Send,
+{
+    let mut conn = match state.db_pool().await.get().await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("db_pool.get() failed: {e}");
+            return;
+        }
+    };
+    let result = diesel::update(webhook_deliveries::table.filter(webhook_deliveries::id.eq(id)))
+        .set((
+            update,
+            webhook_deliveries::time_updated.eq(chrono::Utc::now()),
+        ))
+        .execute(&mut conn)
+        .await;
+    if result != Ok(1) {
+        error!("Unexpected issue updating delivery {id}: {result:?}");
+    }
+}
+
+/// Compute the hex HMAC-SHA256 of a payload
+fn sign(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key size");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_is_hex_hmac_sha256() {
+        assert_eq!(
+            sign("key", "The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn sign_depends_on_secret_and_payload() {
+        let signature = sign("secret", "{}");
+        assert_ne!(signature, sign("other", "{}"));
+        assert_ne!(signature, sign("secret", "{ }"));
+        assert_eq!(signature, sign("secret", "{}"));
+    }
+
+    #[test]
+    fn public_addresses_are_allowed() {
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn internal_addresses_are_refused() {
+        for ip in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "192.0.0.8",
+            "224.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fc00::1",
+            "fe80::1",
+            "2001:db8::1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn local_and_worker_hosts_are_refused() {
+        let workers = ["93.184.216.34".to_string()];
+        for host in ["localhost", "LOCALHOST", "api.localhost", "127.0.0.1"] {
+            assert!(matches!(
+                resolve_destination(host, 80, &[]).await,
+                Err(DestinationError::ForbiddenDestinationError)
+            ));
+        }
+        assert!(matches!(
+            resolve_destination("93.184.216.34", 80, &workers).await,
+            Err(DestinationError::ForbiddenDestinationError)
+        ));
+        assert!(resolve_destination("93.184.216.34", 80, &[]).await.is_ok());
+    }
+}
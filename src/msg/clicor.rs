@@ -6,6 +6,7 @@ use serde::{Deserialize, Serialize};
 pub struct CreateUserRequest {
     username: String,
     password: String,
+    invite: Option<String>,
 }
 
 impl CreateUserRequest {
@@ -16,6 +17,11 @@ impl CreateUserRequest {
     pub fn password(&self) -> &str {
         &self.password
     }
+
+    /// Invite code, required when registration is invite-only
+    pub fn invite(&self) -> Option<&str> {
+        self.invite.as_deref()
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -26,12 +32,109 @@ pub enum CreateUserResponse {
     InvalidUsername,
     InvalidPassword,
     UnavailableUsername,
+    RegistrationClosed,
+    InvalidInvite,
+    PasswordTooShort { min_length: usize },
+    PasswordTooWeak { missing: Vec<PasswordRequirement> },
+}
+
+/// Character class a password policy may demand
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PasswordRequirement {
+    MixedCase,
+    Digit,
+    Symbol,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChangePasswordRequest {
+    old_password: String,
+    new_password: String,
+}
+
+impl ChangePasswordRequest {
+    pub fn old_password(&self) -> &str {
+        &self.old_password
+    }
+
+    pub fn new_password(&self) -> &str {
+        &self.new_password
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum ChangePasswordResponse {
+    /// All of the user's sessions, including the current one, were revoked; API keys survive
+    Changed,
+    InvalidCredentials,
+    PasswordTooShort {
+        min_length: usize,
+    },
+    PasswordTooWeak {
+        missing: Vec<PasswordRequirement>,
+    },
+    /// Too many wrong current passwords or logins; `retry_after` is in seconds
+    RateLimited {
+        retry_after: u64,
+    },
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum CreatePasswordResetResponse {
+    /// The token itself is only ever returned here
+    Created {
+        token: String,
+        expires: chrono::DateTime<chrono::Utc>,
+    },
+    NoSuchUser,
+    Unauthenticated,
+    Forbidden,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ResetPasswordRequest {
+    token: String,
+    new_password: String,
+}
+
+impl ResetPasswordRequest {
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn new_password(&self) -> &str {
+        &self.new_password
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum ResetPasswordResponse {
+    /// All of the user's sessions were revoked; API keys survive
+    Reset,
+    InvalidToken,
+    PasswordTooShort {
+        min_length: usize,
+    },
+    PasswordTooWeak {
+        missing: Vec<PasswordRequirement>,
+    },
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AuthRequest {
     username: String,
     password: String,
+    /// TOTP or recovery code, for users with two-factor authentication enabled
+    #[serde(default)]
+    totp: Option<String>,
 }
 
 impl AuthRequest {
@@ -42,21 +145,441 @@ impl AuthRequest {
     pub fn password(&self) -> &str {
         &self.password
     }
+
+    pub fn totp(&self) -> Option<&str> {
+        self.totp.as_deref()
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(tag = "result")]
 #[serde(rename_all = "snake_case")]
 pub enum AuthResponse {
-    Authenticated { token: String },
+    Authenticated {
+        token: String,
+    },
     UnacceptableCredentials,
     InvalidCredentials,
+    AccountDisabled,
+    /// Password was correct but the account requires a `totp` code as well
+    SecondFactorRequired,
+    /// Too many failed attempts; `retry_after` is in seconds
+    RateLimited {
+        retry_after: u64,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum TotpEnrollResponse {
+    /// Scan `otpauth_uri` (or `qr_svg`) and confirm with a code to finish enrolling
+    Pending {
+        secret: String,
+        otpauth_uri: String,
+        qr_svg: String,
+    },
+    AlreadyEnabled,
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TotpCodeRequest {
+    code: String,
+}
+
+impl TotpCodeRequest {
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum TotpConfirmResponse {
+    /// Each recovery code may be used once in place of a TOTP code
+    Enabled {
+        recovery_codes: Vec<String>,
+    },
+    AlreadyEnabled,
+    NotEnrolled,
+    InvalidCode,
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum TotpDisableResponse {
+    Disabled,
+    NotEnabled,
+    InvalidCode,
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum LogoutResponse {
+    LoggedOut,
+    /// API keys can't log out; revoke them through `DELETE /0/keys/{id}` instead
+    ApiKeyCannotLogOut,
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SessionDescription {
+    id: i32,
+    time_created: chrono::DateTime<chrono::Utc>,
+    time_last_used: chrono::DateTime<chrono::Utc>,
+    time_expires: chrono::DateTime<chrono::Utc>,
+    user_agent: Option<String>,
+    current: bool,
+}
+
+impl SessionDescription {
+    pub fn new(
+        id: i32,
+        time_created: chrono::DateTime<chrono::Utc>,
+        time_last_used: chrono::DateTime<chrono::Utc>,
+        time_expires: chrono::DateTime<chrono::Utc>,
+        user_agent: Option<String>,
+        current: bool,
+    ) -> Self {
+        Self {
+            id,
+            time_created,
+            time_last_used,
+            time_expires,
+            user_agent,
+            current,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn time_created(&self) -> &chrono::DateTime<chrono::Utc> {
+        &self.time_created
+    }
+
+    pub fn time_last_used(&self) -> &chrono::DateTime<chrono::Utc> {
+        &self.time_last_used
+    }
+
+    pub fn time_expires(&self) -> &chrono::DateTime<chrono::Utc> {
+        &self.time_expires
+    }
+
+    pub fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+
+    /// Whether this is the session making the request
+    pub fn current(&self) -> bool {
+        self.current
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum RevokeSessionResponse {
+    Revoked,
+    NoSuchSession,
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UserDescription {
+    id: i32,
+    username: String,
+    is_admin: bool,
+    disabled: bool,
+}
+
+impl UserDescription {
+    pub fn new(id: i32, username: String, is_admin: bool, disabled: bool) -> Self {
+        Self {
+            id,
+            username,
+            is_admin,
+            disabled,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.is_admin
+    }
+
+    pub fn disabled(&self) -> bool {
+        self.disabled
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum ModifyUserResponse {
+    Disabled,
+    Enabled,
+    Deleted,
+    NoSuchUser,
+    OwnsCaptures,
+    LastGroupAdmin,
+    SelfModification,
+    Unauthenticated,
+    Forbidden,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateInviteRequest {
+    expires: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl CreateInviteRequest {
+    pub fn expires(&self) -> Option<&chrono::DateTime<chrono::Utc>> {
+        self.expires.as_ref()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum CreateInviteResponse {
+    /// The code itself is only ever returned here
+    Created {
+        code: String,
+    },
+    Unauthenticated,
+    Forbidden,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InviteDescription {
+    id: i32,
+    creator: i32,
+    time_created: chrono::DateTime<chrono::Utc>,
+    time_expires: Option<chrono::DateTime<chrono::Utc>>,
+    redeemer: Option<i32>,
+    time_redeemed: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl InviteDescription {
+    pub fn new(
+        id: i32,
+        creator: i32,
+        time_created: chrono::DateTime<chrono::Utc>,
+        time_expires: Option<chrono::DateTime<chrono::Utc>>,
+        redeemer: Option<i32>,
+        time_redeemed: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Self {
+        Self {
+            id,
+            creator,
+            time_created,
+            time_expires,
+            redeemer,
+            time_redeemed,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn creator(&self) -> i32 {
+        self.creator
+    }
+
+    pub fn time_created(&self) -> &chrono::DateTime<chrono::Utc> {
+        &self.time_created
+    }
+
+    pub fn time_expires(&self) -> Option<&chrono::DateTime<chrono::Utc>> {
+        self.time_expires.as_ref()
+    }
+
+    pub fn redeemer(&self) -> Option<i32> {
+        self.redeemer
+    }
+
+    pub fn time_redeemed(&self) -> Option<&chrono::DateTime<chrono::Utc>> {
+        self.time_redeemed.as_ref()
+    }
+}
+
+/// Permission which may be granted to an API key
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Scope {
+    #[serde(rename = "capture:create")]
+    CaptureCreate,
+    #[serde(rename = "capture:read")]
+    CaptureRead,
+    #[serde(rename = "resource:read")]
+    ResourceRead,
+    #[serde(rename = "capture:delete")]
+    CaptureDelete,
+    #[serde(rename = "capture:manage")]
+    CaptureManage,
+    #[serde(rename = "capture:share")]
+    CaptureShare,
+    #[serde(rename = "group:write")]
+    GroupWrite,
+    #[serde(rename = "schedule:write")]
+    ScheduleWrite,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::CaptureCreate => "capture:create",
+            Scope::CaptureRead => "capture:read",
+            Scope::ResourceRead => "resource:read",
+            Scope::CaptureDelete => "capture:delete",
+            Scope::CaptureManage => "capture:manage",
+            Scope::CaptureShare => "capture:share",
+            Scope::GroupWrite => "group:write",
+            Scope::ScheduleWrite => "schedule:write",
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "capture:create" => Ok(Scope::CaptureCreate),
+            "capture:read" => Ok(Scope::CaptureRead),
+            "resource:read" => Ok(Scope::ResourceRead),
+            "capture:delete" => Ok(Scope::CaptureDelete),
+            "capture:manage" => Ok(Scope::CaptureManage),
+            "capture:share" => Ok(Scope::CaptureShare),
+            "group:write" => Ok(Scope::GroupWrite),
+            "schedule:write" => Ok(Scope::ScheduleWrite),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateApiKeyRequest {
+    name: String,
+    scopes: Vec<Scope>,
+    expires: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl CreateApiKeyRequest {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn scopes(&self) -> &[Scope] {
+        &self.scopes
+    }
+
+    pub fn expires(&self) -> Option<&chrono::DateTime<chrono::Utc>> {
+        self.expires.as_ref()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum CreateApiKeyResponse {
+    /// The key itself is only ever returned here
+    Created {
+        id: i32,
+        key: String,
+    },
+    InvalidName,
+    NoScopes,
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ApiKeyDescription {
+    id: i32,
+    name: String,
+    scopes: Vec<Scope>,
+    time_created: chrono::DateTime<chrono::Utc>,
+    time_expires: Option<chrono::DateTime<chrono::Utc>>,
+    time_last_used: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl ApiKeyDescription {
+    pub fn new(
+        id: i32,
+        name: String,
+        scopes: Vec<Scope>,
+        time_created: chrono::DateTime<chrono::Utc>,
+        time_expires: Option<chrono::DateTime<chrono::Utc>>,
+        time_last_used: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            scopes,
+            time_created,
+            time_expires,
+            time_last_used,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn scopes(&self) -> &[Scope] {
+        &self.scopes
+    }
+
+    pub fn time_created(&self) -> &chrono::DateTime<chrono::Utc> {
+        &self.time_created
+    }
+
+    pub fn time_expires(&self) -> Option<&chrono::DateTime<chrono::Utc>> {
+        self.time_expires.as_ref()
+    }
+
+    pub fn time_last_used(&self) -> Option<&chrono::DateTime<chrono::Utc>> {
+        self.time_last_used.as_ref()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum RevokeApiKeyResponse {
+    Revoked,
+    NoSuchKey,
+    Unauthenticated,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CreateCaptureRequest {
     url: url::Url,
     public: bool,
+    #[serde(default)]
+    callback_url: Option<url::Url>,
+    /// Group to file the capture under, which the user must contribute to
+    #[serde(default)]
+    group: Option<i32>,
 }
 
 impl CreateCaptureRequest {
@@ -67,14 +590,54 @@ impl CreateCaptureRequest {
     pub fn public(&self) -> bool {
         self.public
     }
+
+    /// URL to notify once every extract of the capture has finished
+    pub fn callback_url(&self) -> Option<&url::Url> {
+        self.callback_url.as_ref()
+    }
+
+    pub fn group(&self) -> Option<i32> {
+        self.group
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(tag = "result")]
 #[serde(rename_all = "snake_case")]
 pub enum CreateCaptureResponse {
-    Initiated { capture_id: uuid::Uuid },
+    Initiated {
+        capture_id: uuid::Uuid,
+        /// Key the callback's signature is made with, only present if a callback was requested
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        callback_secret: Option<String>,
+    },
     NoExtractors,
+    InvalidCallback,
+    GroupForbidden,
+    Unauthenticated,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RetryCaptureRequest {
+    #[serde(default)]
+    extractors: Vec<String>,
+}
+
+impl RetryCaptureRequest {
+    /// Extractors to retry; empty to retry every failed extract
+    pub fn extractors(&self) -> &[String] {
+        &self.extractors
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum RetryCaptureResponse {
+    Retrying { extractors: Vec<String> },
+    NothingToRetry,
+    NoSuchCapture,
+    Forbidden,
     Unauthenticated,
 }
 
@@ -83,6 +646,8 @@ pub struct QueryCaptureResponse {
     in_progress: usize,
     completed: usize,
     failed: usize,
+    #[serde(default)]
+    extracts: Vec<ExtractDescription>,
 }
 
 impl QueryCaptureResponse {
@@ -91,7 +656,26 @@ impl QueryCaptureResponse {
             in_progress: qty,
             completed: 0,
             failed: 0,
+            extracts: Vec::new(),
+        }
+    }
+
+    /// Tally the progress of a capture from the state of each of its extracts
+    pub fn from_extracts(extracts: Vec<ExtractDescription>) -> Self {
+        let mut response = Self::new_from_quantity(0);
+        for e in extracts.iter() {
+            match e.success {
+                None => response.in_progress += 1,
+                Some(true) => response.completed += 1,
+                Some(false) => response.failed += 1,
+            }
         }
+        response.extracts = extracts;
+        response
+    }
+
+    pub fn set_extracts(&mut self, extracts: Vec<ExtractDescription>) {
+        self.extracts = extracts;
     }
 
     pub fn incr_completed(&mut self) {
@@ -104,6 +688,18 @@ impl QueryCaptureResponse {
         self.failed += 1;
     }
 
+    pub fn add_in_progress(&mut self) {
+        self.in_progress += 1;
+    }
+
+    pub fn add_completed(&mut self) {
+        self.completed += 1;
+    }
+
+    pub fn add_failed(&mut self) {
+        self.failed += 1;
+    }
+
     pub fn in_progress(&self) -> usize {
         self.in_progress
     }
@@ -115,4 +711,1486 @@ impl QueryCaptureResponse {
     pub fn failed(&self) -> usize {
         self.failed
     }
+
+    pub fn extracts(&self) -> &[ExtractDescription] {
+        &self.extracts
+    }
+}
+
+/// Change in the progress of a capture, as streamed from `/capture/{uuid}/events`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "event")]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureEvent {
+    ExtractDispatched { extractor: String, worker: String },
+    ExtractCompleted { extractor: String },
+    ExtractFailed { extractor: String, reason: String },
+    CaptureFinished { completed: usize, failed: usize },
+}
+
+impl CaptureEvent {
+    /// Name of the event, as used for the SSE `event` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            CaptureEvent::ExtractDispatched { .. } => "extract_dispatched",
+            CaptureEvent::ExtractCompleted { .. } => "extract_completed",
+            CaptureEvent::ExtractFailed { .. } => "extract_failed",
+            CaptureEvent::CaptureFinished { .. } => "capture_finished",
+        }
+    }
+}
+
+/// Stage an extract has reached
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtractPhase {
+    Pending,
+    Dispatched,
+    Downloading,
+    Installed,
+    Failed,
+}
+
+/// Where a single extract of a capture stands
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExtractDescription {
+    extractor: String,
+    state: ExtractPhase,
+    success: Option<bool>,
+    failure: Option<String>,
+    worker: Option<String>,
+    time_created: chrono::DateTime<chrono::Utc>,
+    time_updated: chrono::DateTime<chrono::Utc>,
+    elapsed: u64,
+    location: Option<String>,
+}
+
+impl ExtractDescription {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        extractor: String,
+        state: ExtractPhase,
+        failure: Option<String>,
+        worker: Option<String>,
+        time_created: chrono::DateTime<chrono::Utc>,
+        time_updated: chrono::DateTime<chrono::Utc>,
+        elapsed: u64,
+        location: Option<String>,
+    ) -> Self {
+        let success = match state {
+            ExtractPhase::Installed => Some(true),
+            ExtractPhase::Failed => Some(false),
+            _ => None,
+        };
+        Self {
+            extractor,
+            state,
+            success,
+            failure,
+            worker,
+            time_created,
+            time_updated,
+            elapsed,
+            location,
+        }
+    }
+
+    pub fn extractor(&self) -> &str {
+        &self.extractor
+    }
+
+    pub fn state(&self) -> ExtractPhase {
+        self.state
+    }
+
+    /// Whether the extract succeeded, or `None` while it is still in progress
+    pub fn success(&self) -> Option<bool> {
+        self.success
+    }
+
+    /// Why the extract failed, if it did
+    pub fn failure(&self) -> Option<&str> {
+        self.failure.as_deref()
+    }
+
+    /// Worker the extract was most recently dispatched to
+    pub fn worker(&self) -> Option<&str> {
+        self.worker.as_deref()
+    }
+
+    pub fn time_created(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time_created
+    }
+
+    /// When the extract last changed state
+    pub fn time_updated(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time_updated
+    }
+
+    /// Seconds spent on the extract, so far if it is still in progress
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    /// Path under which the installed output is served
+    pub fn location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+}
+
+/// Kind of action recorded in the audit log
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    UserCreate,
+    UserDelete,
+    UserDisable,
+    UserEnable,
+    PasswordReset,
+    CaptureCreate,
+    CaptureVisibility,
+    CaptureDelete,
+    ResourceAccess,
+    GroupMembership,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Login => "login",
+            AuditAction::UserCreate => "user_create",
+            AuditAction::UserDelete => "user_delete",
+            AuditAction::UserDisable => "user_disable",
+            AuditAction::UserEnable => "user_enable",
+            AuditAction::PasswordReset => "password_reset",
+            AuditAction::CaptureCreate => "capture_create",
+            AuditAction::CaptureVisibility => "capture_visibility",
+            AuditAction::CaptureDelete => "capture_delete",
+            AuditAction::ResourceAccess => "resource_access",
+            AuditAction::GroupMembership => "group_membership",
+        }
+    }
+}
+
+impl std::str::FromStr for AuditAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "login" => Ok(AuditAction::Login),
+            "user_create" => Ok(AuditAction::UserCreate),
+            "user_delete" => Ok(AuditAction::UserDelete),
+            "user_disable" => Ok(AuditAction::UserDisable),
+            "user_enable" => Ok(AuditAction::UserEnable),
+            "password_reset" => Ok(AuditAction::PasswordReset),
+            "capture_create" => Ok(AuditAction::CaptureCreate),
+            "capture_visibility" => Ok(AuditAction::CaptureVisibility),
+            "capture_delete" => Ok(AuditAction::CaptureDelete),
+            "resource_access" => Ok(AuditAction::ResourceAccess),
+            "group_membership" => Ok(AuditAction::GroupMembership),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Filters and pagination for `GET /0/admin/audit`, given as query parameters
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AuditQuery {
+    actor: Option<i32>,
+    action: Option<AuditAction>,
+    target: Option<String>,
+    success: Option<bool>,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    until: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only return events older than this event ID, as given by `next` in a previous page
+    before: Option<i32>,
+    limit: Option<i64>,
+}
+
+impl AuditQuery {
+    pub fn actor(&self) -> Option<i32> {
+        self.actor
+    }
+
+    pub fn action(&self) -> Option<AuditAction> {
+        self.action
+    }
+
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    pub fn success(&self) -> Option<bool> {
+        self.success
+    }
+
+    pub fn since(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.since
+    }
+
+    pub fn until(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.until
+    }
+
+    pub fn before(&self) -> Option<i32> {
+        self.before
+    }
+
+    pub fn limit(&self) -> Option<i64> {
+        self.limit
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuditEventDescription {
+    id: i32,
+    time_occurred: chrono::DateTime<chrono::Utc>,
+    action: AuditAction,
+    success: bool,
+    actor: Option<i32>,
+    ip: Option<String>,
+    target: Option<String>,
+    detail: Option<String>,
+}
+
+impl AuditEventDescription {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: i32,
+        time_occurred: chrono::DateTime<chrono::Utc>,
+        action: AuditAction,
+        success: bool,
+        actor: Option<i32>,
+        ip: Option<String>,
+        target: Option<String>,
+        detail: Option<String>,
+    ) -> Self {
+        Self {
+            id,
+            time_occurred,
+            action,
+            success,
+            actor,
+            ip,
+            target,
+            detail,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn time_occurred(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time_occurred
+    }
+
+    pub fn action(&self) -> AuditAction {
+        self.action
+    }
+
+    pub fn success(&self) -> bool {
+        self.success
+    }
+
+    pub fn actor(&self) -> Option<i32> {
+        self.actor
+    }
+
+    pub fn ip(&self) -> Option<&str> {
+        self.ip.as_deref()
+    }
+
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    pub fn detail(&self) -> Option<&str> {
+        self.detail.as_deref()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuditPage {
+    events: Vec<AuditEventDescription>,
+    /// Pass as `before` to fetch the next page; absent on the last page
+    next: Option<i32>,
+}
+
+impl AuditPage {
+    pub fn new(events: Vec<AuditEventDescription>, next: Option<i32>) -> Self {
+        Self { events, next }
+    }
+
+    pub fn events(&self) -> &[AuditEventDescription] {
+        &self.events
+    }
+
+    pub fn next(&self) -> Option<i32> {
+        self.next
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateWebhookRequest {
+    url: url::Url,
+}
+
+impl CreateWebhookRequest {
+    pub fn url(&self) -> &url::Url {
+        &self.url
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum CreateWebhookResponse {
+    /// The secret deliveries are signed with is only ever returned here
+    Created {
+        id: i32,
+        secret: String,
+    },
+    InvalidUrl,
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WebhookDescription {
+    id: i32,
+    url: String,
+    time_created: chrono::DateTime<chrono::Utc>,
+}
+
+impl WebhookDescription {
+    pub fn new(id: i32, url: String, time_created: chrono::DateTime<chrono::Utc>) -> Self {
+        Self {
+            id,
+            url,
+            time_created,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn time_created(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time_created
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteWebhookResponse {
+    Deleted,
+    NoSuchWebhook,
+    Unauthenticated,
+}
+
+/// Body POSTed to webhooks and callback URLs once a capture has finished
+///
+/// Deliveries carry an `X-Webarc-Signature` header of the form `sha256=<hex>`,
+/// an HMAC-SHA256 of the body keyed with the webhook's or callback's secret.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WebhookPayload {
+    event: String,
+    capture_id: uuid::Uuid,
+    url: url::Url,
+    time_initiated: chrono::DateTime<chrono::Utc>,
+    time_finished: chrono::DateTime<chrono::Utc>,
+    completed: usize,
+    failed: usize,
+    extracts: Vec<ExtractDescription>,
+}
+
+impl WebhookPayload {
+    /// Describe a finished capture from its extracts
+    pub fn capture_finished(
+        capture_id: uuid::Uuid,
+        url: url::Url,
+        time_initiated: chrono::DateTime<chrono::Utc>,
+        time_finished: chrono::DateTime<chrono::Utc>,
+        extracts: Vec<ExtractDescription>,
+    ) -> Self {
+        let progress = QueryCaptureResponse::from_extracts(extracts);
+        Self {
+            event: "capture_finished".to_string(),
+            capture_id,
+            url,
+            time_initiated,
+            time_finished,
+            completed: progress.completed(),
+            failed: progress.failed(),
+            extracts: progress.extracts,
+        }
+    }
+
+    pub fn event(&self) -> &str {
+        &self.event
+    }
+
+    pub fn capture_id(&self) -> &uuid::Uuid {
+        &self.capture_id
+    }
+
+    pub fn url(&self) -> &url::Url {
+        &self.url
+    }
+
+    pub fn time_initiated(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time_initiated
+    }
+
+    pub fn time_finished(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time_finished
+    }
+
+    pub fn completed(&self) -> usize {
+        self.completed
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    pub fn extracts(&self) -> &[ExtractDescription] {
+        &self.extracts
+    }
+}
+
+/// Whether a webhook delivery has reached its destination
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryState {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl DeliveryState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryState::Pending => "pending",
+            DeliveryState::Delivered => "delivered",
+            DeliveryState::Failed => "failed",
+        }
+    }
+}
+
+impl std::str::FromStr for DeliveryState {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(DeliveryState::Pending),
+            "delivered" => Ok(DeliveryState::Delivered),
+            "failed" => Ok(DeliveryState::Failed),
+            _ => Err(()),
+        }
+    }
+}
+
+/// An attempt to notify a webhook or callback URL, as shown in the delivery log
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WebhookDeliveryDescription {
+    id: i32,
+    capture_id: uuid::Uuid,
+    webhook: Option<i32>,
+    url: String,
+    state: DeliveryState,
+    attempts: i32,
+    response_status: Option<i32>,
+    error: Option<String>,
+    time_created: chrono::DateTime<chrono::Utc>,
+    time_updated: chrono::DateTime<chrono::Utc>,
+}
+
+impl WebhookDeliveryDescription {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: i32,
+        capture_id: uuid::Uuid,
+        webhook: Option<i32>,
+        url: String,
+        state: DeliveryState,
+        attempts: i32,
+        response_status: Option<i32>,
+        error: Option<String>,
+        time_created: chrono::DateTime<chrono::Utc>,
+        time_updated: chrono::DateTime<chrono::Utc>,
+    ) -> Self {
+        Self {
+            id,
+            capture_id,
+            webhook,
+            url,
+            state,
+            attempts,
+            response_status,
+            error,
+            time_created,
+            time_updated,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn capture_id(&self) -> &uuid::Uuid {
+        &self.capture_id
+    }
+
+    /// Account webhook delivered to, or `None` for a capture's own callback URL
+    pub fn webhook(&self) -> Option<i32> {
+        self.webhook
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn state(&self) -> DeliveryState {
+        self.state
+    }
+
+    pub fn attempts(&self) -> i32 {
+        self.attempts
+    }
+
+    /// HTTP status of the most recent response, if one was received
+    pub fn response_status(&self) -> Option<i32> {
+        self.response_status
+    }
+
+    /// Why the most recent attempt failed, if it did
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn time_created(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time_created
+    }
+
+    pub fn time_updated(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time_updated
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TimelineRequest {
+    url: url::Url,
+}
+
+impl TimelineRequest {
+    pub fn url(&self) -> &url::Url {
+        &self.url
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineResponse {
+    Timeline {
+        /// Normalized form of the requested URL, shared by all of its captures
+        url: url::Url,
+        captures: Vec<TimelineEntry>,
+    },
+    Unauthenticated,
+}
+
+/// One capture in the timeline of a tracked URL
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TimelineEntry {
+    capture_id: uuid::Uuid,
+    /// URL as it was submitted, before normalization
+    url: url::Url,
+    time_initiated: chrono::DateTime<chrono::Utc>,
+    time_finished: Option<chrono::DateTime<chrono::Utc>>,
+    public: bool,
+}
+
+impl TimelineEntry {
+    pub fn new(
+        capture_id: uuid::Uuid,
+        url: url::Url,
+        time_initiated: chrono::DateTime<chrono::Utc>,
+        time_finished: Option<chrono::DateTime<chrono::Utc>>,
+        public: bool,
+    ) -> Self {
+        Self {
+            capture_id,
+            url,
+            time_initiated,
+            time_finished,
+            public,
+        }
+    }
+
+    pub fn capture_id(&self) -> &uuid::Uuid {
+        &self.capture_id
+    }
+
+    pub fn url(&self) -> &url::Url {
+        &self.url
+    }
+
+    pub fn time_initiated(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time_initiated
+    }
+
+    pub fn time_finished(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.time_finished
+    }
+
+    pub fn public(&self) -> bool {
+        self.public
+    }
+}
+
+/// Body of requests creating or replacing a schedule
+///
+/// Exactly one of `cron` and `interval` must be given.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ScheduleRequest {
+    url: url::Url,
+    public: bool,
+    /// Cron expression evaluated in UTC, such as `0 6 * * 1`
+    #[serde(default)]
+    cron: Option<String>,
+    /// Seconds between captures
+    #[serde(default)]
+    interval: Option<u32>,
+    #[serde(default = "ScheduleRequest::default_enabled")]
+    enabled: bool,
+}
+
+impl ScheduleRequest {
+    pub fn new(
+        url: url::Url,
+        public: bool,
+        cron: Option<String>,
+        interval: Option<u32>,
+        enabled: bool,
+    ) -> Self {
+        Self {
+            url,
+            public,
+            cron,
+            interval,
+            enabled,
+        }
+    }
+
+    fn default_enabled() -> bool {
+        true
+    }
+
+    pub fn url(&self) -> &url::Url {
+        &self.url
+    }
+
+    pub fn public(&self) -> bool {
+        self.public
+    }
+
+    pub fn cron(&self) -> Option<&str> {
+        self.cron.as_deref()
+    }
+
+    pub fn interval(&self) -> Option<u32> {
+        self.interval
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum SaveScheduleResponse {
+    Saved { schedule: ScheduleDescription },
+    InvalidSchedule { reason: String },
+    NoExtractors,
+    NoSuchSchedule,
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ScheduleDescription {
+    id: i32,
+    url: url::Url,
+    public: bool,
+    cron: Option<String>,
+    interval: Option<u32>,
+    enabled: bool,
+    /// Most recent capture made by the schedule
+    last_capture: Option<uuid::Uuid>,
+    time_created: chrono::DateTime<chrono::Utc>,
+    time_next: chrono::DateTime<chrono::Utc>,
+}
+
+impl ScheduleDescription {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: i32,
+        url: url::Url,
+        public: bool,
+        cron: Option<String>,
+        interval: Option<u32>,
+        enabled: bool,
+        last_capture: Option<uuid::Uuid>,
+        time_created: chrono::DateTime<chrono::Utc>,
+        time_next: chrono::DateTime<chrono::Utc>,
+    ) -> Self {
+        Self {
+            id,
+            url,
+            public,
+            cron,
+            interval,
+            enabled,
+            last_capture,
+            time_created,
+            time_next,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn url(&self) -> &url::Url {
+        &self.url
+    }
+
+    pub fn public(&self) -> bool {
+        self.public
+    }
+
+    pub fn cron(&self) -> Option<&str> {
+        self.cron.as_deref()
+    }
+
+    pub fn interval(&self) -> Option<u32> {
+        self.interval
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn last_capture(&self) -> Option<&uuid::Uuid> {
+        self.last_capture.as_ref()
+    }
+
+    pub fn time_created(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time_created
+    }
+
+    pub fn time_next(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time_next
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteScheduleResponse {
+    Deleted,
+    NoSuchSchedule,
+    Unauthenticated,
+}
+
+/// JSON form of a batch capture request
+///
+/// A bare JSON array of URLs, or a newline-delimited list, is also accepted,
+/// with `public` and comma-separated `tags` given as query parameters.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BatchCaptureRequest {
+    /// URLs as submitted, so that each one can be reported on individually
+    urls: Vec<String>,
+    #[serde(default)]
+    public: bool,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl BatchCaptureRequest {
+    pub fn new(urls: Vec<String>, public: bool, tags: Vec<String>) -> Self {
+        Self { urls, public, tags }
+    }
+
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+
+    pub fn public(&self) -> bool {
+        self.public
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum BatchCaptureResponse {
+    Created {
+        batch_id: uuid::Uuid,
+        results: Vec<BatchCaptureItem>,
+    },
+    InvalidBatch {
+        reason: String,
+    },
+    Unauthenticated,
+}
+
+/// What became of one URL of a batch
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BatchCaptureItem {
+    url: String,
+    #[serde(flatten)]
+    outcome: BatchCaptureOutcome,
+}
+
+impl BatchCaptureItem {
+    pub fn new(url: String, outcome: BatchCaptureOutcome) -> Self {
+        Self { url, outcome }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn outcome(&self) -> &BatchCaptureOutcome {
+        &self.outcome
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum BatchCaptureOutcome {
+    Initiated {
+        capture_id: uuid::Uuid,
+    },
+    NoExtractors,
+    Invalid,
+    /// The URL was acceptable but initiating its capture failed
+    Failed,
+}
+
+/// Aggregate progress of the captures in a batch
+///
+/// Counts are of extracts across every capture in the batch.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BatchProgress {
+    batch_id: uuid::Uuid,
+    public: bool,
+    time_created: chrono::DateTime<chrono::Utc>,
+    in_progress: usize,
+    completed: usize,
+    failed: usize,
+    /// Number of captures with no extracts left in progress
+    finished: usize,
+    captures: Vec<BatchCaptureProgress>,
+}
+
+impl BatchProgress {
+    pub fn new(
+        batch_id: uuid::Uuid,
+        public: bool,
+        time_created: chrono::DateTime<chrono::Utc>,
+    ) -> Self {
+        Self {
+            batch_id,
+            public,
+            time_created,
+            in_progress: 0,
+            completed: 0,
+            failed: 0,
+            finished: 0,
+            captures: vec![],
+        }
+    }
+
+    /// Count an extract towards its capture, which must follow any other capture's extracts
+    pub fn add_extract(
+        &mut self,
+        capture_id: uuid::Uuid,
+        url: String,
+        finished: bool,
+        phase: ExtractPhase,
+    ) {
+        if self.captures.last().map(|c| c.capture_id) != Some(capture_id) {
+            if finished {
+                self.finished += 1;
+            }
+            self.captures.push(BatchCaptureProgress {
+                capture_id,
+                url,
+                finished,
+                in_progress: 0,
+                completed: 0,
+                failed: 0,
+            });
+        }
+        let capture = self.captures.last_mut().unwrap();
+        match phase {
+            ExtractPhase::Installed => {
+                capture.completed += 1;
+                self.completed += 1;
+            }
+            ExtractPhase::Failed => {
+                capture.failed += 1;
+                self.failed += 1;
+            }
+            _ => {
+                capture.in_progress += 1;
+                self.in_progress += 1;
+            }
+        }
+    }
+
+    pub fn batch_id(&self) -> &uuid::Uuid {
+        &self.batch_id
+    }
+
+    pub fn in_progress(&self) -> usize {
+        self.in_progress
+    }
+
+    pub fn completed(&self) -> usize {
+        self.completed
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    pub fn finished(&self) -> usize {
+        self.finished
+    }
+
+    pub fn captures(&self) -> &[BatchCaptureProgress] {
+        &self.captures
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BatchCaptureProgress {
+    capture_id: uuid::Uuid,
+    url: String,
+    finished: bool,
+    in_progress: usize,
+    completed: usize,
+    failed: usize,
+}
+
+impl BatchCaptureProgress {
+    pub fn capture_id(&self) -> &uuid::Uuid {
+        &self.capture_id
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteCaptureResponse {
+    /// The capture is in the trash, and will be removed at `time_purge` unless restored
+    Trashed {
+        time_purge: chrono::DateTime<chrono::Utc>,
+    },
+    Deleted,
+    CaptureInProgress,
+    NoSuchCapture,
+    Forbidden,
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreCaptureResponse {
+    Restored,
+    NotTrashed,
+    NoSuchCapture,
+    Forbidden,
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TrashedCapture {
+    capture_id: uuid::Uuid,
+    url: url::Url,
+    time_deleted: chrono::DateTime<chrono::Utc>,
+    time_purge: chrono::DateTime<chrono::Utc>,
+}
+
+impl TrashedCapture {
+    pub fn new(
+        capture_id: uuid::Uuid,
+        url: url::Url,
+        time_deleted: chrono::DateTime<chrono::Utc>,
+        time_purge: chrono::DateTime<chrono::Utc>,
+    ) -> Self {
+        Self {
+            capture_id,
+            url,
+            time_deleted,
+            time_purge,
+        }
+    }
+
+    pub fn capture_id(&self) -> &uuid::Uuid {
+        &self.capture_id
+    }
+
+    pub fn url(&self) -> &url::Url {
+        &self.url
+    }
+
+    pub fn time_deleted(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time_deleted
+    }
+
+    pub fn time_purge(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time_purge
+    }
+}
+
+/// Changes to a capture; fields left out are kept as they are
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct UpdateCaptureRequest {
+    #[serde(default)]
+    public: Option<bool>,
+    /// Username of the user to transfer the capture to
+    #[serde(default)]
+    owner: Option<String>,
+    /// New title, or an empty string to clear it
+    #[serde(default)]
+    title: Option<String>,
+    /// New notes, or an empty string to clear them
+    #[serde(default)]
+    notes: Option<String>,
+    /// Group to move the capture to, or null to take it out of its group
+    #[serde(default, deserialize_with = "present")]
+    group: Option<Option<i32>>,
+}
+
+/// Tell a field given as null apart from one left out, which `default` leaves as `None`
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl UpdateCaptureRequest {
+    pub fn new(
+        public: Option<bool>,
+        owner: Option<String>,
+        title: Option<String>,
+        notes: Option<String>,
+        group: Option<Option<i32>>,
+    ) -> Self {
+        Self {
+            public,
+            owner,
+            title,
+            notes,
+            group,
+        }
+    }
+
+    pub fn public(&self) -> Option<bool> {
+        self.public
+    }
+
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn notes(&self) -> Option<&str> {
+        self.notes.as_deref()
+    }
+
+    /// `Some(None)` to take the capture out of its group
+    pub fn group(&self) -> Option<Option<i32>> {
+        self.group
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateCaptureResponse {
+    Updated { capture: CaptureDescription },
+    InvalidUpdate { reason: String },
+    NoSuchUser,
+    NoSuchCapture,
+    Forbidden,
+    GroupForbidden,
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CaptureDescription {
+    capture_id: uuid::Uuid,
+    url: url::Url,
+    owner: String,
+    public: bool,
+    title: Option<String>,
+    notes: Option<String>,
+    group: Option<i32>,
+}
+
+impl CaptureDescription {
+    pub fn new(
+        capture_id: uuid::Uuid,
+        url: url::Url,
+        owner: String,
+        public: bool,
+        title: Option<String>,
+        notes: Option<String>,
+        group: Option<i32>,
+    ) -> Self {
+        Self {
+            capture_id,
+            url,
+            owner,
+            public,
+            title,
+            notes,
+            group,
+        }
+    }
+
+    pub fn capture_id(&self) -> &uuid::Uuid {
+        &self.capture_id
+    }
+
+    pub fn url(&self) -> &url::Url {
+        &self.url
+    }
+
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    pub fn public(&self) -> bool {
+        self.public
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn notes(&self) -> Option<&str> {
+        self.notes.as_deref()
+    }
+
+    pub fn group(&self) -> Option<i32> {
+        self.group
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CreateShareRequest {
+    /// Limit the share to the output of one extractor
+    #[serde(default)]
+    extractor: Option<String>,
+    #[serde(default)]
+    expires: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl CreateShareRequest {
+    pub fn new(extractor: Option<String>, expires: Option<chrono::DateTime<chrono::Utc>>) -> Self {
+        Self { extractor, expires }
+    }
+
+    pub fn extractor(&self) -> Option<&str> {
+        self.extractor.as_deref()
+    }
+
+    pub fn expires(&self) -> Option<&chrono::DateTime<chrono::Utc>> {
+        self.expires.as_ref()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum CreateShareResponse {
+    /// The token itself is only ever returned here
+    Created {
+        id: i32,
+        token: String,
+        location: String,
+    },
+    NoSuchExtractor,
+    AlreadyExpired,
+    NoSuchCapture,
+    Forbidden,
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ShareDescription {
+    id: i32,
+    extractor: Option<String>,
+    time_created: chrono::DateTime<chrono::Utc>,
+    time_expires: Option<chrono::DateTime<chrono::Utc>>,
+    time_last_used: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl ShareDescription {
+    pub fn new(
+        id: i32,
+        extractor: Option<String>,
+        time_created: chrono::DateTime<chrono::Utc>,
+        time_expires: Option<chrono::DateTime<chrono::Utc>>,
+        time_last_used: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Self {
+        Self {
+            id,
+            extractor,
+            time_created,
+            time_expires,
+            time_last_used,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn extractor(&self) -> Option<&str> {
+        self.extractor.as_deref()
+    }
+
+    pub fn time_created(&self) -> &chrono::DateTime<chrono::Utc> {
+        &self.time_created
+    }
+
+    pub fn time_expires(&self) -> Option<&chrono::DateTime<chrono::Utc>> {
+        self.time_expires.as_ref()
+    }
+
+    pub fn time_last_used(&self) -> Option<&chrono::DateTime<chrono::Utc>> {
+        self.time_last_used.as_ref()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum RevokeShareResponse {
+    Revoked,
+    NoSuchShare,
+    NoSuchCapture,
+    Forbidden,
+    Unauthenticated,
+}
+
+/// What a member of a group may do with the group's captures
+///
+/// Each role may do everything the ones before it may.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupRole {
+    /// View the group's captures
+    Viewer,
+    /// File captures under the group
+    Contributor,
+    /// Change and delete the group's captures, and manage its members
+    Admin,
+}
+
+impl GroupRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GroupRole::Viewer => "viewer",
+            GroupRole::Contributor => "contributor",
+            GroupRole::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for GroupRole {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(GroupRole::Viewer),
+            "contributor" => Ok(GroupRole::Contributor),
+            "admin" => Ok(GroupRole::Admin),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateGroupRequest {
+    name: String,
+}
+
+impl CreateGroupRequest {
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum CreateGroupResponse {
+    Created { group: GroupDescription },
+    InvalidName,
+    NameTaken,
+    Unauthenticated,
+}
+
+/// A group as seen by one of its members
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GroupDescription {
+    id: i32,
+    name: String,
+    role: GroupRole,
+    time_created: chrono::DateTime<chrono::Utc>,
+}
+
+impl GroupDescription {
+    pub fn new(
+        id: i32,
+        name: String,
+        role: GroupRole,
+        time_created: chrono::DateTime<chrono::Utc>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            role,
+            time_created,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn role(&self) -> GroupRole {
+        self.role
+    }
+
+    pub fn time_created(&self) -> &chrono::DateTime<chrono::Utc> {
+        &self.time_created
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteGroupResponse {
+    Deleted,
+    NoSuchGroup,
+    Forbidden,
+    Unauthenticated,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MemberDescription {
+    username: String,
+    role: GroupRole,
+    time_added: chrono::DateTime<chrono::Utc>,
+}
+
+impl MemberDescription {
+    pub fn new(
+        username: String,
+        role: GroupRole,
+        time_added: chrono::DateTime<chrono::Utc>,
+    ) -> Self {
+        Self {
+            username,
+            role,
+            time_added,
+        }
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn role(&self) -> GroupRole {
+        self.role
+    }
+
+    pub fn time_added(&self) -> &chrono::DateTime<chrono::Utc> {
+        &self.time_added
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SetMemberRequest {
+    role: GroupRole,
+}
+
+impl SetMemberRequest {
+    pub fn new(role: GroupRole) -> Self {
+        Self { role }
+    }
+
+    pub fn role(&self) -> GroupRole {
+        self.role
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result")]
+#[serde(rename_all = "snake_case")]
+pub enum GroupMemberResponse {
+    Saved,
+    Removed,
+    NoSuchUser,
+    NoSuchMember,
+    NoSuchGroup,
+    /// The change would leave the group without an admin
+    LastAdmin,
+    Forbidden,
+    Unauthenticated,
 }
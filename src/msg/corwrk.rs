@@ -40,6 +40,7 @@ pub enum InitiateExtractResponse {
 pub enum QueryExtractProgressResponse {
     InProgress,
     UnsupportedUrl,
+    ExtractionFailed,
     Failed,
     Completed,
     NoSuchExtract,
@@ -54,7 +55,7 @@ pub struct ConfirmExtractRequest {
 impl ConfirmExtractRequest {
     pub fn new(ticket: &uuid::Uuid, hash: &str) -> Self {
         Self {
-            ticket: ticket.clone(),
+            ticket: *ticket,
             hash: hash.to_string(),
         }
     }
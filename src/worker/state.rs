@@ -39,7 +39,7 @@ impl State {
     /// Determine which executable to use for a given extractor name
     pub async fn locate_extractor(&self, extractor: &str) -> Option<String> {
         let extractors = self.extractors.read().await;
-        extractors.get(extractor).map(|a| a.clone())
+        extractors.get(extractor).cloned()
     }
 
     /// Get the blob storage directory
@@ -71,6 +71,20 @@ impl State {
         debug!("Task {ticket} failed");
     }
 
+    /// Mark extract as impossible because the extractor does not support its URL
+    pub async fn reject_extract(&self, ticket: Uuid) {
+        let mut tasks = self.tasks.write().await;
+        tasks.insert(ticket, QueryExtractProgressResponse::UnsupportedUrl);
+        debug!("Task {ticket} rejected");
+    }
+
+    /// Mark extract as failed because the extractor could not extract its URL
+    pub async fn fail_extract(&self, ticket: Uuid) {
+        let mut tasks = self.tasks.write().await;
+        tasks.insert(ticket, QueryExtractProgressResponse::ExtractionFailed);
+        debug!("Task {ticket} could not be extracted");
+    }
+
     /// Mark extract as completed
     pub async fn finalize_extract(&self, ticket: Uuid, hash: String) {
         let mut tasks = self.tasks.write().await;
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::Path;
 
 use actix_web::web;
 use async_process::Command;
@@ -28,6 +28,14 @@ pub async fn extract_task(ticket: Uuid, extractor: String, url: Url, state: web:
     };
     let blob = if output.status.success() {
         output.stdout
+    } else if output.status.code() == Some(10) {
+        debug!("Extractor declined URL {url}");
+        state.reject_extract(ticket).await;
+        return;
+    } else if output.status.code() == Some(11) {
+        debug!("Extractor failed to extract URL {url}");
+        state.fail_extract(ticket).await;
+        return;
     } else {
         let err_string = str::from_utf8(&output.stderr)
             .unwrap_or("[bytes]")
@@ -50,7 +58,7 @@ pub async fn extract_task(ticket: Uuid, extractor: String, url: Url, state: web:
 
 /// Write a blob to disc, returning its sha256 sum
 pub async fn write_blob(
-    blob_dir: &PathBuf,
+    blob_dir: &Path,
     ticket: &Uuid,
     bytevec: Vec<u8>,
 ) -> Result<String, WriteBlobError> {